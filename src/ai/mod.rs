@@ -0,0 +1,698 @@
+use bevy::log::LogPlugin;
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::board::bitboard::{Bitboard, PieceMask};
+use crate::board::board_core::{self, BoardData, BoardSize, PieceRotation, PieceType};
+use crate::board::move_gen::{reachable_placements, ReachablePlacement};
+use crate::game_state_machine::{GamePlayState, GameStateInfo};
+use crate::input::InputSource;
+use crate::piece_queue::{PieceQueue, QUEUE_LENGTH};
+use crate::versus::VersusSettings;
+use crate::{GameSettings, LockController, Randomizer, FRAMES_PER_SECOND};
+
+const DEFAULT_PIECES_PER_SECOND: f32 = 2.0;
+
+//how much each feature of the board after a placement counts towards its score. features that
+//make the board worse want negative weights
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AiWeights {
+    //the column heights added up
+    pub aggregate_height: f32,
+    //empty cells with a block somewhere above them
+    pub holes: f32,
+    //the height differences between neighbouring columns added up
+    pub bumpiness: f32,
+    //how far columns sit below both of their neighbours
+    pub wells: f32,
+    //places a t piece could be spun into
+    pub t_slots: f32,
+    pub lines_cleared: f32,
+}
+
+impl Default for AiWeights {
+    fn default() -> Self {
+        AiWeights {
+            aggregate_height: -0.51,
+            holes: -0.36,
+            bumpiness: -0.18,
+            wells: -0.05,
+            t_slots: 0.2,
+            lines_cleared: 0.76,
+        }
+    }
+}
+
+//how well the ai or a bot plays against a person, picked with --difficulty
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+    Expert,
+}
+
+impl Difficulty {
+    fn from_argument(argument: &str) -> Option<Difficulty> {
+        match argument {
+            "easy" => Some(Difficulty::Easy),
+            "medium" => Some(Difficulty::Medium),
+            "hard" => Some(Difficulty::Hard),
+            "expert" => Some(Difficulty::Expert),
+            _ => None,
+        }
+    }
+
+    pub fn from_arguments(arguments: &[String]) -> Option<Difficulty> {
+        let index = arguments
+            .iter()
+            .position(|argument| argument == "--difficulty")?;
+        let difficulty = arguments
+            .get(index + 1)
+            .and_then(|argument| Difficulty::from_argument(argument));
+        if difficulty.is_none() {
+            warn!("--difficulty needs easy, medium, hard or expert");
+        }
+        difficulty
+    }
+
+    pub fn pieces_per_second(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.75,
+            Difficulty::Medium => 1.25,
+            Difficulty::Hard => 2.0,
+            Difficulty::Expert => 3.5,
+        }
+    }
+
+    //how far the ai's score for a placement can be off either way, so easier ais misjudge
+    //boards the way people do
+    pub fn evaluation_noise(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 1.5,
+            Difficulty::Medium => 0.6,
+            Difficulty::Hard => 0.25,
+            Difficulty::Expert => 0.,
+        }
+    }
+
+    //a bot can't be made to misjudge, so instead it sometimes plays one of the moves it liked
+    //less than its best
+    pub fn mistake_chance(&self) -> f64 {
+        match self {
+            Difficulty::Easy => 0.3,
+            Difficulty::Medium => 0.15,
+            Difficulty::Hard => 0.05,
+            Difficulty::Expert => 0.,
+        }
+    }
+}
+
+//the built-in ai, switched on with --ai. --ai-pps sets how fast it plays and --ai-weights loads
+//its weights from a json file. --difficulty sets both its speed and how often it misjudges,
+//with --ai-pps still picking the speed when it's given too
+pub struct AiSettings {
+    pub enabled: bool,
+    pub difficulty: Option<Difficulty>,
+    pub pieces_per_second: f32,
+    pub evaluation_noise: f32,
+    pub weights: AiWeights,
+}
+
+impl FromWorld for AiSettings {
+    fn from_world(_world: &mut World) -> Self {
+        AiSettings::from_arguments(&std::env::args().collect::<Vec<String>>())
+    }
+}
+
+impl AiSettings {
+    fn from_arguments(arguments: &[String]) -> AiSettings {
+        let argument_after = |flag: &str| {
+            arguments
+                .iter()
+                .position(|argument| argument == flag)
+                .and_then(|index| arguments.get(index + 1))
+        };
+
+        let difficulty = Difficulty::from_arguments(arguments);
+        let default_pieces_per_second = difficulty
+            .map(|difficulty| difficulty.pieces_per_second())
+            .unwrap_or(DEFAULT_PIECES_PER_SECOND);
+        let pieces_per_second = match argument_after("--ai-pps").map(|pps| pps.parse::<f32>()) {
+            Some(Ok(pps)) if pps > 0. => pps,
+            Some(_) => {
+                warn!("--ai-pps needs a number above 0");
+                default_pieces_per_second
+            }
+            None => default_pieces_per_second,
+        };
+
+        let weights = match argument_after("--ai-weights") {
+            Some(path) => match AiWeights::load(Path::new(path)) {
+                Ok(weights) => weights,
+                Err(error) => {
+                    warn!("couldn't load ai weights {}: {}", path, error);
+                    AiWeights::default()
+                }
+            },
+            None => AiWeights::default(),
+        };
+
+        AiSettings {
+            enabled: arguments.iter().any(|argument| argument == "--ai"),
+            difficulty,
+            pieces_per_second,
+            evaluation_noise: difficulty
+                .map(|difficulty| difficulty.evaluation_noise())
+                .unwrap_or(0.),
+            weights,
+        }
+    }
+}
+
+impl AiWeights {
+    fn load(path: &Path) -> Result<AiWeights, String> {
+        let text = fs::read_to_string(path).map_err(|error| error.to_string())?;
+        serde_json::from_str(&text).map_err(|error| error.to_string())
+    }
+}
+
+//the locked blocks, without anything needed to draw them, so the ai can try placements on
+//copies of it and the benchmark can play whole games without the app
+#[derive(Clone)]
+pub struct AiBoard {
+    rows: Bitboard,
+}
+
+impl AiBoard {
+    pub fn empty(size: BoardSize) -> AiBoard {
+        AiBoard {
+            rows: Bitboard::empty(size),
+        }
+    }
+
+    pub fn size(&self) -> BoardSize {
+        self.rows.size()
+    }
+
+    //the board with the player's piece left out
+    pub fn from_board_data(board_data: &BoardData, player_cells: &[IVec2]) -> AiBoard {
+        let mut rows = board_data.filled().clone();
+        for cell in player_cells {
+            rows.unset(*cell);
+        }
+        AiBoard { rows }
+    }
+
+    //cells off the sides or bottom count as filled, cells above the top as empty
+    fn is_filled(&self, x: i32, y: i32) -> bool {
+        let size = self.size();
+        if !(0..size.width).contains(&x) || y < 0 {
+            return true;
+        }
+        y < size.height() && self.rows.row(y) & (1 << x) != 0
+    }
+
+    pub fn fits(&self, cells: &[IVec2]) -> bool {
+        self.rows.fits(&PieceMask::from_cells(cells, self.size()))
+    }
+
+    //locks the cells in and clears any rows they fill, returning how many
+    pub fn place(&mut self, cells: &[IVec2]) -> u32 {
+        for cell in cells {
+            self.rows.set(*cell);
+        }
+        self.rows.clear_full_rows()
+    }
+
+    fn column_heights(&self) -> Vec<i32> {
+        (0..self.size().width)
+            .map(|x| {
+                (0..self.size().height())
+                    .rev()
+                    .find(|y| self.is_filled(x, *y))
+                    .map_or(0, |y| y + 1)
+            })
+            .collect()
+    }
+
+    //spots a t piece pointing down would fill, with at least three of the corners around its
+    //centre blocked
+    fn t_slots(&self, heights: &[i32]) -> u32 {
+        let top = heights.iter().max().copied().unwrap_or(0);
+        let size = self.size();
+        let mut t_slots = 0;
+        for y in 1..top.min(size.height() - 1) {
+            for x in 1..size.width - 1 {
+                let fits = [(x - 1, y), (x, y), (x + 1, y), (x, y - 1)]
+                    .iter()
+                    .all(|(x, y)| !self.is_filled(*x, *y));
+                let corners = [
+                    (x - 1, y - 1),
+                    (x + 1, y - 1),
+                    (x - 1, y + 1),
+                    (x + 1, y + 1),
+                ]
+                .iter()
+                .filter(|(x, y)| self.is_filled(*x, *y))
+                .count();
+                if fits && corners >= 3 {
+                    t_slots += 1;
+                }
+            }
+        }
+        t_slots
+    }
+
+    pub fn evaluate(&self, lines_cleared: u32, weights: &AiWeights) -> f32 {
+        let heights = self.column_heights();
+
+        let aggregate_height: i32 = heights.iter().sum();
+        let holes: i32 = (0..self.size().width)
+            .map(|x| {
+                (0..heights[x as usize])
+                    .filter(|y| !self.is_filled(x, *y))
+                    .count() as i32
+            })
+            .sum();
+        let bumpiness: i32 = heights
+            .windows(2)
+            .map(|pair| (pair[0] - pair[1]).abs())
+            .sum();
+        let wells: i32 = (0..heights.len())
+            .map(|x| {
+                let left = if x == 0 { i32::MAX } else { heights[x - 1] };
+                let right = heights.get(x + 1).copied().unwrap_or(i32::MAX);
+                (left.min(right) - heights[x]).max(0)
+            })
+            .sum();
+
+        weights.aggregate_height * aggregate_height as f32
+            + weights.holes * holes as f32
+            + weights.bumpiness * bumpiness as f32
+            + weights.wells * wells as f32
+            + weights.t_slots * self.t_slots(&heights) as f32
+            + weights.lines_cleared * lines_cleared as f32
+    }
+}
+
+//the ai's choice, and whether the piece from hold is the one to place
+#[derive(Debug, Clone)]
+pub struct AiMove {
+    pub placement: ReachablePlacement,
+    pub uses_hold: bool,
+}
+
+//scores every placement of the current piece, and of the piece hold would give if there is
+//one, and picks the best. each score is pushed up or down by up to the noise
+#[allow(clippy::too_many_arguments)]
+pub fn best_move(
+    board: &AiBoard,
+    current_piece: PieceType,
+    origin: IVec2,
+    rotation: PieceRotation,
+    hold_piece: Option<PieceType>,
+    weights: &AiWeights,
+    evaluation_noise: f32,
+    rng: &mut impl Rng,
+) -> Option<AiMove> {
+    let fits = |cells: &[IVec2]| board.fits(cells);
+    let mut options: Vec<(ReachablePlacement, bool)> =
+        reachable_placements(current_piece, origin, rotation, fits)
+            .into_iter()
+            .map(|placement| (placement, false))
+            .collect();
+    if let Some(hold_piece) = hold_piece {
+        options.extend(
+            reachable_placements(
+                hold_piece,
+                hold_piece.return_spawn_coord_base(board.size()),
+                PieceRotation::Zero,
+                fits,
+            )
+            .into_iter()
+            .map(|placement| (placement, true)),
+        );
+    }
+
+    let mut best: Option<(f32, AiMove)> = None;
+    for (placement, uses_hold) in options {
+        let mut after = board.clone();
+        let lines_cleared = after.place(&placement.cells);
+        let mut score = after.evaluate(lines_cleared, weights);
+        if evaluation_noise > 0. {
+            score += rng.gen_range(-evaluation_noise..evaluation_noise);
+        }
+        let better = match &best {
+            Some((best_score, _)) => score > *best_score,
+            None => true,
+        };
+        if better {
+            best = Some((
+                score,
+                AiMove {
+                    placement,
+                    uses_hold,
+                },
+            ));
+        }
+    }
+    best.map(|(_, ai_move)| ai_move)
+}
+
+pub struct AiPlayer {
+    //frames the current piece has been waiting for the ai
+    frames_waited: u32,
+    //where the evaluation noise comes from
+    rng: StdRng,
+}
+
+impl FromWorld for AiPlayer {
+    fn from_world(_world: &mut World) -> Self {
+        AiPlayer {
+            frames_waited: 0,
+            rng: StdRng::from_entropy(),
+        }
+    }
+}
+
+//in versus the ai plays the second board, against the keyboard
+pub fn setup_ai(
+    ai_settings: Res<AiSettings>,
+    versus_settings: Res<VersusSettings>,
+    mut input_source: ResMut<InputSource>,
+) {
+    if ai_settings.enabled && !versus_settings.enabled {
+        *input_source = InputSource::Ai;
+    }
+}
+
+//places the player's piece wherever the ai picks, waiting between pieces to keep to its pps
+#[allow(clippy::too_many_arguments)]
+pub fn run_ai(
+    game_state: Res<GameStateInfo>,
+    game_settings: Res<GameSettings>,
+    ai_settings: Res<AiSettings>,
+    input_source: Res<InputSource>,
+    mut ai_player: ResMut<AiPlayer>,
+    mut piece_queue: ResMut<PieceQueue>,
+    mut lock_controller: ResMut<LockController>,
+    mut board_data: ResMut<BoardData>,
+) {
+    if *input_source != InputSource::Ai || game_state.game_state != GamePlayState::Playing {
+        return;
+    }
+    let active_piece = match board_data.active_piece {
+        Some(piece) => piece,
+        None => return,
+    };
+    let player_cells = board_core::player_piece_cells(&board_data);
+    if player_cells.is_empty() {
+        return;
+    }
+
+    ai_player.frames_waited += 1;
+    let frames_per_piece = (FRAMES_PER_SECOND as f32 / ai_settings.pieces_per_second) as u32;
+    if ai_player.frames_waited < frames_per_piece {
+        return;
+    }
+
+    let hold_piece = if piece_queue.hold_used {
+        None
+    } else {
+        piece_queue
+            .held_piece
+            .or_else(|| piece_queue.next_pieces.front().copied())
+    };
+    let board = AiBoard::from_board_data(&board_data, &player_cells);
+    let ai_player = &mut *ai_player;
+    let ai_move = match best_move(
+        &board,
+        active_piece.piece_type,
+        active_piece
+            .piece_type
+            .origin_of(active_piece.rotation, &player_cells),
+        active_piece.rotation,
+        hold_piece,
+        &ai_settings.weights,
+        ai_settings.evaluation_noise,
+        &mut ai_player.rng,
+    ) {
+        Some(ai_move) => ai_move,
+        None => return,
+    };
+
+    //the piece from hold spawns next frame and the ai picks again for it then
+    if ai_move.uses_hold {
+        piece_queue.hold_requested = true;
+        return;
+    }
+
+    if board_core::place_player_piece(
+        &mut board_data,
+        ai_move.placement.rotation,
+        &ai_move.placement.cells,
+    ) {
+        lock_controller.frames_grounded = game_settings.lock_delay;
+        lock_controller.spin = ai_move.placement.spin;
+        ai_player.frames_waited = 0;
+    }
+}
+
+//what the ai managed in a headless game
+pub struct BenchmarkResult {
+    pub seed: u64,
+    pub pieces_placed: u32,
+    pub lines_cleared: u32,
+    pub tetrises: u32,
+    pub topped_out: bool,
+    pub seconds: f32,
+}
+
+//plays a game with the ai and no app, piece after piece as fast as it can think, until it
+//tops out or has placed the number of pieces
+pub fn run_benchmark(weights: &AiWeights, seed: u64, piece_limit: u32) -> BenchmarkResult {
+    let start_time = Instant::now();
+    let mut randomizer = Randomizer::new(seed);
+    let mut board = AiBoard::empty(BoardSize::default());
+    let mut next_pieces: VecDeque<PieceType> = VecDeque::new();
+    let mut held_piece: Option<PieceType> = None;
+    let mut result = BenchmarkResult {
+        seed,
+        pieces_placed: 0,
+        lines_cleared: 0,
+        tetrises: 0,
+        topped_out: false,
+        seconds: 0.,
+    };
+
+    while result.pieces_placed < piece_limit {
+        while next_pieces.len() <= QUEUE_LENGTH {
+            next_pieces.push_back(randomizer.next_block());
+        }
+        let current_piece = next_pieces.pop_front().unwrap();
+        let hold_piece = held_piece.or_else(|| next_pieces.front().copied());
+
+        let ai_move = match best_move(
+            &board,
+            current_piece,
+            current_piece.return_spawn_coord_base(board.size()),
+            PieceRotation::Zero,
+            hold_piece,
+            weights,
+            0.,
+            &mut rand::thread_rng(),
+        ) {
+            Some(ai_move) => ai_move,
+            None => {
+                result.topped_out = true;
+                break;
+            }
+        };
+        if ai_move.uses_hold {
+            if held_piece.is_none() {
+                next_pieces.pop_front();
+            }
+            held_piece = Some(current_piece);
+        }
+
+        let lines_cleared = board.place(&ai_move.placement.cells);
+        result.pieces_placed += 1;
+        result.lines_cleared += lines_cleared;
+        if lines_cleared == 4 {
+            result.tetrises += 1;
+        }
+    }
+
+    result.seconds = start_time.elapsed().as_secs_f32();
+    result
+}
+
+//--ai-benchmark <pieces> plays a headless game and logs how it went instead of opening the
+//game. returns true when it ran
+pub fn run_benchmark_from_arguments() -> bool {
+    let arguments: Vec<String> = std::env::args().collect();
+    let index = match arguments
+        .iter()
+        .position(|argument| argument == "--ai-benchmark")
+    {
+        Some(index) => index,
+        None => return false,
+    };
+    //the benchmark runs instead of the game's app, so it sets up the logging that app would have
+    App::new().add_plugin(LogPlugin);
+    let piece_limit = match arguments.get(index + 1).map(|pieces| pieces.parse::<u32>()) {
+        Some(Ok(piece_limit)) => piece_limit,
+        _ => {
+            warn!("--ai-benchmark needs a number of pieces to play");
+            return true;
+        }
+    };
+
+    let settings = AiSettings::from_arguments(&arguments);
+    let seed = rand::random();
+    let result = run_benchmark(&settings.weights, seed, piece_limit);
+    info!(
+        "seed {}, {} pieces{}, {} lines, {} tetrises in {:.2}s, thinking {:.2}ms per piece",
+        result.seed,
+        result.pieces_placed,
+        if result.topped_out {
+            " before topping out"
+        } else {
+            ""
+        },
+        result.lines_cleared,
+        result.tetrises,
+        result.seconds,
+        result.seconds * 1000. / result.pieces_placed.max(1) as f32,
+    );
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_WEIGHTS: AiWeights = AiWeights {
+        aggregate_height: 0.,
+        holes: 0.,
+        bumpiness: 0.,
+        wells: 0.,
+        t_slots: 0.,
+        lines_cleared: 0.,
+    };
+
+    fn board(filled: &[(i32, i32)]) -> AiBoard {
+        let mut board = AiBoard::empty(BoardSize::default());
+        let cells: Vec<IVec2> = filled.iter().map(|&(x, y)| IVec2 { x, y }).collect();
+        assert_eq!(board.place(&cells), 0);
+        board
+    }
+
+    //a row filled all the way across but for the columns given
+    fn row_without(y: i32, gaps: &[i32]) -> Vec<(i32, i32)> {
+        (0..10)
+            .filter(|x| !gaps.contains(x))
+            .map(|x| (x, y))
+            .collect()
+    }
+
+    #[test]
+    fn holes_are_counted_under_blocks() {
+        let weights = AiWeights {
+            holes: 1.,
+            ..NO_WEIGHTS
+        };
+        assert_eq!(board(&[]).evaluate(0, &weights), 0.);
+        assert_eq!(board(&[(0, 0), (1, 1)]).evaluate(0, &weights), 1.);
+        assert_eq!(board(&[(2, 3), (5, 1), (5, 2)]).evaluate(0, &weights), 4.);
+    }
+
+    #[test]
+    fn bumpiness_and_wells_come_from_column_heights() {
+        //every column three high but the leftmost, which is an empty well against the wall
+        let mut filled = row_without(0, &[0]);
+        filled.extend(row_without(1, &[0]));
+        filled.extend(row_without(2, &[0]));
+        let board = board(&filled);
+
+        let only = |weights: AiWeights| board.evaluate(0, &weights);
+        assert_eq!(
+            only(AiWeights {
+                aggregate_height: 1.,
+                ..NO_WEIGHTS
+            }),
+            27.
+        );
+        assert_eq!(
+            only(AiWeights {
+                bumpiness: 1.,
+                ..NO_WEIGHTS
+            }),
+            3.
+        );
+        assert_eq!(
+            only(AiWeights {
+                wells: 1.,
+                ..NO_WEIGHTS
+            }),
+            3.
+        );
+        assert_eq!(
+            only(AiWeights {
+                holes: 1.,
+                ..NO_WEIGHTS
+            }),
+            0.
+        );
+        assert_eq!(
+            only(AiWeights {
+                lines_cleared: 1.,
+                ..NO_WEIGHTS
+            }),
+            0.
+        );
+        assert_eq!(
+            board.evaluate(
+                4,
+                &AiWeights {
+                    lines_cleared: 1.,
+                    ..NO_WEIGHTS
+                }
+            ),
+            4.
+        );
+    }
+
+    #[test]
+    fn t_slots_need_three_corners() {
+        let weights = AiWeights {
+            t_slots: 1.,
+            ..NO_WEIGHTS
+        };
+        let mut filled = row_without(0, &[4]);
+        filled.extend(row_without(1, &[3, 4, 5]));
+        assert_eq!(board(&filled).evaluate(0, &weights), 0.);
+
+        //an overhang on one side makes it a t-spin double slot
+        filled.push((3, 2));
+        assert_eq!(board(&filled).evaluate(0, &weights), 1.);
+    }
+
+    #[test]
+    fn default_weights_prefer_a_clean_board() {
+        let weights = AiWeights::default();
+        let flat = board(&row_without(0, &[9]));
+        let mut covered = row_without(0, &[9]);
+        covered.push((9, 1));
+        assert!(flat.evaluate(0, &weights) > board(&covered).evaluate(0, &weights));
+        assert!(flat.evaluate(1, &weights) > flat.evaluate(0, &weights));
+    }
+}
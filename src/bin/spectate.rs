@@ -0,0 +1,45 @@
+//prints the events a game publishes for spectators, one json line each, to test the feed with.
+//run the game with --spectator-port 7878 and then "cargo run --bin spectate 7878". an address
+//like 127.0.0.1:7878 works too
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+
+fn main() {
+    let target = match std::env::args().nth(1) {
+        Some(target) => target,
+        None => {
+            eprintln!("usage: spectate <port or address>");
+            return;
+        }
+    };
+    let address = if target.contains(':') {
+        target
+    } else {
+        format!("127.0.0.1:{}", target)
+    };
+
+    let stream = match TcpStream::connect(&address) {
+        Ok(stream) => stream,
+        Err(error) => {
+            eprintln!("couldn't connect to {}: {}", address, error);
+            return;
+        }
+    };
+    eprintln!("watching {}", address);
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(error) => {
+                eprintln!("lost the game: {}", error);
+                return;
+            }
+        };
+        if writeln!(stdout, "{}", line).is_err() {
+            return;
+        }
+    }
+    eprintln!("the game closed the feed");
+}
@@ -0,0 +1,238 @@
+//a tiny tetris bot protocol bot to test the game's bot support with. run the game with
+//--bot "cargo run --bin tbp_stub_bot" or point --bot at the built binary.
+//it drops every piece straight down wherever it leaves the fewest holes
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Write};
+
+const BOARD_WIDTH: i32 = 10;
+const BOARD_HEIGHT: i32 = 40;
+const ORIENTATIONS: [&str; 4] = ["north", "east", "south", "west"];
+
+type Board = Vec<Vec<bool>>;
+
+fn north_cells(piece: &str) -> [(i32, i32); 4] {
+    match piece {
+        "I" => [(-1, 0), (0, 0), (1, 0), (2, 0)],
+        "O" => [(0, 0), (1, 0), (0, 1), (1, 1)],
+        "T" => [(-1, 0), (0, 0), (1, 0), (0, 1)],
+        "L" => [(-1, 0), (0, 0), (1, 0), (1, 1)],
+        "J" => [(-1, 0), (0, 0), (1, 0), (-1, 1)],
+        "S" => [(-1, 0), (0, 0), (0, 1), (1, 1)],
+        _ => [(-1, 1), (0, 1), (0, 0), (1, 0)],
+    }
+}
+
+//each clockwise turn takes (x, y) to (y, -x)
+fn piece_cells(piece: &str, orientation: usize, x: i32, y: i32) -> Vec<(i32, i32)> {
+    north_cells(piece)
+        .iter()
+        .map(|cell| {
+            let mut cell = *cell;
+            for _ in 0..orientation {
+                cell = (cell.1, -cell.0);
+            }
+            (cell.0 + x, cell.1 + y)
+        })
+        .collect()
+}
+
+fn fits(board: &Board, cells: &[(i32, i32)]) -> bool {
+    cells.iter().all(|(x, y)| {
+        *x >= 0
+            && *x < BOARD_WIDTH
+            && *y >= 0
+            && *y < BOARD_HEIGHT
+            && !board[*y as usize][*x as usize]
+    })
+}
+
+fn place(board: &mut Board, cells: &[(i32, i32)]) {
+    for (x, y) in cells {
+        board[*y as usize][*x as usize] = true;
+    }
+    board.retain(|row| !row.iter().all(|filled| *filled));
+    while board.len() < BOARD_HEIGHT as usize {
+        board.push(vec![false; BOARD_WIDTH as usize]);
+    }
+}
+
+//holes first, then how tall the stack gets
+fn score(board: &Board) -> (usize, usize) {
+    let mut holes = 0;
+    let mut height = 0;
+    for x in 0..BOARD_WIDTH as usize {
+        let column: Vec<bool> = board.iter().map(|row| row[x]).collect();
+        let column_height = column
+            .iter()
+            .rposition(|filled| *filled)
+            .map(|y| y + 1)
+            .unwrap_or(0);
+        holes += column[..column_height]
+            .iter()
+            .filter(|filled| !**filled)
+            .count();
+        height = height.max(column_height);
+    }
+    (holes, height)
+}
+
+//the best straight drop for the piece, as a tbp move
+fn best_move(board: &Board, piece: &str) -> Option<(Value, (usize, usize))> {
+    let mut best: Option<(Value, (usize, usize))> = None;
+    for (orientation, orientation_name) in ORIENTATIONS.iter().enumerate() {
+        for x in -2..BOARD_WIDTH + 2 {
+            let spawn_y = BOARD_HEIGHT - 3;
+            if !fits(board, &piece_cells(piece, orientation, x, spawn_y)) {
+                continue;
+            }
+            let mut y = spawn_y;
+            while fits(board, &piece_cells(piece, orientation, x, y - 1)) {
+                y -= 1;
+            }
+            let mut after = board.clone();
+            place(&mut after, &piece_cells(piece, orientation, x, y));
+            let placement_score = score(&after);
+            let better = match &best {
+                Some((_, best_score)) => placement_score < *best_score,
+                None => true,
+            };
+            if better {
+                best = Some((
+                    json!({
+                        "location": {"type": piece, "orientation": orientation_name, "x": x, "y": y},
+                        "spin": "none",
+                    }),
+                    placement_score,
+                ));
+            }
+        }
+    }
+    best
+}
+
+struct Game {
+    board: Board,
+    queue: Vec<String>,
+    hold: Option<String>,
+}
+
+impl Game {
+    fn from_start(message: &Value) -> Game {
+        let board = (0..BOARD_HEIGHT as usize)
+            .map(|y| {
+                (0..BOARD_WIDTH as usize)
+                    .map(|x| !message["board"][y][x].is_null())
+                    .collect()
+            })
+            .collect();
+        let pieces = |value: &Value| -> Vec<String> {
+            value
+                .as_array()
+                .map(|pieces| {
+                    pieces
+                        .iter()
+                        .filter_map(|piece| piece.as_str().map(|piece| piece.to_string()))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        Game {
+            board,
+            queue: pieces(&message["queue"]),
+            hold: message["hold"].as_str().map(|piece| piece.to_string()),
+        }
+    }
+
+    //the current piece, or the one hold would give
+    fn suggest(&self) -> Vec<Value> {
+        let mut options = vec![];
+        if let Some(current) = self.queue.first() {
+            options.extend(best_move(&self.board, current));
+        }
+        let held = self.hold.as_ref().or_else(|| self.queue.get(1));
+        if let Some(held) = held {
+            options.extend(best_move(&self.board, held));
+        }
+        options.sort_by_key(|(_, placement_score)| *placement_score);
+        options.into_iter().map(|(tbp_move, _)| tbp_move).collect()
+    }
+
+    fn play(&mut self, tbp_move: &Value) {
+        let location = &tbp_move["location"];
+        let piece = location["type"].as_str().unwrap_or("").to_string();
+        if self.queue.is_empty() {
+            return;
+        }
+        if self.queue[0] != piece {
+            //the move used hold
+            let current = self.queue.remove(0);
+            if let Some(held) = self.hold.take() {
+                self.queue.insert(0, held);
+            }
+            self.hold = Some(current);
+        }
+        if !self.queue.is_empty() {
+            self.queue.remove(0);
+        }
+
+        let orientation = ORIENTATIONS
+            .iter()
+            .position(|name| Some(*name) == location["orientation"].as_str())
+            .unwrap_or(0);
+        let x = location["x"].as_i64().unwrap_or(0) as i32;
+        let y = location["y"].as_i64().unwrap_or(0) as i32;
+        let cells = piece_cells(&piece, orientation, x, y);
+        if fits(&self.board, &cells) {
+            place(&mut self.board, &cells);
+        }
+    }
+}
+
+fn send(message: Value) {
+    let mut stdout = io::stdout();
+    let _ = writeln!(stdout, "{}", message);
+    let _ = stdout.flush();
+}
+
+fn main() {
+    send(json!({
+        "type": "info",
+        "name": "stub bot",
+        "version": "0.1",
+        "author": "tetris_rs",
+        "features": [],
+    }));
+
+    let mut game: Option<Game> = None;
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let message: Value = match serde_json::from_str(&line) {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+        match message["type"].as_str() {
+            Some("rules") => send(json!({"type": "ready"})),
+            Some("start") => game = Some(Game::from_start(&message)),
+            Some("suggest") => {
+                let moves = game.as_ref().map(|game| game.suggest()).unwrap_or_default();
+                send(json!({"type": "suggestion", "moves": moves}));
+            }
+            Some("play") => {
+                if let Some(game) = &mut game {
+                    game.play(&message["move"]);
+                }
+            }
+            Some("new_piece") => {
+                if let (Some(game), Some(piece)) = (&mut game, message["piece"].as_str()) {
+                    game.queue.push(piece.to_string());
+                }
+            }
+            Some("stop") => game = None,
+            Some("quit") => break,
+            _ => {}
+        }
+    }
+}
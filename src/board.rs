@@ -1,2 +1,5 @@
-﻿pub mod board_core;
+﻿pub mod bitboard;
+pub mod board_core;
+pub mod fumen;
+pub mod move_gen;
 mod piece_consts;
@@ -0,0 +1,431 @@
+use bevy::prelude::*;
+
+use crate::board::board_core::BoardSize;
+
+//a row of the board, one bit per column with the leftmost column in the lowest bit
+pub type Row = u16;
+
+//the tallest a piece gets
+const MASK_ROWS: usize = 4;
+
+//which cells of the board have a block in them, bottom row first
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Bitboard {
+    size: BoardSize,
+    //a row with every column filled
+    full_row: Row,
+    rows: Vec<Row>,
+}
+
+impl Bitboard {
+    pub fn empty(size: BoardSize) -> Bitboard {
+        Bitboard {
+            size,
+            full_row: Row::MAX >> (Row::BITS as i32 - size.width),
+            rows: vec![0; size.height() as usize],
+        }
+    }
+
+    pub fn size(&self) -> BoardSize {
+        self.size
+    }
+
+    pub fn in_bounds(&self, cell: IVec2) -> bool {
+        self.size.contains(cell)
+    }
+
+    //cells off the board are never filled
+    pub fn is_filled(&self, cell: IVec2) -> bool {
+        self.in_bounds(cell) && self.rows[cell.y as usize] & (1 << cell.x) != 0
+    }
+
+    pub fn set(&mut self, cell: IVec2) {
+        if self.in_bounds(cell) {
+            self.rows[cell.y as usize] |= 1 << cell.x;
+        }
+    }
+
+    pub fn unset(&mut self, cell: IVec2) {
+        if self.in_bounds(cell) {
+            self.rows[cell.y as usize] &= !(1 << cell.x);
+        }
+    }
+
+    pub fn row(&self, y: i32) -> Row {
+        self.rows[y as usize]
+    }
+
+    pub fn row_full(&self, y: i32) -> bool {
+        self.rows[y as usize] == self.full_row
+    }
+
+    pub fn clear(&mut self) {
+        for row in self.rows.iter_mut() {
+            *row = 0;
+        }
+    }
+
+    //true when the piece is on the board and none of its cells are filled
+    pub fn fits(&self, mask: &PieceMask) -> bool {
+        if !mask.in_bounds {
+            return false;
+        }
+        for row in 0..mask.height {
+            if self.rows[mask.bottom + row] & mask.rows[row] != 0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    //takes out every full row and drops the rows above down into the gap, returning how many
+    //there were
+    pub fn clear_full_rows(&mut self) -> u32 {
+        let mut kept = 0;
+        for y in 0..self.rows.len() {
+            if self.rows[y] != self.full_row {
+                self.rows[kept] = self.rows[y];
+                kept += 1;
+            }
+        }
+        let cleared = self.rows.len() - kept;
+        for row in &mut self.rows[kept..] {
+            *row = 0;
+        }
+        cleared as u32
+    }
+}
+
+//a piece's cells as a bitmask for each row it covers, so the whole piece can be checked
+//against the board a row at a time
+pub struct PieceMask {
+    bottom: usize,
+    height: usize,
+    rows: [Row; MASK_ROWS],
+    //false when a cell is off the board or the cells cover more rows than a piece can
+    in_bounds: bool,
+}
+
+impl PieceMask {
+    //the mask of the cells on a board of this size
+    pub fn from_cells(cells: &[IVec2], size: BoardSize) -> PieceMask {
+        let mut mask = PieceMask {
+            bottom: 0,
+            height: 0,
+            rows: [0; MASK_ROWS],
+            in_bounds: false,
+        };
+        if cells.is_empty() {
+            mask.in_bounds = true;
+            return mask;
+        }
+
+        let mut bottom = size.height();
+        let mut top = -1;
+        for cell in cells {
+            if !size.contains(*cell) {
+                return mask;
+            }
+            bottom = bottom.min(cell.y);
+            top = top.max(cell.y);
+        }
+        let height = (top - bottom + 1) as usize;
+        if height > MASK_ROWS {
+            return mask;
+        }
+
+        for cell in cells {
+            mask.rows[(cell.y - bottom) as usize] |= 1 << cell.x;
+        }
+        mask.bottom = bottom as usize;
+        mask.height = height;
+        mask.in_bounds = true;
+        mask
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::HashMap;
+    use std::time::Instant;
+
+    use crate::board::board_core::{PieceRotation, PieceType};
+
+    const ALL_PIECE_TYPES: [PieceType; 7] = [
+        PieceType::I,
+        PieceType::J,
+        PieceType::L,
+        PieceType::O,
+        PieceType::S,
+        PieceType::T,
+        PieceType::Z,
+    ];
+
+    const BENCHMARK_ROUNDS: u32 = 100;
+
+    //how long each way of storing the board takes for the same work
+    struct BoardBenchmarkResult {
+        checks: u64,
+        hash_map_collision_seconds: f32,
+        bitboard_collision_seconds: f32,
+        piece_mask_collision_seconds: f32,
+        hash_map_row_seconds: f32,
+        bitboard_row_seconds: f32,
+    }
+
+    //tries every position of every piece on a set of half filled boards, and looks for full rows
+    //on them, once with the blocks in a hash map of points the way the board used to keep them
+    //and once with the bitboard
+    fn run_benchmark(seed: u64, rounds: u32) -> BoardBenchmarkResult {
+        let size = BoardSize::default();
+        let mut rng = StdRng::seed_from_u64(seed);
+        let boards: Vec<Bitboard> = (0..16)
+            .map(|_| {
+                let mut board = Bitboard::empty(size);
+                for y in 0..size.height() / 2 {
+                    for x in 0..size.width {
+                        if rng.gen_bool(0.6) {
+                            board.set(IVec2 { x, y });
+                        }
+                    }
+                }
+                board
+            })
+            .collect();
+        let hash_maps: Vec<HashMap<IVec2, bool>> = boards
+            .iter()
+            .map(|board| {
+                let mut points = HashMap::new();
+                for y in 0..size.height() {
+                    for x in 0..size.width {
+                        points.insert(IVec2 { x, y }, board.is_filled(IVec2 { x, y }));
+                    }
+                }
+                points
+            })
+            .collect();
+
+        let mut placements: Vec<Vec<IVec2>> = vec![];
+        for piece_type in ALL_PIECE_TYPES {
+            for rotation in PieceRotation::ALL {
+                for y in -2..size.height() + 2 {
+                    for x in -2..size.width + 2 {
+                        placements.push(piece_type.cells(rotation, IVec2 { x, y }));
+                    }
+                }
+            }
+        }
+        let checks = placements.len() as u64 * boards.len() as u64 * rounds as u64;
+
+        let start_time = Instant::now();
+        let mut hash_map_fits = 0;
+        for _ in 0..rounds {
+            for points in &hash_maps {
+                for cells in &placements {
+                    let fits = cells.iter().all(|cell| points.get(cell) == Some(&false));
+                    if fits {
+                        hash_map_fits += 1;
+                    }
+                }
+            }
+        }
+        let hash_map_collision_seconds = start_time.elapsed().as_secs_f32();
+
+        //the game and the ai build a mask for every check they make
+        let start_time = Instant::now();
+        let mut bitboard_fits = 0;
+        for _ in 0..rounds {
+            for board in &boards {
+                for cells in &placements {
+                    if board.fits(&PieceMask::from_cells(cells, size)) {
+                        bitboard_fits += 1;
+                    }
+                }
+            }
+        }
+        let bitboard_collision_seconds = start_time.elapsed().as_secs_f32();
+        assert_eq!(hash_map_fits, bitboard_fits);
+
+        //the masks are built up front, the way a piece that's checked again and again would keep
+        //one
+        let masks: Vec<PieceMask> = placements
+            .iter()
+            .map(|cells| PieceMask::from_cells(cells, size))
+            .collect();
+        let start_time = Instant::now();
+        let mut piece_mask_fits = 0;
+        for _ in 0..rounds {
+            for board in &boards {
+                for mask in &masks {
+                    if board.fits(mask) {
+                        piece_mask_fits += 1;
+                    }
+                }
+            }
+        }
+        let piece_mask_collision_seconds = start_time.elapsed().as_secs_f32();
+        assert_eq!(hash_map_fits, piece_mask_fits);
+
+        let row_rounds = u64::from(rounds) * 100;
+        let start_time = Instant::now();
+        let mut hash_map_full_rows = 0;
+        for _ in 0..row_rounds {
+            for points in &hash_maps {
+                for y in 0..size.height() {
+                    if (0..size.width).all(|x| points.get(&IVec2 { x, y }) == Some(&true)) {
+                        hash_map_full_rows += 1;
+                    }
+                }
+            }
+        }
+        let hash_map_row_seconds = start_time.elapsed().as_secs_f32();
+
+        let start_time = Instant::now();
+        let mut bitboard_full_rows = 0;
+        for _ in 0..row_rounds {
+            for board in &boards {
+                for y in 0..size.height() {
+                    if board.row_full(y) {
+                        bitboard_full_rows += 1;
+                    }
+                }
+            }
+        }
+        let bitboard_row_seconds = start_time.elapsed().as_secs_f32();
+        assert_eq!(hash_map_full_rows, bitboard_full_rows);
+
+        BoardBenchmarkResult {
+            checks,
+            hash_map_collision_seconds,
+            bitboard_collision_seconds,
+            piece_mask_collision_seconds,
+            hash_map_row_seconds,
+            bitboard_row_seconds,
+        }
+    }
+
+    //times the board storage against a hash map of points, failing if the bitboard isn't the
+    //faster one. it takes a while, so it only runs when asked for with
+    //cargo test --release board_benchmark -- --ignored
+    #[test]
+    #[ignore]
+    fn board_benchmark() {
+        let result = run_benchmark(rand::random(), BENCHMARK_ROUNDS);
+        let timings = format!(
+            "{} collision checks: hash map {:.3}s, bitboard {:.3}s, prebuilt piece masks {:.3}s. full row checks: hash map {:.3}s, bitboard {:.3}s",
+            result.checks,
+            result.hash_map_collision_seconds,
+            result.bitboard_collision_seconds,
+            result.piece_mask_collision_seconds,
+            result.hash_map_row_seconds,
+            result.bitboard_row_seconds,
+        );
+        assert!(
+            result.bitboard_collision_seconds < result.hash_map_collision_seconds,
+            "{}",
+            timings
+        );
+        assert!(
+            result.piece_mask_collision_seconds < result.hash_map_collision_seconds,
+            "{}",
+            timings
+        );
+        assert!(
+            result.bitboard_row_seconds < result.hash_map_row_seconds,
+            "{}",
+            timings
+        );
+    }
+
+    fn random_board(rng: &mut StdRng, size: BoardSize) -> Bitboard {
+        let mut board = Bitboard::empty(size);
+        for y in 0..size.height() {
+            for x in 0..size.width {
+                if rng.gen_bool(0.4) {
+                    board.set(IVec2 { x, y });
+                }
+            }
+        }
+        board
+    }
+
+    fn fill_row(board: &mut Bitboard, y: i32) {
+        for x in 0..board.size().width {
+            board.set(IVec2 { x, y });
+        }
+    }
+
+    #[test]
+    fn clear_full_rows_drops_the_rows_above() {
+        let mut board = Bitboard::empty(BoardSize::default());
+        fill_row(&mut board, 0);
+        board.set(IVec2 { x: 3, y: 1 });
+        fill_row(&mut board, 2);
+        fill_row(&mut board, 3);
+        board.set(IVec2 { x: 5, y: 4 });
+        board.set(IVec2 { x: 0, y: 29 });
+
+        assert_eq!(board.clear_full_rows(), 3);
+        assert_eq!(board.row(0), 1 << 3);
+        assert_eq!(board.row(1), 1 << 5);
+        assert_eq!(board.row(26), 1);
+        for y in (2..26).chain(27..30) {
+            assert_eq!(board.row(y), 0, "row {}", y);
+        }
+        assert_eq!(board.clear_full_rows(), 0);
+    }
+
+    #[test]
+    fn full_rows_on_the_widest_board() {
+        let size = BoardSize {
+            width: Row::BITS as i32,
+            ..default()
+        };
+        let mut board = Bitboard::empty(size);
+        fill_row(&mut board, 0);
+        board.set(IVec2 { x: 15, y: 1 });
+        assert!(board.row_full(0));
+        assert!(!board.row_full(1));
+        assert!(!board.is_filled(IVec2 { x: 16, y: 1 }));
+        assert_eq!(board.clear_full_rows(), 1);
+        assert_eq!(board.row(0), 1 << 15);
+    }
+
+    //the bitboard checks have to agree with looking at the cells one at a time, for pieces on,
+    //against and off every edge of the board
+    #[test]
+    fn fits_matches_cell_by_cell() {
+        let mut rng = StdRng::seed_from_u64(41);
+        for width in [4, 10, Row::BITS as i32] {
+            let size = BoardSize {
+                width,
+                visible_height: 8,
+                buffer_height: 4,
+            };
+            for _ in 0..8 {
+                let board = random_board(&mut rng, size);
+                for piece_type in ALL_PIECE_TYPES {
+                    for rotation in PieceRotation::ALL {
+                        for y in -2..size.height() + 2 {
+                            for x in -2..size.width + 2 {
+                                let cells = piece_type.cells(rotation, IVec2 { x, y });
+                                let expected = cells
+                                    .iter()
+                                    .all(|cell| size.contains(*cell) && !board.is_filled(*cell));
+                                assert_eq!(
+                                    board.fits(&PieceMask::from_cells(&cells, size)),
+                                    expected,
+                                    "{:?}",
+                                    cells
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,11 +1,21 @@
-﻿use crate::FlexDirection::Row;
+use crate::board::bitboard::{self, Bitboard, PieceMask};
+use crate::board::fumen::{
+    FumenBlock, FumenField, FumenPage, FumenPiece, FumenRotation, FUMEN_FIELD_TOP,
+    FUMEN_FIELD_WIDTH,
+};
+use crate::board::move_gen::{self, Spin};
+use crate::board::piece_consts::{kick_table_row, HALF_TURN_KICKS, I_KICKS, JLSTZ_KICKS};
+use crate::theme::Theme;
+use crate::FlexDirection::Row;
+use crate::{GameSettings, ScoreEvent};
 use bevy::math::vec3;
 use bevy::prelude::*;
 use bevy::reflect::List;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
 use std::option::Option;
 use std::process::id;
-use crate::ScoreEvent;
 
 pub struct BoardPlugin;
 
@@ -21,16 +31,20 @@ impl Plugin for BoardPlugin {
 }
 
 //sizes
-const BOARD_WIDTH_PIXELS: f32 = PIECE_SIZE_PIXEL * BOARD_WIDTH as f32; // total width of board is 360 - 20 for left and right wall bring it to 320
-const BOARD_HEIGHT_PIXELS: f32 = PIECE_SIZE_PIXEL * BOARD_HEIGHT as f32;
-
 const WALL_SIZE_PIXEL: f32 = 20.;
+const GARBAGE_METER_WIDTH_PIXEL: f32 = 12.;
 
 const PIECE_SIZE_PIXEL: f32 = 32.;
 
-const BOARD_WIDTH: i32 = 10;
-const BOARD_HEIGHT: i32 = 30;
-const BOARD_GAMEPLAY_HEIGHT: i32 = 20;
+//a row of the board is one Row of the bitboard, so it can't be any wider than that
+const MIN_BOARD_WIDTH: i32 = 4;
+const MAX_BOARD_WIDTH: i32 = bitboard::Row::BITS as i32;
+const MIN_VISIBLE_HEIGHT: i32 = 4;
+//pieces spawn in the buffer, and the tallest spawns three rows up into it
+const MIN_BUFFER_HEIGHT: i32 = 3;
+const MAX_BOARD_HEIGHT: i32 = 200;
+//frames between flashes of rows being cleared
+const ROW_CLEAR_FLASH_FRAMES: u32 = 4;
 
 const BLOCK_LIGHT_BLUE_SPRITE: &str = "LightBlueBlock.png";
 const BLOCK_BLUE_SPRITE: &str = "BlueBlock.png";
@@ -39,11 +53,133 @@ const BLOCK_YELLOW_SPRITE: &str = "YellowBlock.png";
 const BLOCK_GREEN_SPRITE: &str = "GreenBlock.png";
 const BLOCK_PURPLE_SPRITE: &str = "PurpleBlock.png";
 const BLOCK_RED_SPRITE: &str = "RedBlock.png";
-
-const WALL_COLOR: Color = Color::rgb(1., 1., 1.);
+const BLOCK_WHITE_SPRITE: &str = "WhiteBlock.png";
 
 //data structure stuff
 
+//how many cells the board has. the buffer is the rows above the visible ones that pieces spawn
+//into, which aren't drawn inside the walls
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BoardSize {
+    pub width: i32,
+    pub visible_height: i32,
+    pub buffer_height: i32,
+}
+
+impl Default for BoardSize {
+    fn default() -> Self {
+        BoardSize {
+            width: 10,
+            visible_height: 20,
+            buffer_height: 10,
+        }
+    }
+}
+
+impl BoardSize {
+    //--board-width, --board-height and --buffer-height change the board from the usual 10 by 20
+    //with 10 rows of buffer
+    pub fn from_arguments(arguments: &[String]) -> BoardSize {
+        let default = BoardSize::default();
+        let size = BoardSize {
+            width: cells_from_arguments(arguments, "--board-width", default.width),
+            visible_height: cells_from_arguments(
+                arguments,
+                "--board-height",
+                default.visible_height,
+            ),
+            buffer_height: cells_from_arguments(
+                arguments,
+                "--buffer-height",
+                default.buffer_height,
+            ),
+        };
+        match size.validate() {
+            Ok(()) => size,
+            Err(error) => {
+                warn!("{}, playing on the usual board instead", error);
+                default
+            }
+        }
+    }
+
+    //every size that comes from outside the game, on the command line, in a save, a replay or
+    //from a netplay host, goes through here before a board is made at it
+    pub fn validate(&self) -> Result<(), InvalidBoardSize> {
+        if (MIN_BOARD_WIDTH..=MAX_BOARD_WIDTH).contains(&self.width)
+            && (MIN_VISIBLE_HEIGHT..=MAX_BOARD_HEIGHT).contains(&self.visible_height)
+            && (MIN_BUFFER_HEIGHT..=MAX_BOARD_HEIGHT).contains(&self.buffer_height)
+        {
+            Ok(())
+        } else {
+            Err(InvalidBoardSize(*self))
+        }
+    }
+
+    //the visible rows and the buffer together
+    pub fn height(&self) -> i32 {
+        self.visible_height + self.buffer_height
+    }
+
+    pub fn contains(&self, cell: IVec2) -> bool {
+        (0..self.width).contains(&cell.x) && (0..self.height()).contains(&cell.y)
+    }
+
+    fn width_pixels(&self) -> f32 {
+        PIECE_SIZE_PIXEL * self.width as f32
+    }
+
+    fn visible_height_pixels(&self) -> f32 {
+        PIECE_SIZE_PIXEL * self.visible_height as f32
+    }
+
+    //how much room the board takes up on screen with its walls
+    pub fn pixels(&self) -> Vec2 {
+        Vec2 {
+            x: self.width_pixels() + (WALL_SIZE_PIXEL * 2.) + 2.,
+            y: self.visible_height_pixels() + (WALL_SIZE_PIXEL * 2.) + 2.,
+        }
+    }
+}
+
+//a number of cells given after the flag, or the default when the flag isn't there
+fn cells_from_arguments(arguments: &[String], flag: &str, default: i32) -> i32 {
+    let index = match arguments.iter().position(|argument| argument == flag) {
+        Some(index) => index,
+        None => return default,
+    };
+    match arguments.get(index + 1).map(|cells| cells.parse::<i32>()) {
+        Some(Ok(cells)) => cells,
+        _ => {
+            warn!("{} needs a number of cells", flag);
+            default
+        }
+    }
+}
+
+//a board size the game can't be played at
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InvalidBoardSize(pub BoardSize);
+
+impl fmt::Display for InvalidBoardSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "a board {} wide and {} tall with {} rows of buffer can't be played, boards are {} to {} \
+             wide, {} to {} tall and have {} to {} rows of buffer",
+            self.0.width,
+            self.0.visible_height,
+            self.0.buffer_height,
+            MIN_BOARD_WIDTH,
+            MAX_BOARD_WIDTH,
+            MIN_VISIBLE_HEIGHT,
+            MAX_BOARD_HEIGHT,
+            MIN_BUFFER_HEIGHT,
+            MAX_BOARD_HEIGHT
+        )
+    }
+}
+
 pub struct BlockId {
     highest_block_id: u64,
 }
@@ -57,115 +193,258 @@ impl FromWorld for BlockId {
 }
 
 //represents a set of blocks in a piece
-struct Piece {
-    rotation: PieceRotation,
+#[derive(Copy, Clone, Serialize, Deserialize)]
+pub struct Piece {
+    pub piece_type: PieceType,
+    pub rotation: PieceRotation,
 }
 
-enum PieceRotation {
+//rotation states clockwise from how a piece spawns
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PieceRotation {
     Zero,
     Ninety,
     OneEighty,
     TwoSeventy,
 }
 
-//a point on the grid
-struct BoardPoint {
-    is_full: bool,
-    coordinates: IVec2,
-    entity_in_point: Option<Entity>,
-    id: u64,
+impl PieceRotation {
+    pub const ALL: [PieceRotation; 4] = [
+        PieceRotation::Zero,
+        PieceRotation::Ninety,
+        PieceRotation::OneEighty,
+        PieceRotation::TwoSeventy,
+    ];
+
+    fn index(&self) -> usize {
+        match self {
+            PieceRotation::Zero => 0,
+            PieceRotation::Ninety => 1,
+            PieceRotation::OneEighty => 2,
+            PieceRotation::TwoSeventy => 3,
+        }
+    }
+
+    pub fn rotated(&self, direction: RotationDirection) -> PieceRotation {
+        let turns = match direction {
+            RotationDirection::Clockwise => 1,
+            RotationDirection::Half => 2,
+            RotationDirection::CounterClockwise => 3,
+        };
+        PieceRotation::ALL[(self.index() + turns) % 4]
+    }
+
+    fn from_fumen_rotation(rotation: FumenRotation) -> PieceRotation {
+        match rotation {
+            FumenRotation::Spawn => PieceRotation::Zero,
+            FumenRotation::Right => PieceRotation::Ninety,
+            FumenRotation::Reverse => PieceRotation::OneEighty,
+            FumenRotation::Left => PieceRotation::TwoSeventy,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RotationDirection {
+    Clockwise,
+    CounterClockwise,
+    Half,
+}
+
+//how the board settles after rows are cleared
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LineClearGravity {
+    //the rows above a cleared row move down by however many rows were cleared under them
+    Naive,
+    //what's left of each piece falls as a whole until it lands on something
+    Sticky,
+    //every group of touching blocks falls on its own until it lands, and any rows that fills
+    //are cleared again as a chain
+    Cascade,
+}
+
+impl LineClearGravity {
+    pub fn from_argument(argument: &str) -> Option<LineClearGravity> {
+        match argument {
+            "naive" => Some(LineClearGravity::Naive),
+            "sticky" => Some(LineClearGravity::Sticky),
+            "cascade" => Some(LineClearGravity::Cascade),
+            _ => None,
+        }
+    }
 }
 
+//a block sitting in a cell of the board
+#[derive(Copy, Clone, PartialEq)]
+pub struct BoardCell {
+    pub id: u64,
+    pub color: PieceColor,
+}
+
+//everything on the board. the board's sprites are drawn from this and never read back
 pub struct BoardData {
-    board_points: HashMap<IVec2, BoardPoint>,
+    //which cells have a block in them, what every collision and row check looks at
+    filled: Bitboard,
+    //the block in each cell, a row at a time from the bottom
+    cells: Vec<Option<BoardCell>>,
+    //where the player's piece is. its blocks are in the cells along with everything else
+    player_cells: Vec<IVec2>,
     pieces_in_game: Vec<Piece>,
+    //the piece the player is controlling, if there is one
+    pub active_piece: Option<Piece>,
+    pub comment: String,
+    //the full rows waiting out the line clear delay, if there are any
+    row_clear: Option<RowClear>,
+}
+
+//full rows that are flashing before they're taken out
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RowClear {
+    rows: Vec<i32>,
+    frames: u32,
+    frames_left: u32,
 }
 
 impl FromWorld for BoardData {
     fn from_world(world: &mut World) -> Self {
+        //the board plugin goes in before the game settings do
+        world.init_resource::<GameSettings>();
+        BoardData::new(world.resource::<GameSettings>().board_size)
+    }
+}
+
+impl BoardData {
+    pub fn new(size: BoardSize) -> BoardData {
         BoardData {
-            board_points: HashMap::new(),
+            filled: Bitboard::empty(size),
+            cells: vec![None; (size.width * size.height()) as usize],
+            player_cells: vec![],
             pieces_in_game: vec![],
+            active_piece: None,
+            comment: String::new(),
+            row_clear: None,
         }
     }
-}
 
-impl Piece {
-    fn new(
-        piece_type: PieceType,
-        mut commands: &mut Commands,
-        asset_server: &Res<AssetServer>,
-        mut board_data: &mut ResMut<BoardData>,
-        highest_block_id: &mut ResMut<BlockId>,
-    ) -> Piece {
-        let piece_coords: Vec<IVec2> = piece_type.get_block_coords_delta();
-        let new_piece_id: u64 = highest_block_id.highest_block_id + 1;
-        highest_block_id.highest_block_id = new_piece_id;
+    pub fn size(&self) -> BoardSize {
+        self.filled.size()
+    }
 
-        let new_piece = Piece {
-            rotation: PieceRotation::Zero,
-        };
+    fn cell_index(&self, coordinates: &IVec2) -> Option<usize> {
+        if self.filled.in_bounds(*coordinates) {
+            Some((coordinates.y * self.size().width + coordinates.x) as usize)
+        } else {
+            None
+        }
+    }
 
-        for piece_coord in piece_coords {
-            let spawn_coord_base = piece_type.return_spawn_coord_base();
-            let piece_true_coord = IVec2 {
-                x: piece_coord.x + spawn_coord_base.x,
-                y: piece_coord.y + spawn_coord_base.y,
-            };
-            //info!(piece_true_coord.x, piece_true_coord.y);
-            let entity_commands = commands.spawn_bundle(BlockBundle::new(
-                BoardPointCoordinates {
-                    coordinates: piece_true_coord,
-                },
-                piece_type.get_block_color(),
-                new_piece_id,
-                &asset_server,
-            ));
+    fn clear_points(&mut self) {
+        self.filled.clear();
+        for cell in self.cells.iter_mut() {
+            *cell = None;
+        }
+        self.player_cells.clear();
+        self.active_piece = None;
+        self.row_clear = None;
+    }
+
+    pub fn filled(&self) -> &Bitboard {
+        &self.filled
+    }
+
+    pub fn cell(&self, coordinates: &IVec2) -> Option<&BoardCell> {
+        self.cell_index(coordinates)
+            .and_then(|index| self.cells[index].as_ref())
+    }
+
+    pub fn player_cells(&self) -> &[IVec2] {
+        &self.player_cells
+    }
+
+    fn player_id(&self) -> Option<u64> {
+        self.player_cells
+            .first()
+            .and_then(|cell| self.cell(cell))
+            .map(|block| block.id)
+    }
+
+    //returns false if the cell is off the board
+    fn put_block(&mut self, coordinates: &IVec2, block: BoardCell) -> bool {
+        match self.cell_index(coordinates) {
+            Some(index) => {
+                self.cells[index] = Some(block);
+                self.filled.set(*coordinates);
+                true
+            }
+            None => false,
+        }
+    }
 
-            let entity = entity_commands.id();
+    fn take_block(&mut self, coordinates: &IVec2) -> Option<BoardCell> {
+        let index = self.cell_index(coordinates)?;
+        self.filled.unset(*coordinates);
+        self.cells[index].take()
+    }
 
-            if let Some(mut board_point) = board_data.board_points.get_mut(&piece_true_coord) {
-                board_point.entity_in_point = Option::from(entity);
-                board_point.is_full = true;
-                board_point.id = new_piece_id;
+    //moves blocks from one cell to another. they're all taken out before any are put back so
+    //blocks of a piece can move into each other's cells
+    fn move_blocks(&mut self, moves: &[(IVec2, IVec2)]) {
+        let taken: Vec<Option<BoardCell>> = moves
+            .iter()
+            .map(|(from, _to)| self.take_block(from))
+            .collect();
+        for ((_from, to), block) in moves.iter().zip(taken) {
+            if let Some(block) = block {
+                self.put_block(to, block);
             }
         }
+    }
+
+    //true when a block is sitting in the point, the player's piece included
+    pub fn is_filled(&self, coordinates: &IVec2) -> bool {
+        self.filled.is_filled(*coordinates)
+    }
 
-        new_piece
+    //true when the piece with this id could have blocks in all of the cells. this is the check
+    //every move and rotation of the player's piece goes through
+    pub fn piece_fits(&self, cells: &[IVec2], id: u64) -> bool {
+        self.filled.fits(&PieceMask::from_cells(cells, self.size()))
+            || cells.iter().all(|cell| self.point_free_for(cell, id))
+    }
+
+    //true when a block of the piece with this id could go in the point
+    fn point_free_for(&self, coordinates: &IVec2, id: u64) -> bool {
+        self.filled.in_bounds(*coordinates)
+            && (!self.filled.is_filled(*coordinates)
+                || self.cell(coordinates).map_or(true, |block| block.id == id))
     }
 }
 
-#[derive(Component)]
-pub struct CurrentPlayerControlled {}
+//where the middle of a cell is drawn, from the middle of the board
+pub fn cell_world_position(size: BoardSize, cell: IVec2) -> Vec3 {
+    vec3(
+        (cell.x as f32 * PIECE_SIZE_PIXEL) - ((size.width_pixels() - PIECE_SIZE_PIXEL) / 2.),
+        (cell.y as f32 * PIECE_SIZE_PIXEL)
+            - ((size.visible_height_pixels() - PIECE_SIZE_PIXEL) / 2.),
+        0.,
+    )
+}
 
+//one cell of a player's board. every cell has a sprite for the whole game, shown with the
+//colour of whatever block is in the cell and hidden while it's empty
 #[derive(Component)]
-pub struct BlockID {
-    id: u64,
+pub struct BoardCellSprite {
+    pub player: usize,
+    cell: IVec2,
+    shown: Option<PieceColor>,
 }
 
-//board point components
+//the letter of a piece over one of the board's blocks, a child of its BoardCellSprite
 #[derive(Component)]
-pub struct BoardPointCoordinates {
-    coordinates: IVec2,
-}
-
-impl BoardPointCoordinates {
-    fn world_position(&self) -> Vec3 {
-        vec3(
-            (self.coordinates.x as f32 * PIECE_SIZE_PIXEL as f32)
-                - ((BOARD_WIDTH_PIXELS as f32 - PIECE_SIZE_PIXEL as f32) / 2.),
-            (self.coordinates.y as f32 * PIECE_SIZE_PIXEL as f32)
-                - ((BOARD_HEIGHT_PIXELS as f32
-                    - (PIECE_SIZE_PIXEL as f32 * 10.)
-                    - PIECE_SIZE_PIXEL)
-                    / 2.),
-            0.,
-        )
-    }
-}
+pub struct BlockGlyph {}
 
 //piece components
-#[derive(Component, Copy, Clone)]
+#[derive(Component, Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum PieceType {
     I, // 4x1
     J, // 3 tall with a two base, facing left like a j
@@ -206,88 +485,160 @@ impl PieceType {
         piece_color
     }
 
+    //where the blocks sit in the piece's rotation box when it spawns, with y going up
     fn get_block_coords_delta(&self) -> Vec<IVec2> {
         let mut block_coords_delta: Vec<IVec2> = vec![];
         match self {
             PieceType::I => {
-                block_coords_delta.push(IVec2 { x: 0, y: 0 });
-                block_coords_delta.push(IVec2 { x: 1, y: 0 });
-                block_coords_delta.push(IVec2 { x: 2, y: 0 });
-                block_coords_delta.push(IVec2 { x: 3, y: 0 });
+                block_coords_delta.push(IVec2 { x: 0, y: 2 });
+                block_coords_delta.push(IVec2 { x: 1, y: 2 });
+                block_coords_delta.push(IVec2 { x: 2, y: 2 });
+                block_coords_delta.push(IVec2 { x: 3, y: 2 });
             }
             PieceType::J => {
-                block_coords_delta.push(IVec2 { x: 0, y: 0 });
-                block_coords_delta.push(IVec2 { x: 0, y: -1 });
-                block_coords_delta.push(IVec2 { x: 1, y: -1 });
-                block_coords_delta.push(IVec2 { x: 2, y: -1 });
+                block_coords_delta.push(IVec2 { x: 0, y: 2 });
+                block_coords_delta.push(IVec2 { x: 0, y: 1 });
+                block_coords_delta.push(IVec2 { x: 1, y: 1 });
+                block_coords_delta.push(IVec2 { x: 2, y: 1 });
             }
             PieceType::L => {
-                block_coords_delta.push(IVec2 { x: 0, y: 0 });
-                block_coords_delta.push(IVec2 { x: 1, y: 0 });
-                block_coords_delta.push(IVec2 { x: 2, y: 0 });
+                block_coords_delta.push(IVec2 { x: 0, y: 1 });
+                block_coords_delta.push(IVec2 { x: 1, y: 1 });
                 block_coords_delta.push(IVec2 { x: 2, y: 1 });
+                block_coords_delta.push(IVec2 { x: 2, y: 2 });
             }
             PieceType::O => {
-                block_coords_delta.push(IVec2 { x: 0, y: 0 });
-                block_coords_delta.push(IVec2 { x: 1, y: 0 });
                 block_coords_delta.push(IVec2 { x: 1, y: 1 });
-                block_coords_delta.push(IVec2 { x: 0, y: 1 });
+                block_coords_delta.push(IVec2 { x: 2, y: 1 });
+                block_coords_delta.push(IVec2 { x: 2, y: 2 });
+                block_coords_delta.push(IVec2 { x: 1, y: 2 });
             }
             PieceType::S => {
-                block_coords_delta.push(IVec2 { x: 0, y: 0 });
-                block_coords_delta.push(IVec2 { x: 1, y: 0 });
+                block_coords_delta.push(IVec2 { x: 0, y: 1 });
                 block_coords_delta.push(IVec2 { x: 1, y: 1 });
-                block_coords_delta.push(IVec2 { x: 2, y: 1 });
+                block_coords_delta.push(IVec2 { x: 1, y: 2 });
+                block_coords_delta.push(IVec2 { x: 2, y: 2 });
             }
             PieceType::T => {
-                block_coords_delta.push(IVec2 { x: 0, y: 0 });
-                block_coords_delta.push(IVec2 { x: 1, y: 0 });
-                block_coords_delta.push(IVec2 { x: 2, y: 0 });
+                block_coords_delta.push(IVec2 { x: 0, y: 1 });
                 block_coords_delta.push(IVec2 { x: 1, y: 1 });
+                block_coords_delta.push(IVec2 { x: 2, y: 1 });
+                block_coords_delta.push(IVec2 { x: 1, y: 2 });
             }
             PieceType::Z => {
-                block_coords_delta.push(IVec2 { x: 0, y: 0 });
-                block_coords_delta.push(IVec2 { x: 1, y: 0 });
-                block_coords_delta.push(IVec2 { x: 1, y: -1 });
-                block_coords_delta.push(IVec2 { x: 2, y: -1 });
+                block_coords_delta.push(IVec2 { x: 0, y: 2 });
+                block_coords_delta.push(IVec2 { x: 1, y: 2 });
+                block_coords_delta.push(IVec2 { x: 1, y: 1 });
+                block_coords_delta.push(IVec2 { x: 2, y: 1 });
             }
         }
 
         block_coords_delta
     }
 
-    fn return_spawn_coord_base(&self) -> IVec2 {
-        let block_base_coord: IVec2;
+    //bottom left corner of the rotation box when the piece spawns, in the middle of the board
+    //just above the visible rows. pieces that can't be quite in the middle lean left
+    pub fn return_spawn_coord_base(&self, size: BoardSize) -> IVec2 {
         match self {
-            PieceType::I => {
-                block_base_coord = (IVec2 { x: 3, y: 21 });
-            }
-            PieceType::J => {
-                block_base_coord = (IVec2 { x: 3, y: 22 });
-            }
-            PieceType::L => {
-                block_base_coord = (IVec2 { x: 3, y: 21 });
-            }
-            PieceType::O => {
-                block_base_coord = (IVec2 { x: 4, y: 21 });
-            }
-            PieceType::S => {
-                block_base_coord = (IVec2 { x: 3, y: 21 });
-            }
-            PieceType::T => {
-                block_base_coord = (IVec2 { x: 3, y: 21 });
-            }
-            PieceType::Z => {
-                block_base_coord = (IVec2 { x: 3, y: 22 });
+            PieceType::I => IVec2 {
+                x: (size.width - 4) / 2,
+                y: size.visible_height - 1,
+            },
+            _ => IVec2 {
+                x: (size.width - 3) / 2,
+                y: size.visible_height,
+            },
+        }
+    }
+
+    //where the blocks are with the rotation box's bottom left corner at origin
+    pub fn cells(&self, rotation: PieceRotation, origin: IVec2) -> Vec<IVec2> {
+        let box_size = match self {
+            PieceType::I => 4,
+            _ => 3,
+        };
+        let mut cells = self.get_block_coords_delta();
+        //the o piece looks the same every way round, so it stays where it is
+        if *self != PieceType::O {
+            for _ in 0..rotation.index() {
+                for cell in cells.iter_mut() {
+                    *cell = IVec2 {
+                        x: cell.y,
+                        y: box_size - 1 - cell.x,
+                    };
+                }
             }
         }
+        cells.iter().map(|cell| *cell + origin).collect()
+    }
+
+    //works out where the rotation box is from the blocks of a piece
+    pub fn origin_of(&self, rotation: PieceRotation, cells: &[IVec2]) -> IVec2 {
+        let box_cells = self.cells(rotation, IVec2::ZERO);
+        min_corner(cells) - min_corner(&box_cells)
+    }
+
+    pub fn kicks(&self, from: PieceRotation, to: PieceRotation) -> &'static [(i32, i32)] {
+        if (from.index() + 2) % 4 == to.index() {
+            return &HALF_TURN_KICKS;
+        }
+        let row = kick_table_row(from.index(), to.index());
+        match self {
+            PieceType::I => &I_KICKS[row],
+            _ => &JLSTZ_KICKS[row],
+        }
+    }
 
-        block_base_coord
+    fn from_fumen_block(block: FumenBlock) -> Option<PieceType> {
+        match block {
+            FumenBlock::I => Some(PieceType::I),
+            FumenBlock::J => Some(PieceType::J),
+            FumenBlock::L => Some(PieceType::L),
+            FumenBlock::O => Some(PieceType::O),
+            FumenBlock::S => Some(PieceType::S),
+            FumenBlock::T => Some(PieceType::T),
+            FumenBlock::Z => Some(PieceType::Z),
+            FumenBlock::Empty | FumenBlock::Gray => None,
+        }
     }
 }
 
-#[derive(Component)]
-enum PieceColor {
+fn min_corner(cells: &[IVec2]) -> IVec2 {
+    cells
+        .iter()
+        .fold(IVec2::splat(i32::MAX), |corner, cell| corner.min(*cell))
+}
+
+//tries each of the rotation's kicks in turn and returns where the rotation box ends up with the
+//first one that fits
+pub fn kicked_origin(
+    piece_type: PieceType,
+    from: PieceRotation,
+    to: PieceRotation,
+    origin: IVec2,
+    fits: impl Fn(&[IVec2]) -> bool,
+) -> Option<IVec2> {
+    kicked_rotation(piece_type, from, to, origin, fits).map(|(_, kicked_origin)| kicked_origin)
+}
+
+//the same, along with which kick it was. spins care about the kick a rotation used
+pub fn kicked_rotation(
+    piece_type: PieceType,
+    from: PieceRotation,
+    to: PieceRotation,
+    origin: IVec2,
+    fits: impl Fn(&[IVec2]) -> bool,
+) -> Option<(usize, IVec2)> {
+    piece_type
+        .kicks(from, to)
+        .iter()
+        .map(|(x, y)| origin + IVec2 { x: *x, y: *y })
+        .enumerate()
+        .find(|(_, kicked_origin)| fits(&piece_type.cells(to, *kicked_origin)))
+}
+
+#[derive(Component, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PieceColor {
     LightBlue,
     Blue,
     Orange,
@@ -295,10 +646,12 @@ enum PieceColor {
     Green,
     Purple,
     Red,
+    Gray,
 }
 
 impl PieceColor {
-    fn return_texture_path(&self) -> &str {
+    //the default theme's texture
+    pub fn return_texture_path(&self) -> &str {
         match self {
             PieceColor::LightBlue => BLOCK_LIGHT_BLUE_SPRITE,
             PieceColor::Blue => BLOCK_BLUE_SPRITE,
@@ -307,47 +660,34 @@ impl PieceColor {
             PieceColor::Green => BLOCK_GREEN_SPRITE,
             PieceColor::Purple => BLOCK_PURPLE_SPRITE,
             PieceColor::Red => BLOCK_RED_SPRITE,
+            PieceColor::Gray => BLOCK_WHITE_SPRITE,
         }
     }
-}
 
-#[derive(Bundle)]
-struct BlockBundle {
-    #[bundle]
-    sprite_bundle: SpriteBundle,
-    piece_coordinates: BoardPointCoordinates,
-    id: BlockID,
-    piece_color: PieceColor,
-    player_controlled: CurrentPlayerControlled,
-}
-
-impl BlockBundle {
-    fn new(
-        location: BoardPointCoordinates,
-        color: PieceColor,
-        id: u64,
-        asset_server: &Res<AssetServer>,
-    ) -> BlockBundle {
-        BlockBundle {
-            sprite_bundle: SpriteBundle {
-                sprite: Sprite {
-                    color: Default::default(),
-                    flip_x: false,
-                    flip_y: false,
-                    custom_size: None,
-                    anchor: Default::default(),
-                },
-                transform: Transform {
-                    translation: location.world_position(),
-                    ..default()
-                },
-                texture: asset_server.load(color.return_texture_path()),
-                ..default()
-            },
-            piece_coordinates: location,
-            id: BlockID { id },
-            piece_color: color,
-            player_controlled: CurrentPlayerControlled {},
+    fn from_fumen_block(block: FumenBlock) -> Option<PieceColor> {
+        match block {
+            FumenBlock::Empty => None,
+            FumenBlock::I => Some(PieceColor::LightBlue),
+            FumenBlock::J => Some(PieceColor::Blue),
+            FumenBlock::L => Some(PieceColor::Orange),
+            FumenBlock::O => Some(PieceColor::Yellow),
+            FumenBlock::S => Some(PieceColor::Green),
+            FumenBlock::T => Some(PieceColor::Purple),
+            FumenBlock::Z => Some(PieceColor::Red),
+            FumenBlock::Gray => Some(PieceColor::Gray),
+        }
+    }
+
+    fn fumen_block(&self) -> FumenBlock {
+        match self {
+            PieceColor::LightBlue => FumenBlock::I,
+            PieceColor::Blue => FumenBlock::J,
+            PieceColor::Orange => FumenBlock::L,
+            PieceColor::Yellow => FumenBlock::O,
+            PieceColor::Green => FumenBlock::S,
+            PieceColor::Purple => FumenBlock::T,
+            PieceColor::Red => FumenBlock::Z,
+            PieceColor::Gray => FumenBlock::Gray,
         }
     }
 }
@@ -361,7 +701,7 @@ struct WallBundle {
 }
 
 #[derive(Component)]
-enum BoardWallPosition {
+pub enum BoardWallPosition {
     Top,
     Bottom,
     Left,
@@ -369,53 +709,51 @@ enum BoardWallPosition {
 }
 
 impl BoardWallPosition {
-    fn position(&self) -> Vec3 {
+    fn position(&self, size: BoardSize) -> Vec3 {
         match self {
             BoardWallPosition::Top => Vec3 {
                 x: 0.,
-                y: (BOARD_HEIGHT_PIXELS / 2.) + (WALL_SIZE_PIXEL / 2.) + 1.,
+                y: (size.visible_height_pixels() / 2.) + (WALL_SIZE_PIXEL / 2.) + 1.,
                 z: 0.,
             },
             BoardWallPosition::Bottom => Vec3 {
                 x: 0.,
-                y: -(((BOARD_HEIGHT_PIXELS - (PIECE_SIZE_PIXEL as f32 * 10.)) / 2.)
-                    + (WALL_SIZE_PIXEL / 2.)
-                    + 1.),
+                y: -((size.visible_height_pixels() / 2.) + (WALL_SIZE_PIXEL / 2.) + 1.),
                 z: 0.,
             },
             BoardWallPosition::Left => Vec3 {
-                x: -((BOARD_WIDTH_PIXELS / 2.) + (WALL_SIZE_PIXEL / 2.) + 1.),
+                x: -((size.width_pixels() / 2.) + (WALL_SIZE_PIXEL / 2.) + 1.),
                 y: 0.,
                 z: 0.,
             },
             BoardWallPosition::Right => Vec3 {
-                x: ((BOARD_WIDTH_PIXELS / 2.) + (WALL_SIZE_PIXEL / 2.) + 1.),
+                x: ((size.width_pixels() / 2.) + (WALL_SIZE_PIXEL / 2.) + 1.),
                 y: 0.,
                 z: 0.,
             },
         }
     }
 
-    fn scale(&self) -> Vec3 {
+    fn scale(&self, size: BoardSize) -> Vec3 {
         match self {
             BoardWallPosition::Top => Vec3 {
-                x: BOARD_WIDTH_PIXELS + (WALL_SIZE_PIXEL * 2.) + 2.,
+                x: size.width_pixels() + (WALL_SIZE_PIXEL * 2.) + 2.,
                 y: WALL_SIZE_PIXEL,
                 z: 1.,
             },
             BoardWallPosition::Bottom => Vec3 {
-                x: BOARD_WIDTH_PIXELS + (WALL_SIZE_PIXEL * 2.) + 2.,
+                x: size.width_pixels() + (WALL_SIZE_PIXEL * 2.) + 2.,
                 y: WALL_SIZE_PIXEL,
                 z: 1.,
             },
             BoardWallPosition::Left => Vec3 {
                 x: WALL_SIZE_PIXEL,
-                y: BOARD_HEIGHT_PIXELS + 2. - (PIECE_SIZE_PIXEL as f32 * 10.),
+                y: size.visible_height_pixels() + 2.,
                 z: 1.,
             },
             BoardWallPosition::Right => Vec3 {
                 x: WALL_SIZE_PIXEL,
-                y: BOARD_HEIGHT_PIXELS + 2. - (PIECE_SIZE_PIXEL as f32 * 10.),
+                y: size.visible_height_pixels() + 2.,
                 z: 1.,
             },
         }
@@ -423,16 +761,13 @@ impl BoardWallPosition {
 }
 
 impl WallBundle {
-    fn new(wall_position: BoardWallPosition) -> WallBundle {
+    fn new(wall_position: BoardWallPosition, size: BoardSize, color: Color) -> WallBundle {
         WallBundle {
             sprite_bundle: SpriteBundle {
-                sprite: Sprite {
-                    color: WALL_COLOR,
-                    ..default()
-                },
+                sprite: Sprite { color, ..default() },
                 transform: Transform {
-                    translation: wall_position.position(),
-                    scale: wall_position.scale(),
+                    translation: wall_position.position(size),
+                    scale: wall_position.scale(size),
                     ..default()
                 },
                 ..default()
@@ -442,268 +777,968 @@ impl WallBundle {
     }
 }
 
-pub fn setup_board(mut commands: Commands, mut board_data: ResMut<BoardData>) {
-    for y in 0..BOARD_HEIGHT {
-        for x in 0..BOARD_WIDTH {
-            board_data.board_points.insert(
-                IVec2 { x, y },
-                BoardPoint {
-                    is_full: false,
-                    coordinates: IVec2 { x, y },
-                    id: 0,
-                    entity_in_point: None,
+//a bar beside the board as tall as the garbage waiting to come in, growing up from the bottom
+pub fn garbage_meter_position(size: BoardSize, offset: Vec3) -> Vec3 {
+    let board_bottom = cell_world_position(size, IVec2::ZERO).y - (PIECE_SIZE_PIXEL / 2.);
+    offset
+        + Vec3 {
+            x: -((size.width_pixels() / 2.)
+                + WALL_SIZE_PIXEL
+                + (GARBAGE_METER_WIDTH_PIXEL / 2.)
+                + 8.),
+            y: board_bottom,
+            z: 0.,
+        }
+}
+
+pub fn garbage_meter_scale(lines: u32) -> Vec3 {
+    Vec3 {
+        x: GARBAGE_METER_WIDTH_PIXEL,
+        y: lines as f32 * PIECE_SIZE_PIXEL,
+        z: 1.,
+    }
+}
+
+//blocks showing the queue and hold, kept apart from the board's blocks
+#[derive(Component)]
+pub struct PreviewBlock {}
+
+//where the preview pieces go, in board cells from the board's bottom left corner
+pub enum PreviewSlot {
+    Hold,
+    Next(usize),
+}
+
+impl PreviewSlot {
+    //the hold is left of the board and the queue runs down its right, both from a little below
+    //the top of the visible rows
+    fn origin(&self, size: BoardSize) -> IVec2 {
+        let top = size.visible_height - 3;
+        match self {
+            PreviewSlot::Hold => IVec2 { x: -6, y: top },
+            PreviewSlot::Next(index) => IVec2 {
+                x: size.width + 2,
+                y: top - 3 * *index as i32,
+            },
+        }
+    }
+}
+
+//offset moves the preview along with a board that isn't drawn in the middle of the screen
+pub fn spawn_preview_piece(
+    commands: &mut Commands,
+    theme: &Theme,
+    size: BoardSize,
+    piece_type: PieceType,
+    slot: PreviewSlot,
+    offset: Vec3,
+) {
+    let color = piece_type.get_block_color();
+    for cell in piece_type.cells(PieceRotation::Zero, slot.origin(size)) {
+        commands
+            .spawn_bundle(SpriteBundle {
+                transform: Transform {
+                    translation: cell_world_position(size, cell) + offset,
+                    ..default()
+                },
+                sprite: Sprite {
+                    color: theme.block_tint(color),
+                    ..default()
                 },
+                texture: theme.block_texture(color),
+                ..default()
+            })
+            .insert(PreviewBlock {})
+            .with_children(|parent| {
+                if theme.block_glyphs {
+                    parent.spawn_bundle(glyph_bundle(theme, Some(color)));
+                }
+            });
+    }
+}
+
+//the letter drawn just in front of a block
+fn glyph_bundle(theme: &Theme, color: Option<PieceColor>) -> Text2dBundle {
+    Text2dBundle {
+        text: theme.glyph_text(color),
+        transform: Transform::from_xyz(0., 0., 1.),
+        ..default()
+    }
+}
+
+//the entity a player's board is drawn under, so the whole board can be spawned again at once.
+//its walls and cell sprites are its children
+#[derive(Component)]
+pub struct BoardRoot {
+    pub player: usize,
+    size: BoardSize,
+}
+
+pub fn setup_board(mut commands: Commands, theme: Res<Theme>, game_settings: Res<GameSettings>) {
+    spawn_board(
+        &mut commands,
+        &theme,
+        game_settings.board_size,
+        0,
+        Vec3::ZERO,
+    );
+}
+
+//a player's walls and the grid of sprites their board is drawn with, offset from the middle of
+//the screen
+pub fn spawn_board(
+    commands: &mut Commands,
+    theme: &Theme,
+    size: BoardSize,
+    player: usize,
+    offset: Vec3,
+) {
+    commands
+        .spawn_bundle(SpatialBundle {
+            transform: Transform::from_translation(offset),
+            ..default()
+        })
+        .insert(BoardRoot { player, size })
+        .with_children(|parent| {
+            //parent.spawn_bundle(WallBundle::new(BoardWallPosition::Top, size, theme.wall_color));
+            for wall_position in [
+                BoardWallPosition::Bottom,
+                BoardWallPosition::Left,
+                BoardWallPosition::Right,
+            ] {
+                parent.spawn_bundle(WallBundle::new(wall_position, size, theme.wall_color));
+            }
+
+            for y in 0..size.height() {
+                for x in 0..size.width {
+                    let cell = IVec2 { x, y };
+                    parent
+                        .spawn_bundle(SpriteBundle {
+                            transform: Transform {
+                                translation: cell_world_position(size, cell),
+                                ..default()
+                            },
+                            visibility: Visibility { is_visible: false },
+                            ..default()
+                        })
+                        .insert(BoardCellSprite {
+                            player,
+                            cell,
+                            shown: None,
+                        })
+                        .with_children(|cell_sprite| {
+                            cell_sprite
+                                .spawn_bundle(glyph_bundle(theme, None))
+                                .insert(BlockGlyph {});
+                        });
+                }
+            }
+        });
+}
+
+//a game can start on a different size of board than the one on screen, like a replay or a save
+//from one. any board that's the wrong size is spawned again where it was
+pub fn respawn_resized_boards(
+    mut commands: Commands,
+    theme: Res<Theme>,
+    game_settings: Res<GameSettings>,
+    root_query: Query<(Entity, &BoardRoot, &Transform)>,
+) {
+    if !game_settings.is_changed() {
+        return;
+    }
+
+    let size = game_settings.board_size;
+    for (entity, root, transform) in root_query.iter() {
+        if root.size != size {
+            commands.entity(entity).despawn_recursive();
+            spawn_board(
+                &mut commands,
+                &theme,
+                size,
+                root.player,
+                transform.translation,
             );
         }
     }
+}
+
+//what a cell looks like right now. rows being cleared flash white for the first half of the line
+//clear delay, then dissolve from the middle of the board outwards
+fn shown_color(board_data: &BoardData, cell: IVec2) -> Option<PieceColor> {
+    let color = board_data.cell(&cell).map(|block| block.color)?;
+    let row_clear = match &board_data.row_clear {
+        Some(row_clear) if row_clear.rows.contains(&cell.y) && row_clear.frames > 0 => row_clear,
+        _ => return Some(color),
+    };
+
+    let progress = 1.0 - row_clear.frames_left as f32 / row_clear.frames as f32;
+    if progress < 0.5 {
+        if (row_clear.frames_left / ROW_CLEAR_FLASH_FRAMES) % 2 == 0 {
+            return Some(PieceColor::Gray);
+        }
+        return Some(color);
+    }
+    let width = board_data.size().width;
+    let distance_from_middle = (2 * cell.x + 1 - width).abs();
+    let dissolved = (progress - 0.5) * 2.0 * (width + 1) as f32;
+    if (distance_from_middle as f32) < dissolved {
+        None
+    } else {
+        Some(color)
+    }
+}
+
+//brings a cell's sprite up to date with the board. sprites whose cell hasn't changed are left
+//alone so nothing has to be redrawn for them, unless the theme changed under them
+pub fn draw_board_cell(
+    board_data: &BoardData,
+    theme: &Theme,
+    theme_changed: bool,
+    (mut cell_sprite, mut sprite, mut texture, mut visibility): (
+        Mut<BoardCellSprite>,
+        Mut<Sprite>,
+        Mut<Handle<Image>>,
+        Mut<Visibility>,
+    ),
+) {
+    let color = shown_color(board_data, cell_sprite.cell);
+    if color == cell_sprite.shown && !theme_changed {
+        return;
+    }
+    cell_sprite.shown = color;
+    match color {
+        Some(color) => {
+            *texture = theme.block_texture(color);
+            sprite.color = theme.block_tint(color);
+            visibility.is_visible = true;
+        }
+        None => visibility.is_visible = false,
+    }
+}
+
+//draws the board in the world, which in versus is the first player's
+pub fn draw_board(
+    board_data: Res<BoardData>,
+    theme: Res<Theme>,
+    mut sprite_query: Query<(
+        &mut BoardCellSprite,
+        &mut Sprite,
+        &mut Handle<Image>,
+        &mut Visibility,
+    )>,
+) {
+    for sprite in sprite_query.iter_mut() {
+        if sprite.0.player == 0 {
+            draw_board_cell(&board_data, &theme, theme.is_changed(), sprite);
+        }
+    }
+}
 
-    //commands.spawn_bundle(WallBundle::new(BoardWallPosition::Top));
-    commands.spawn_bundle(WallBundle::new(BoardWallPosition::Bottom));
-    commands.spawn_bundle(WallBundle::new(BoardWallPosition::Left));
-    commands.spawn_bundle(WallBundle::new(BoardWallPosition::Right));
+//writes the letters over the board's blocks from what each block is showing, so they follow every
+//player's board and drop out while a cleared row flashes
+pub fn draw_block_glyphs(
+    theme: Res<Theme>,
+    cell_query: Query<(&BoardCellSprite, ChangeTrackers<BoardCellSprite>)>,
+    mut glyph_query: Query<(&Parent, &mut Text), With<BlockGlyph>>,
+) {
+    for (parent, mut text) in glyph_query.iter_mut() {
+        if let Ok((cell_sprite, tracker)) = cell_query.get(parent.get()) {
+            if tracker.is_changed() || theme.is_changed() {
+                *text = theme.glyph_text(cell_sprite.shown);
+            }
+        }
+    }
 }
 
+//puts a new piece for the player at the top of the board
 pub fn spawn_new_block(
-    commands: &mut Commands,
-    board_data: &mut ResMut<BoardData>,
+    board_data: &mut BoardData,
     piece_type: PieceType,
-    asset_server: &Res<AssetServer>,
-    highest_block_id: &mut ResMut<BlockId>,
+    highest_block_id: &mut BlockId,
 ) {
-    Piece::new(
+    let new_piece_id: u64 = highest_block_id.highest_block_id + 1;
+    highest_block_id.highest_block_id = new_piece_id;
+
+    let spawn_coord_base = piece_type.return_spawn_coord_base(board_data.size());
+    let block = BoardCell {
+        id: new_piece_id,
+        color: piece_type.get_block_color(),
+    };
+    board_data.player_cells = piece_type
+        .get_block_coords_delta()
+        .iter()
+        .map(|delta| *delta + spawn_coord_base)
+        .filter(|cell| board_data.put_block(cell, block))
+        .collect();
+    board_data.active_piece = Some(Piece {
         piece_type,
-        commands,
-        asset_server,
-        board_data,
-        highest_block_id,
-    );
+        rotation: PieceRotation::Zero,
+    });
 }
 
-pub fn move_all_pieces(
-    mut board_data: &mut ResMut<BoardData>,
-    mut commands: &mut Commands,
-    mut blocks_query: &mut Query<(
-        Entity,
-        &BlockID,
-        &mut BoardPointCoordinates,
-        &mut Transform,
-        Option<&CurrentPlayerControlled>,
-    )>,
-) -> bool {
-    let hashmap_of_moves = check_all_pieces_move_validity(&board_data, &blocks_query);
-
-    let mut something_moved: bool = false;
-
-    for (entity, id, mut coords, mut transform, player_controlled_block) in blocks_query.iter_mut()
-    {
-        let &id_can_move = hashmap_of_moves.get(&id.id).unwrap();
-        //info!(id_can_move);
-        //info!("{}: block can move = {}", id.id, id_can_move);
-
-        if id_can_move == true {
-            let point = board_data
-                .board_points
-                .get_mut(&coords.coordinates)
-                .unwrap();
-
-            point.is_full = false;
-            point.id = 0;
-            point.entity_in_point = None;
-
-            let new_point = board_data
-                .board_points
-                .get_mut(&IVec2 {
-                    x: coords.coordinates.x,
-                    y: coords.coordinates.y - 1,
-                })
-                .unwrap();
-
-            new_point.is_full = true;
-            new_point.id = id.id;
-            new_point.entity_in_point = Option::from(entity);
-
-            coords.coordinates = IVec2 {
-                x: coords.coordinates.x,
-                y: coords.coordinates.y - 1,
+//true when every block of a new piece has an empty point to spawn into
+pub fn piece_fits_at_spawn(board_data: &BoardData, piece_type: PieceType) -> bool {
+    let spawn_coord_base = piece_type.return_spawn_coord_base(board_data.size());
+    let cells: Vec<IVec2> = piece_type
+        .get_block_coords_delta()
+        .iter()
+        .map(|delta| *delta + spawn_coord_base)
+        .collect();
+    board_data
+        .filled
+        .fits(&PieceMask::from_cells(&cells, board_data.size()))
+}
+
+//moves things down a row when gravity ticks. sticky gravity keeps every piece with room under
+//it falling, naive and cascade gravity only ever move the player's piece. returns true if
+//anything moved
+pub fn gravity_tick(board_data: &mut BoardData, gravity: LineClearGravity) -> bool {
+    match gravity {
+        LineClearGravity::Naive | LineClearGravity::Cascade => {
+            move_piece_horizontal(board_data, IVec2 { x: 0, y: -1 })
+        }
+        LineClearGravity::Sticky => move_all_pieces(board_data),
+    }
+}
+
+//drops every piece with room under it by a row, the player's included. the blocks of a piece
+//fall together. returns true if anything moved
+pub fn move_all_pieces(board_data: &mut BoardData) -> bool {
+    let hashmap_of_moves = check_all_pieces_move_validity(board_data);
+    let player_id = board_data.player_id();
+    let size = board_data.size();
+
+    let mut moves: Vec<(IVec2, IVec2)> = vec![];
+    for y in 0..size.height() {
+        for x in 0..size.width {
+            let cell = IVec2 { x, y };
+            if let Some(block) = board_data.cell(&cell) {
+                if hashmap_of_moves.get(&block.id) == Some(&true) {
+                    moves.push((cell, cell - IVec2::Y));
+                }
+            }
+        }
+    }
+    board_data.move_blocks(&moves);
+
+    if let Some(player_id) = player_id {
+        if hashmap_of_moves.get(&player_id) == Some(&true) {
+            for cell in board_data.player_cells.iter_mut() {
+                cell.y -= 1;
+            }
+        }
+    }
+    !moves.is_empty()
+}
+
+//drops every group of touching blocks with room under it by a row. which piece a block came from
+//doesn't matter, a group is whatever is connected now. returns true if anything moved
+pub fn move_all_groups(board_data: &mut BoardData) -> bool {
+    let groups = connected_groups(board_data);
+    let size = board_data.size();
+    let group_of = |cell: IVec2| board_data.cell_index(&cell).and_then(|index| groups[index]);
+
+    let mut group_can_fall: HashMap<usize, bool> = HashMap::new();
+    for y in 0..size.height() {
+        for x in 0..size.width {
+            let cell = IVec2 { x, y };
+            let group = match group_of(cell) {
+                Some(group) => group,
+                None => continue,
             };
+            let below = cell - IVec2::Y;
+            let room_below = size.contains(below)
+                && (!board_data.is_filled(&below) || group_of(below) == Some(group));
+            let can_fall = group_can_fall.entry(group).or_insert(true);
+            *can_fall = *can_fall && room_below;
+        }
+    }
+
+    let mut moves: Vec<(IVec2, IVec2)> = vec![];
+    for y in 0..size.height() {
+        for x in 0..size.width {
+            let cell = IVec2 { x, y };
+            if let Some(group) = group_of(cell) {
+                if group_can_fall.get(&group) == Some(&true) {
+                    moves.push((cell, cell - IVec2::Y));
+                }
+            }
+        }
+    }
+    board_data.move_blocks(&moves);
+    !moves.is_empty()
+}
 
-            transform.translation = coords.world_position();
+//labels every filled cell with the group of blocks it touches on any side, by cell index
+fn connected_groups(board_data: &BoardData) -> Vec<Option<usize>> {
+    let mut groups: Vec<Option<usize>> = vec![None; board_data.cells.len()];
+    let mut next_group = 0;
+    let size = board_data.size();
+
+    for y in 0..size.height() {
+        for x in 0..size.width {
+            let start = IVec2 { x, y };
+            let start_index = match board_data.cell_index(&start) {
+                Some(index) if board_data.is_filled(&start) && groups[index].is_none() => index,
+                _ => continue,
+            };
 
-            something_moved = true;
-        } else if let Some(player_controlled_block) = player_controlled_block {
-            commands.entity(entity).remove::<CurrentPlayerControlled>();
+            groups[start_index] = Some(next_group);
+            let mut to_visit = vec![start];
+            while let Some(cell) = to_visit.pop() {
+                for direction in [IVec2::X, -IVec2::X, IVec2::Y, -IVec2::Y] {
+                    let neighbour = cell + direction;
+                    if !board_data.is_filled(&neighbour) {
+                        continue;
+                    }
+                    if let Some(index) = board_data.cell_index(&neighbour) {
+                        if groups[index].is_none() {
+                            groups[index] = Some(next_group);
+                            to_visit.push(neighbour);
+                        }
+                    }
+                }
+            }
+            next_group += 1;
         }
     }
-    something_moved
+    groups
 }
 
-fn check_all_pieces_move_validity(
-    board_data: &ResMut<BoardData>,
-    blocks_query: &Query<(
-        Entity,
-        &BlockID,
-        &mut BoardPointCoordinates,
-        &mut Transform,
-        Option<&CurrentPlayerControlled>,
-    )>,
-) -> HashMap<u64, bool> {
+fn check_all_pieces_move_validity(board_data: &BoardData) -> HashMap<u64, bool> {
     let mut hashmap = HashMap::new();
+    let size = board_data.size();
 
-    for (_entity, id, coords, _transform, _player_controlled_block) in blocks_query.iter() {
-        //info!("loop run");
-        if let Some(point) = board_data.board_points.get(&IVec2 {
-            x: coords.coordinates.x,
-            y: coords.coordinates.y - 1,
-        }) {
-            //info!(point.is_full, point.id);
-            if point.is_full && point.id > 0 && point.id != id.id {
-                hashmap.insert(id.id, false);
-            } else if hashmap.contains_key(&id.id) == false {
-                hashmap.insert(id.id, true);
-            } else {
+    for y in 0..size.height() {
+        for x in 0..size.width {
+            let block = match board_data.cell(&IVec2 { x, y }) {
+                Some(block) => block,
+                None => continue,
+            };
+            if !board_data.point_free_for(&IVec2 { x, y: y - 1 }, block.id) {
+                hashmap.insert(block.id, false);
+            } else if !hashmap.contains_key(&block.id) {
+                hashmap.insert(block.id, true);
             }
-        } else {
-            hashmap.insert(id.id, false);
         }
     }
     hashmap
 }
 
-pub fn move_piece_horizontal(
-    mut board_data: &mut ResMut<'_, BoardData>,
-    mut commands: &Commands,
-    mut blocks_query: &mut Query<
-        (Entity, &BlockID, &mut BoardPointCoordinates, &mut Transform),
-        With<CurrentPlayerControlled>,
-    >,
-    direction: IVec2,
-) {
-    let move_valid = check_individual_piece_move(board_data, blocks_query, direction);
-    for (entity, id, mut coords, mut transform) in blocks_query.iter_mut() {
-        if move_valid == true {
-            let point = board_data
-                .board_points
-                .get_mut(&IVec2 {
-                    x: coords.coordinates.x,
-                    y: coords.coordinates.y,
-                })
-                .unwrap();
-
-            point.is_full = false;
-            point.id = 0;
-            point.entity_in_point = None;
-
-            let new_point = board_data
-                .board_points
-                .get_mut(&IVec2 {
-                    x: coords.coordinates.x + direction.x,
-                    y: coords.coordinates.y + direction.y,
-                })
-                .unwrap();
-
-            new_point.is_full = true;
-            new_point.id = id.id;
-            new_point.entity_in_point = Option::from(entity);
-
-            *coords = BoardPointCoordinates {
-                coordinates: IVec2 {
-                    x: coords.coordinates.x + direction.x,
-                    y: coords.coordinates.y + direction.y,
-                },
-            };
-            transform.translation = coords.world_position();
-        }
+//returns true if the piece moved
+pub fn move_piece_horizontal(board_data: &mut BoardData, direction: IVec2) -> bool {
+    let move_valid = check_individual_piece_move(board_data, direction);
+    if move_valid {
+        let new_cells: Vec<IVec2> = board_data
+            .player_cells
+            .iter()
+            .map(|cell| *cell + direction)
+            .collect();
+        move_player_blocks(board_data, &new_cells);
     }
+    move_valid && !board_data.player_cells.is_empty()
+}
+
+//true when the player's piece is resting on the floor or another block
+pub fn player_piece_grounded(board_data: &BoardData) -> bool {
+    !board_data.player_cells.is_empty()
+        && !check_individual_piece_move(board_data, IVec2 { x: 0, y: -1 })
+}
+
+//locks the player's piece in place so it becomes part of the board
+pub fn lock_player_piece(board_data: &mut BoardData) {
+    board_data.player_cells.clear();
+    board_data.active_piece = None;
+}
+
+//true when nothing is above the player's piece, so it could have been dropped straight there
+pub fn player_piece_clear_above(board_data: &BoardData) -> bool {
+    let piece_id = match board_data.player_id() {
+        Some(piece_id) => piece_id,
+        None => return true,
+    };
+    board_data.player_cells.iter().all(|cell| {
+        (cell.y + 1..board_data.size().height())
+            .all(|y| board_data.point_free_for(&IVec2 { x: cell.x, y }, piece_id))
+    })
+}
+
+pub fn player_piece_cells(board_data: &BoardData) -> Vec<IVec2> {
+    board_data.player_cells.clone()
 }
 
-fn check_individual_piece_move(
-    board_data: &ResMut<BoardData>,
-    blocks_query: &mut Query<
-        (Entity, &BlockID, &mut BoardPointCoordinates, &mut Transform),
-        With<CurrentPlayerControlled>,
-    >,
-    direction: IVec2,
+//turns the player's piece using the srs kicks. returns the spin the piece would lock with
+//where it ended up, or none if it couldn't turn
+pub fn rotate_player_piece(
+    board_data: &mut BoardData,
+    direction: RotationDirection,
+) -> Option<Spin> {
+    let piece = board_data.active_piece?;
+    let piece_id = board_data.player_id()?;
+
+    let origin = piece
+        .piece_type
+        .origin_of(piece.rotation, &board_data.player_cells);
+    let new_rotation = piece.rotation.rotated(direction);
+    let fits = |cells: &[IVec2]| board_data.piece_fits(cells, piece_id);
+    let (kick_index, new_origin) =
+        kicked_rotation(piece.piece_type, piece.rotation, new_rotation, origin, fits)?;
+    let spin = move_gen::spin_after_rotation(
+        piece.piece_type,
+        new_origin,
+        new_rotation,
+        kick_index,
+        &fits,
+    );
+
+    move_player_blocks(
+        board_data,
+        &piece.piece_type.cells(new_rotation, new_origin),
+    );
+    board_data.active_piece = Some(Piece {
+        rotation: new_rotation,
+        ..piece
+    });
+    Some(spin)
+}
+
+//moves the player's piece straight to the cells, turned to the rotation. returns false and
+//leaves it where it was if the cells aren't free
+pub fn place_player_piece(
+    board_data: &mut BoardData,
+    rotation: PieceRotation,
+    cells: &[IVec2],
 ) -> bool {
-    let mut move_valid: bool = true;
-
-    for (_entity, id, coords, _transform) in blocks_query.iter() {
-        if let Some(point) = board_data.board_points.get(&IVec2 {
-            x: coords.coordinates.x + direction.x,
-            y: coords.coordinates.y + direction.y,
-        }) {
-            if point.is_full && point.id > 0 && point.id != id.id {
-                move_valid = false;
-            } else if move_valid == false {
-            } else {
-                move_valid = true;
+    let piece = match board_data.active_piece {
+        Some(piece) => piece,
+        None => return false,
+    };
+    let piece_id = match board_data.player_id() {
+        Some(piece_id) => piece_id,
+        None => return false,
+    };
+    if cells.len() != board_data.player_cells.len() || !board_data.piece_fits(cells, piece_id) {
+        return false;
+    }
+
+    move_player_blocks(board_data, cells);
+    board_data.active_piece = Some(Piece { rotation, ..piece });
+    true
+}
+
+//drops the player's piece as far as it goes and returns how many rows it fell
+pub fn drop_player_piece(board_data: &mut BoardData) -> u32 {
+    let mut rows_dropped = 0;
+    while move_piece_horizontal(board_data, IVec2 { x: 0, y: -1 }) {
+        rows_dropped += 1;
+    }
+    rows_dropped
+}
+
+//takes the player's piece off the board, used when it's swapped into hold
+pub fn remove_player_piece(board_data: &mut BoardData) {
+    for cell in std::mem::take(&mut board_data.player_cells) {
+        board_data.take_block(&cell);
+    }
+    board_data.active_piece = None;
+}
+
+fn move_player_blocks(board_data: &mut BoardData, new_cells: &[IVec2]) {
+    let moves: Vec<(IVec2, IVec2)> = board_data
+        .player_cells
+        .iter()
+        .zip(new_cells.iter())
+        .map(|(cell, new_cell)| (*cell, *new_cell))
+        .collect();
+    board_data.move_blocks(&moves);
+    board_data.player_cells = new_cells.to_vec();
+}
+
+fn check_individual_piece_move(board_data: &BoardData, direction: IVec2) -> bool {
+    let piece_id = match board_data.player_id() {
+        Some(piece_id) => piece_id,
+        None => return true,
+    };
+    let cells: Vec<IVec2> = board_data
+        .player_cells
+        .iter()
+        .map(|cell| *cell + direction)
+        .collect();
+    board_data.piece_fits(&cells, piece_id)
+}
+
+//marks the full rows to be cleared once the line clear delay runs out, returning how many there
+//are. check_each_row takes them out
+pub fn begin_row_clear(board_data: &mut BoardData, line_clear_delay: u32) -> u32 {
+    let rows: Vec<i32> = (0..board_data.size().height())
+        .filter(|y| board_data.filled.row_full(*y))
+        .collect();
+    let rows_full = rows.len() as u32;
+    board_data.row_clear = if rows.is_empty() {
+        None
+    } else {
+        Some(RowClear {
+            rows,
+            frames: line_clear_delay,
+            frames_left: line_clear_delay,
+        })
+    };
+    rows_full
+}
+
+//counts a frame of the line clear delay off. returns true once it has run out, or if nothing was
+//waiting to be cleared
+pub fn row_clear_done(board_data: &mut BoardData) -> bool {
+    match &mut board_data.row_clear {
+        Some(row_clear) => {
+            row_clear.frames_left = row_clear.frames_left.saturating_sub(1);
+            row_clear.frames_left == 0
+        }
+        None => true,
+    }
+}
+
+//clears every full row and returns how many there were. naive gravity moves the rows above
+//down into the gap straight away, sticky and cascade gravity leave them for move_all_pieces or
+//move_all_groups to drop
+pub fn check_each_row(
+    score_event: &mut EventWriter<ScoreEvent>,
+    board_data: &mut BoardData,
+    gravity: LineClearGravity,
+) -> u32 {
+    let mut rows_deleted: u32 = 0;
+    let mut moves: Vec<(IVec2, IVec2)> = vec![];
+    let size = board_data.size();
+
+    for y in 0..size.height() {
+        if board_data.filled.row_full(y) {
+            rows_deleted += 1;
+            for x in 0..size.width {
+                board_data.take_block(&IVec2 { x, y });
+            }
+        } else if rows_deleted > 0 && gravity == LineClearGravity::Naive {
+            for x in 0..size.width {
+                let cell = IVec2 { x, y };
+                if board_data.is_filled(&cell) {
+                    moves.push((cell, cell - IVec2::Y * rows_deleted as i32));
+                }
             }
-        } else {
-            move_valid = false;
         }
     }
-    move_valid
+    board_data.move_blocks(&moves);
+    board_data.row_clear = None;
+    score_event.send(ScoreEvent {
+        score: rows_deleted as u64,
+    });
+    rows_deleted
 }
 
-pub fn update_board_data(
-    mut board_data:ResMut<BoardData>,
-    mut blocks_query: Query<(
-        Entity,
-        &BlockID,
-        &mut BoardPointCoordinates,
-        &mut Transform,
-        Option<&CurrentPlayerControlled>,
-    )>,
-) {
-    for (entity, id, mut coords, mut _transform, _player_controlled_block) in
-        blocks_query.iter_mut()
-    {
-        let new_point = board_data
-            .board_points
-            .get_mut(&coords.coordinates)
-            .unwrap();
+//a block on the board as it's written into a save file
+#[derive(Serialize, Deserialize)]
+pub struct SavedBlock {
+    x: i32,
+    y: i32,
+    color: PieceColor,
+    id: u64,
+    player_controlled: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SavedBoard {
+    blocks: Vec<SavedBlock>,
+    active_piece: Option<Piece>,
+    highest_block_id: u64,
+    comment: String,
+    row_clear: Option<RowClear>,
+    size: BoardSize,
+}
 
-        new_point.is_full = true;
-        new_point.id = id.id;
-        new_point.entity_in_point = Option::from(entity);
+impl SavedBoard {
+    pub fn size(&self) -> BoardSize {
+        self.size
     }
 }
 
-pub fn check_each_row(
-    mut score_event: &mut EventWriter<ScoreEvent>,
-    mut board_data: &mut ResMut<BoardData>,
-    commands: &mut Commands,
+pub fn save_board(world: &World) -> SavedBoard {
+    let board_data = world.resource::<BoardData>();
+    let size = board_data.size();
+    let mut blocks = vec![];
+    for y in 0..size.height() {
+        for x in 0..size.width {
+            let cell = IVec2 { x, y };
+            if let Some(block) = board_data.cell(&cell) {
+                blocks.push(SavedBlock {
+                    x,
+                    y,
+                    color: block.color,
+                    id: block.id,
+                    player_controlled: board_data.player_cells.contains(&cell),
+                });
+            }
+        }
+    }
+
+    SavedBoard {
+        blocks,
+        active_piece: board_data.active_piece,
+        highest_block_id: world.resource::<BlockId>().highest_block_id,
+        comment: board_data.comment.clone(),
+        row_clear: board_data.row_clear.clone(),
+        size,
+    }
+}
+
+//puts a saved board back, the same size it was saved at
+pub fn restore_board(world: &mut World, saved_board: &SavedBoard) {
+    let mut board_data = BoardData::new(saved_board.size);
+    for block in &saved_board.blocks {
+        let coordinates = IVec2 {
+            x: block.x,
+            y: block.y,
+        };
+        let saved_block = BoardCell {
+            id: block.id,
+            color: block.color,
+        };
+        if !board_data.put_block(&coordinates, saved_block) {
+            warn!("saved block at {} is off the board", coordinates);
+            continue;
+        }
+        if block.player_controlled {
+            board_data.player_cells.push(coordinates);
+        }
+    }
+
+    board_data.active_piece = saved_board.active_piece;
+    board_data.comment = saved_board.comment.clone();
+    board_data.row_clear = saved_board.row_clear.clone();
+    world.insert_resource(board_data);
+    world.insert_resource(BlockId {
+        highest_block_id: saved_board.highest_block_id,
+    });
+}
+
+//empties the board when a game is reset, making it the size the game settings ask for
+pub fn clear_board(world: &mut World) {
+    let size = world.resource::<GameSettings>().board_size;
+    world.insert_resource(BoardData::new(size));
+    world.insert_resource(BlockId {
+        highest_block_id: 1,
+    });
+}
+
+//replaces everything on the board with the field and piece from a fumen page.
+//returns true if the page had a piece, which becomes the player controlled piece
+pub fn load_fumen_page(
+    page: &FumenPage,
+    board_data: &mut BoardData,
+    highest_block_id: &mut BlockId,
 ) -> bool {
-    
-    let mut row_deleted: bool = false;
-    let mut rows_deleted: i32 = 0;
-    
-    for y in 0..BOARD_HEIGHT {
-        let mut blocks_in_row_count: i32 = 0;
-
-        for x in 0..BOARD_WIDTH {
-            if let Some(point) = board_data.board_points.get(&IVec2 { x, y }) {
-                if point.is_full {
-                    blocks_in_row_count += 1;
-                }
+    board_data.clear_points();
+
+    //the whole field shares one id so it stays where it was placed
+    let field_id = highest_block_id.highest_block_id + 1;
+    highest_block_id.highest_block_id = field_id;
+
+    let size = board_data.size();
+    for y in 0..size.height() {
+        for x in 0..size.width {
+            if let Some(color) = PieceColor::from_fumen_block(page.field.get(x, y)) {
+                board_data.put_block(
+                    &IVec2 { x, y },
+                    BoardCell {
+                        id: field_id,
+                        color,
+                    },
+                );
             }
         }
+    }
 
-        if blocks_in_row_count == BOARD_WIDTH {
-            row_deleted = true;
-            rows_deleted += 1;
-            for i in 0..BOARD_WIDTH {
-                if let Some(point) = board_data.board_points.get_mut(&IVec2 { x: i, y }) {
-                    point.is_full = false;
-                    point.id = 0;
+    board_data.comment = page.comment.clone();
 
-                    commands.entity(point.entity_in_point.unwrap()).despawn();
+    let piece = match page.piece {
+        Some(piece) => piece,
+        None => return false,
+    };
+    let color = match PieceColor::from_fumen_block(piece.block) {
+        Some(color) => color,
+        None => return false,
+    };
 
-                    point.entity_in_point = None;
+    let piece_id = highest_block_id.highest_block_id + 1;
+    highest_block_id.highest_block_id = piece_id;
 
-                }
+    board_data.active_piece = PieceType::from_fumen_block(piece.block).map(|piece_type| Piece {
+        piece_type,
+        rotation: PieceRotation::from_fumen_rotation(piece.rotation),
+    });
+    let block = BoardCell {
+        id: piece_id,
+        color,
+    };
+    board_data.player_cells = piece
+        .cells()
+        .into_iter()
+        .filter(|cell| board_data.put_block(cell, block))
+        .collect();
+    true
+}
+
+//pushes every block up and fills the bottom rows with gray blocks, leaving one column of each
+//row empty. hole_columns has a column for each row, top row first. returns false if blocks
+//were pushed off the top of the board
+pub fn add_garbage_rows(
+    board_data: &mut BoardData,
+    highest_block_id: &mut BlockId,
+    hole_columns: &[i32],
+) -> bool {
+    let rows = hole_columns.len() as i32;
+    let shift = IVec2 { x: 0, y: rows };
+
+    let size = board_data.size();
+    let mut fits = true;
+    let mut moves: Vec<(IVec2, IVec2)> = vec![];
+    for y in 0..size.height() {
+        for x in 0..size.width {
+            let cell = IVec2 { x, y };
+            if !board_data.is_filled(&cell) {
+                continue;
+            }
+            if size.contains(cell + shift) {
+                moves.push((cell, cell + shift));
+            } else {
+                board_data.take_block(&cell);
+                fits = false;
             }
         }
     }
-    score_event.send(ScoreEvent {score: rows_deleted as u64});
-    row_deleted
+    board_data.move_blocks(&moves);
+    board_data.player_cells = board_data
+        .player_cells
+        .iter()
+        .map(|cell| *cell + shift)
+        .filter(|cell| size.contains(*cell))
+        .collect();
+
+    //the garbage shares one id so it stays where it was put
+    let garbage_id = highest_block_id.highest_block_id + 1;
+    highest_block_id.highest_block_id = garbage_id;
+    let garbage = BoardCell {
+        id: garbage_id,
+        color: PieceColor::Gray,
+    };
+    for (row, hole_column) in hole_columns.iter().enumerate() {
+        let y = rows - 1 - row as i32;
+        for x in (0..size.width).filter(|x| x != hole_column) {
+            board_data.put_block(&IVec2 { x, y }, garbage);
+        }
+    }
+    fits
+}
+
+//builds a fumen page from the blocks on the board. the player controlled blocks become the page piece.
+//a fumen field is 10 wide and 23 high, so a wider board or a block above that has no page
+pub fn board_to_fumen_page(board_data: &BoardData) -> Option<FumenPage> {
+    let mut field = FumenField::default();
+    let mut piece_block = FumenBlock::Empty;
+    let mut piece_cells: Vec<IVec2> = vec![];
+
+    let size = board_data.size();
+    if size.width > FUMEN_FIELD_WIDTH {
+        warn!(
+            "can't make a fumen of a board {} wide, fumen is {} wide",
+            size.width, FUMEN_FIELD_WIDTH
+        );
+        return None;
+    }
+    for y in 0..size.height() {
+        for x in 0..size.width {
+            let cell = IVec2 { x, y };
+            let color = match board_data.cell(&cell) {
+                Some(block) => block.color,
+                None => continue,
+            };
+            if y >= FUMEN_FIELD_TOP {
+                warn!(
+                    "can't make a fumen of a board with blocks in row {}, fumen is {} high",
+                    y + 1,
+                    FUMEN_FIELD_TOP
+                );
+                return None;
+            }
+            if board_data.player_cells.contains(&cell) {
+                piece_block = color.fumen_block();
+                piece_cells.push(cell);
+            } else {
+                field.set(x, y, color.fumen_block());
+            }
+        }
+    }
+
+    let piece = FumenPiece::from_cells(piece_block, &piece_cells);
+    if piece.is_none() {
+        //a piece fumen can't describe still belongs on the board, so keep it in the field
+        for cell in piece_cells {
+            field.set(cell.x, cell.y, piece_block);
+        }
+    }
+
+    Some(FumenPage {
+        field,
+        piece,
+        comment: board_data.comment.clone(),
+        ..default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(width: i32, visible_height: i32, buffer_height: i32) -> BoardSize {
+        BoardSize {
+            width,
+            visible_height,
+            buffer_height,
+        }
+    }
+
+    #[test]
+    fn board_sizes_are_validated() {
+        assert_eq!(BoardSize::default().validate(), Ok(()));
+        assert_eq!(size(4, 4, 3).validate(), Ok(()));
+        assert_eq!(size(16, 200, 200).validate(), Ok(()));
+        for bad_size in [
+            size(17, 20, 10),
+            size(3, 20, 10),
+            size(-10, 20, 10),
+            size(10, 3, 10),
+            size(10, 201, 10),
+            size(10, 20, 2),
+            size(10, 20, i32::MAX),
+        ] {
+            assert_eq!(bad_size.validate(), Err(InvalidBoardSize(bad_size)));
+        }
+    }
+
+    #[test]
+    fn bad_board_size_arguments_fall_back_to_the_usual_board() {
+        let arguments = |values: &[&str]| -> Vec<String> {
+            values.iter().map(|value| value.to_string()).collect()
+        };
+        assert_eq!(
+            BoardSize::from_arguments(&arguments(&["--board-width", "16", "--board-height", "8"])),
+            size(16, 8, 10)
+        );
+        assert_eq!(
+            BoardSize::from_arguments(&arguments(&["--board-width", "17"])),
+            BoardSize::default()
+        );
+        assert_eq!(
+            BoardSize::from_arguments(&arguments(&["--board-width", "wide"])),
+            BoardSize::default()
+        );
+    }
 }
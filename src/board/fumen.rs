@@ -0,0 +1,840 @@
+use bevy::prelude::*;
+use std::fmt;
+
+//fumen (v115) encoding and decoding so setups can be shared with the rest of the community
+//
+//a fumen field is 10 wide and 23 tall plus a hidden garbage row underneath it (y = -1).
+//cells are stored top row first, the same order the format writes them in
+
+pub const FUMEN_FIELD_WIDTH: i32 = 10;
+pub const FUMEN_FIELD_TOP: i32 = 23;
+const FUMEN_FIELD_BLOCKS: usize = ((FUMEN_FIELD_TOP + 1) * FUMEN_FIELD_WIDTH) as usize;
+
+const ENCODE_TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const ENCODE_TABLE_LENGTH: u32 = 64;
+
+const COMMENT_TABLE: &[u8] =
+    b" !\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~";
+const COMMENT_CHAR_RANGE: u32 = COMMENT_TABLE.len() as u32 + 1;
+const COMMENT_MAX_LENGTH: usize = 4095;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FumenError {
+    MissingPrefix,
+    UnsupportedVersion(String),
+    InvalidCharacter(char),
+    UnexpectedEnd,
+    InvalidValue,
+}
+
+impl fmt::Display for FumenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FumenError::MissingPrefix => write!(f, "not a fumen string"),
+            FumenError::UnsupportedVersion(version) => {
+                write!(f, "unsupported fumen version {}", version)
+            }
+            FumenError::InvalidCharacter(character) => {
+                write!(f, "invalid character '{}' in fumen data", character)
+            }
+            FumenError::UnexpectedEnd => write!(f, "fumen data ended early"),
+            FumenError::InvalidValue => write!(f, "fumen data contains an invalid value"),
+        }
+    }
+}
+
+//block values in the order fumen numbers them
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FumenBlock {
+    Empty,
+    I,
+    L,
+    O,
+    Z,
+    T,
+    J,
+    S,
+    Gray,
+}
+
+impl FumenBlock {
+    fn from_value(value: u32) -> Result<FumenBlock, FumenError> {
+        match value {
+            0 => Ok(FumenBlock::Empty),
+            1 => Ok(FumenBlock::I),
+            2 => Ok(FumenBlock::L),
+            3 => Ok(FumenBlock::O),
+            4 => Ok(FumenBlock::Z),
+            5 => Ok(FumenBlock::T),
+            6 => Ok(FumenBlock::J),
+            7 => Ok(FumenBlock::S),
+            8 => Ok(FumenBlock::Gray),
+            _ => Err(FumenError::InvalidValue),
+        }
+    }
+
+    fn value(&self) -> u32 {
+        *self as u32
+    }
+
+    pub fn is_tetromino(&self) -> bool {
+        !matches!(self, FumenBlock::Empty | FumenBlock::Gray)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FumenRotation {
+    Spawn,
+    Right,
+    Reverse,
+    Left,
+}
+
+impl FumenRotation {
+    const ALL: [FumenRotation; 4] = [
+        FumenRotation::Spawn,
+        FumenRotation::Right,
+        FumenRotation::Reverse,
+        FumenRotation::Left,
+    ];
+
+    fn from_value(value: u32) -> FumenRotation {
+        match value {
+            0 => FumenRotation::Reverse,
+            1 => FumenRotation::Right,
+            2 => FumenRotation::Spawn,
+            _ => FumenRotation::Left,
+        }
+    }
+
+    fn value(&self) -> u32 {
+        match self {
+            FumenRotation::Reverse => 0,
+            FumenRotation::Right => 1,
+            FumenRotation::Spawn => 2,
+            FumenRotation::Left => 3,
+        }
+    }
+
+    //rotates an offset from the spawn orientation into this orientation
+    fn rotate(&self, offset: IVec2) -> IVec2 {
+        match self {
+            FumenRotation::Spawn => offset,
+            FumenRotation::Right => IVec2::new(offset.y, -offset.x),
+            FumenRotation::Reverse => IVec2::new(-offset.x, -offset.y),
+            FumenRotation::Left => IVec2::new(-offset.y, offset.x),
+        }
+    }
+}
+
+//a piece on a page. x and y are the rotation center used by the shapes below
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FumenPiece {
+    pub block: FumenBlock,
+    pub rotation: FumenRotation,
+    pub x: i32,
+    pub y: i32,
+}
+
+impl FumenPiece {
+    fn spawn_offsets(block: FumenBlock) -> [IVec2; 4] {
+        let offsets = match block {
+            FumenBlock::I => [[0, 0], [-1, 0], [1, 0], [2, 0]],
+            FumenBlock::L => [[0, 0], [-1, 0], [1, 0], [1, 1]],
+            FumenBlock::O => [[0, 0], [1, 0], [0, 1], [1, 1]],
+            FumenBlock::Z => [[0, 0], [1, 0], [0, 1], [-1, 1]],
+            FumenBlock::T => [[0, 0], [-1, 0], [1, 0], [0, 1]],
+            FumenBlock::J => [[0, 0], [-1, 0], [1, 0], [-1, 1]],
+            FumenBlock::S => [[0, 0], [-1, 0], [0, 1], [1, 1]],
+            FumenBlock::Empty | FumenBlock::Gray => [[0, 0]; 4],
+        };
+        offsets.map(|[x, y]| IVec2::new(x, y))
+    }
+
+    //the four field coordinates the piece covers
+    pub fn cells(&self) -> [IVec2; 4] {
+        FumenPiece::spawn_offsets(self.block)
+            .map(|offset| self.rotation.rotate(offset) + IVec2::new(self.x, self.y))
+    }
+
+    //finds the piece placement that covers exactly the given cells, if there is one
+    pub fn from_cells(block: FumenBlock, cells: &[IVec2]) -> Option<FumenPiece> {
+        if !block.is_tetromino() || cells.len() != 4 {
+            return None;
+        }
+
+        for rotation in FumenRotation::ALL {
+            for offset in FumenPiece::spawn_offsets(block) {
+                let center = cells[0] - rotation.rotate(offset);
+                let piece = FumenPiece {
+                    block,
+                    rotation,
+                    x: center.x,
+                    y: center.y,
+                };
+                let piece_cells = piece.cells();
+                if cells.iter().all(|cell| piece_cells.contains(cell)) {
+                    return Some(piece);
+                }
+            }
+        }
+        None
+    }
+
+    //fumen stores some pieces around a different center than the shapes above use
+    fn decode_coordinate(value: u32, block: FumenBlock, rotation: FumenRotation) -> IVec2 {
+        let mut x = (value % FUMEN_FIELD_WIDTH as u32) as i32;
+        let mut y = FUMEN_FIELD_TOP - (value / FUMEN_FIELD_WIDTH as u32) as i32 - 1;
+
+        match (block, rotation) {
+            (FumenBlock::O, FumenRotation::Left) => {
+                x += 1;
+                y -= 1;
+            }
+            (FumenBlock::O, FumenRotation::Reverse) => x += 1,
+            (FumenBlock::O, FumenRotation::Spawn) => y -= 1,
+            (FumenBlock::I, FumenRotation::Reverse) => x += 1,
+            (FumenBlock::I, FumenRotation::Left) => y -= 1,
+            (FumenBlock::S, FumenRotation::Spawn) => y -= 1,
+            (FumenBlock::S, FumenRotation::Right) => x -= 1,
+            (FumenBlock::Z, FumenRotation::Spawn) => y -= 1,
+            (FumenBlock::Z, FumenRotation::Left) => x += 1,
+            _ => {}
+        }
+
+        IVec2::new(x, y)
+    }
+
+    fn encode_coordinate(&self) -> u32 {
+        let mut x = self.x;
+        let mut y = self.y;
+
+        match (self.block, self.rotation) {
+            (FumenBlock::O, FumenRotation::Left) => {
+                x -= 1;
+                y += 1;
+            }
+            (FumenBlock::O, FumenRotation::Reverse) => x -= 1,
+            (FumenBlock::O, FumenRotation::Spawn) => y += 1,
+            (FumenBlock::I, FumenRotation::Reverse) => x -= 1,
+            (FumenBlock::I, FumenRotation::Left) => y += 1,
+            (FumenBlock::S, FumenRotation::Spawn) => y += 1,
+            (FumenBlock::S, FumenRotation::Right) => x += 1,
+            (FumenBlock::Z, FumenRotation::Spawn) => y += 1,
+            (FumenBlock::Z, FumenRotation::Left) => x -= 1,
+            _ => {}
+        }
+
+        ((FUMEN_FIELD_TOP - y - 1) * FUMEN_FIELD_WIDTH + x) as u32
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FumenField {
+    cells: [FumenBlock; FUMEN_FIELD_BLOCKS],
+}
+
+impl Default for FumenField {
+    fn default() -> Self {
+        FumenField {
+            cells: [FumenBlock::Empty; FUMEN_FIELD_BLOCKS],
+        }
+    }
+}
+
+impl FumenField {
+    fn index(x: i32, y: i32) -> Option<usize> {
+        if (0..FUMEN_FIELD_WIDTH).contains(&x) && (-1..FUMEN_FIELD_TOP).contains(&y) {
+            Some(((FUMEN_FIELD_TOP - y - 1) * FUMEN_FIELD_WIDTH + x) as usize)
+        } else {
+            None
+        }
+    }
+
+    //y = -1 is the garbage row under the field
+    pub fn get(&self, x: i32, y: i32) -> FumenBlock {
+        match FumenField::index(x, y) {
+            Some(index) => self.cells[index],
+            None => FumenBlock::Empty,
+        }
+    }
+
+    pub fn set(&mut self, x: i32, y: i32, block: FumenBlock) {
+        if let Some(index) = FumenField::index(x, y) {
+            self.cells[index] = block;
+        }
+    }
+
+    fn put_piece(&mut self, piece: &FumenPiece) {
+        for cell in piece.cells() {
+            self.set(cell.x, cell.y, piece.block);
+        }
+    }
+
+    //removes full rows from the field, the garbage row is never cleared
+    fn clear_lines(&mut self) {
+        let mut rows: Vec<[FumenBlock; FUMEN_FIELD_WIDTH as usize]> = vec![];
+        for y in 0..FUMEN_FIELD_TOP {
+            let row = self.row(y);
+            if row.contains(&FumenBlock::Empty) {
+                rows.push(row);
+            }
+        }
+        for y in 0..FUMEN_FIELD_TOP {
+            let row = rows
+                .get(y as usize)
+                .copied()
+                .unwrap_or([FumenBlock::Empty; FUMEN_FIELD_WIDTH as usize]);
+            self.set_row(y, row);
+        }
+    }
+
+    //pushes the garbage row up into the field
+    fn rise(&mut self) {
+        for y in (0..FUMEN_FIELD_TOP).rev() {
+            let row = self.row(y - 1);
+            self.set_row(y, row);
+        }
+        self.set_row(-1, [FumenBlock::Empty; FUMEN_FIELD_WIDTH as usize]);
+    }
+
+    fn mirror(&mut self) {
+        for y in 0..FUMEN_FIELD_TOP {
+            let mut row = self.row(y);
+            row.reverse();
+            self.set_row(y, row);
+        }
+    }
+
+    fn row(&self, y: i32) -> [FumenBlock; FUMEN_FIELD_WIDTH as usize] {
+        let mut row = [FumenBlock::Empty; FUMEN_FIELD_WIDTH as usize];
+        for (x, block) in row.iter_mut().enumerate() {
+            *block = self.get(x as i32, y);
+        }
+        row
+    }
+
+    fn set_row(&mut self, y: i32, row: [FumenBlock; FUMEN_FIELD_WIDTH as usize]) {
+        for (x, block) in row.iter().enumerate() {
+            self.set(x as i32, y, *block);
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FumenFlags {
+    //place the piece into the field (and clear lines) for the next page
+    pub lock: bool,
+    //push the garbage row up after locking
+    pub rise: bool,
+    //mirror the field after locking
+    pub mirror: bool,
+    //only read from the first page
+    pub colorize: bool,
+}
+
+impl Default for FumenFlags {
+    fn default() -> Self {
+        FumenFlags {
+            lock: true,
+            rise: false,
+            mirror: false,
+            colorize: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FumenPage {
+    pub field: FumenField,
+    pub piece: Option<FumenPiece>,
+    pub comment: String,
+    pub flags: FumenFlags,
+}
+
+impl FumenPage {
+    //the field the next page starts from
+    fn next_field(&self) -> FumenField {
+        let mut field = self.field.clone();
+        if self.flags.lock {
+            if let Some(piece) = &self.piece {
+                field.put_piece(piece);
+            }
+            field.clear_lines();
+            if self.flags.rise {
+                field.rise();
+            }
+            if self.flags.mirror {
+                field.mirror();
+            }
+        }
+        field
+    }
+}
+
+struct FumenReader {
+    values: Vec<u32>,
+    position: usize,
+}
+
+impl FumenReader {
+    fn new(data: &str) -> Result<FumenReader, FumenError> {
+        let mut values = vec![];
+        for character in data.chars().filter(|character| *character != '?') {
+            match ENCODE_TABLE.iter().position(|c| *c as char == character) {
+                Some(value) => values.push(value as u32),
+                None => return Err(FumenError::InvalidCharacter(character)),
+            }
+        }
+        Ok(FumenReader {
+            values,
+            position: 0,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.values.len()
+    }
+
+    fn poll(&mut self, count: usize) -> Result<u32, FumenError> {
+        let mut value = 0;
+        for i in 0..count {
+            let digit = *self
+                .values
+                .get(self.position)
+                .ok_or(FumenError::UnexpectedEnd)?;
+            value += digit * ENCODE_TABLE_LENGTH.pow(i as u32);
+            self.position += 1;
+        }
+        Ok(value)
+    }
+}
+
+#[derive(Default)]
+struct FumenWriter {
+    values: Vec<u32>,
+}
+
+impl FumenWriter {
+    fn push(&mut self, mut value: u32, count: usize) {
+        for _ in 0..count {
+            self.values.push(value % ENCODE_TABLE_LENGTH);
+            value /= ENCODE_TABLE_LENGTH;
+        }
+    }
+
+    fn finish(self) -> String {
+        let data: String = self
+            .values
+            .iter()
+            .map(|value| ENCODE_TABLE[*value as usize] as char)
+            .collect();
+
+        //fumen breaks long strings with a '?' so they wrap nicely when pasted
+        if data.len() < 42 {
+            return data;
+        }
+        let (head, tail) = data.split_at(42);
+        let mut chunks = vec![head];
+        let mut rest = tail;
+        while !rest.is_empty() {
+            let (chunk, remaining) = rest.split_at(rest.len().min(47));
+            chunks.push(chunk);
+            rest = remaining;
+        }
+        chunks.join("?")
+    }
+}
+
+//decodes every page of a fumen string like "v115@vhAAgH"
+pub fn decode(fumen: &str) -> Result<Vec<FumenPage>, FumenError> {
+    let fumen = fumen.trim();
+    let fumen = fumen.rsplit("fumen.zui.jp/?").next().unwrap_or(fumen);
+    let (version, data) = fumen.split_once('@').ok_or(FumenError::MissingPrefix)?;
+    match version {
+        "v115" | "m115" | "d115" => {}
+        _ => return Err(FumenError::UnsupportedVersion(version.to_string())),
+    }
+
+    let mut reader = FumenReader::new(data)?;
+    let mut pages = vec![];
+    let mut previous_field = FumenField::default();
+    let mut previous_comment = String::new();
+    let mut colorize = true;
+    let mut repeat_count = 0;
+
+    while !reader.is_empty() {
+        let field = if repeat_count > 0 {
+            repeat_count -= 1;
+            previous_field.clone()
+        } else {
+            let (field, changed) = decode_field(&mut reader, &previous_field)?;
+            if !changed {
+                repeat_count = reader.poll(1)?;
+            }
+            field
+        };
+
+        let mut action = reader.poll(3)?;
+        let block = FumenBlock::from_value(action % 8)?;
+        action /= 8;
+        let rotation = FumenRotation::from_value(action % 4);
+        action /= 4;
+        let coordinate = action % FUMEN_FIELD_BLOCKS as u32;
+        action /= FUMEN_FIELD_BLOCKS as u32;
+        let rise = action % 2 == 1;
+        action /= 2;
+        let mirror = action % 2 == 1;
+        action /= 2;
+        let page_colorize = action % 2 == 1;
+        action /= 2;
+        let has_comment = action % 2 == 1;
+        action /= 2;
+        let lock = action % 2 == 0;
+
+        if pages.is_empty() {
+            colorize = page_colorize;
+        }
+
+        if has_comment {
+            previous_comment = decode_comment(&mut reader)?;
+        }
+
+        let piece = if block.is_tetromino() {
+            let center = FumenPiece::decode_coordinate(coordinate, block, rotation);
+            Some(FumenPiece {
+                block,
+                rotation,
+                x: center.x,
+                y: center.y,
+            })
+        } else {
+            None
+        };
+
+        let page = FumenPage {
+            field,
+            piece,
+            comment: previous_comment.clone(),
+            flags: FumenFlags {
+                lock,
+                rise,
+                mirror,
+                colorize,
+            },
+        };
+        previous_field = page.next_field();
+        pages.push(page);
+    }
+
+    Ok(pages)
+}
+
+//encodes the pages into a single v115 fumen string
+pub fn encode(pages: &[FumenPage]) -> String {
+    let mut writer = FumenWriter::default();
+    let mut previous_field = FumenField::default();
+    let mut previous_comment = String::new();
+    let mut last_repeat_index: Option<usize> = None;
+
+    for (page_index, page) in pages.iter().enumerate() {
+        let (field_values, changed) = encode_field(&previous_field, &page.field);
+        match last_repeat_index {
+            _ if changed => {
+                writer.values.extend(field_values);
+                last_repeat_index = None;
+            }
+            Some(index) if writer.values[index] < ENCODE_TABLE_LENGTH - 1 => {
+                writer.values[index] += 1;
+            }
+            _ => {
+                writer.values.extend(field_values);
+                writer.push(0, 1);
+                last_repeat_index = Some(writer.values.len() - 1);
+            }
+        }
+
+        let has_comment = page.comment != previous_comment;
+        let (block, rotation, coordinate) = match &page.piece {
+            Some(piece) => (piece.block, piece.rotation, piece.encode_coordinate()),
+            None => (FumenBlock::Empty, FumenRotation::Reverse, 0),
+        };
+
+        let mut action = !page.flags.lock as u32;
+        action = action * 2 + has_comment as u32;
+        action = action * 2 + (page_index == 0 && page.flags.colorize) as u32;
+        action = action * 2 + page.flags.mirror as u32;
+        action = action * 2 + page.flags.rise as u32;
+        action = action * FUMEN_FIELD_BLOCKS as u32 + coordinate;
+        action = action * 4 + rotation.value();
+        action = action * 8 + block.value();
+        writer.push(action, 3);
+
+        if has_comment {
+            encode_comment(&mut writer, &page.comment);
+            previous_comment = page.comment.clone();
+        }
+
+        previous_field = page.next_field();
+    }
+
+    format!("v115@{}", writer.finish())
+}
+
+fn decode_field(
+    reader: &mut FumenReader,
+    previous_field: &FumenField,
+) -> Result<(FumenField, bool), FumenError> {
+    let mut field = previous_field.clone();
+    let mut changed = true;
+    let mut index = 0;
+
+    while index < FUMEN_FIELD_BLOCKS {
+        let value = reader.poll(2)?;
+        let diff = value / FUMEN_FIELD_BLOCKS as u32;
+        let count = value % FUMEN_FIELD_BLOCKS as u32 + 1;
+        if diff == 8 && count == FUMEN_FIELD_BLOCKS as u32 {
+            changed = false;
+        }
+
+        for _ in 0..count {
+            let cell = field.cells.get_mut(index).ok_or(FumenError::InvalidValue)?;
+            let block = (cell.value() + diff)
+                .checked_sub(8)
+                .ok_or(FumenError::InvalidValue)?;
+            *cell = FumenBlock::from_value(block)?;
+            index += 1;
+        }
+    }
+
+    Ok((field, changed))
+}
+
+fn encode_field(previous_field: &FumenField, field: &FumenField) -> (Vec<u32>, bool) {
+    let mut writer = FumenWriter::default();
+    let diffs = field
+        .cells
+        .iter()
+        .zip(previous_field.cells.iter())
+        .map(|(current, previous)| current.value() + 8 - previous.value());
+
+    let mut run_diff = None;
+    let mut run_length = 0;
+    for diff in diffs {
+        match run_diff {
+            Some(current) if current == diff => run_length += 1,
+            Some(current) => {
+                writer.push(current * FUMEN_FIELD_BLOCKS as u32 + run_length - 1, 2);
+                run_diff = Some(diff);
+                run_length = 1;
+            }
+            None => {
+                run_diff = Some(diff);
+                run_length = 1;
+            }
+        }
+    }
+    let last_diff = run_diff.unwrap_or(8);
+    writer.push(last_diff * FUMEN_FIELD_BLOCKS as u32 + run_length - 1, 2);
+
+    let changed = !(last_diff == 8 && run_length == FUMEN_FIELD_BLOCKS as u32);
+    (writer.values, changed)
+}
+
+fn decode_comment(reader: &mut FumenReader) -> Result<String, FumenError> {
+    let length = reader.poll(2)? as usize;
+    let mut escaped = String::new();
+    for _ in 0..(length + 3) / 4 {
+        let mut value = reader.poll(5)?;
+        for _ in 0..4 {
+            let index = (value % COMMENT_CHAR_RANGE) as usize;
+            escaped.push(*COMMENT_TABLE.get(index).ok_or(FumenError::InvalidValue)? as char);
+            value /= COMMENT_CHAR_RANGE;
+        }
+    }
+    escaped.truncate(length);
+    Ok(unescape(&escaped))
+}
+
+fn encode_comment(writer: &mut FumenWriter, comment: &str) {
+    let escaped = escape(comment, COMMENT_MAX_LENGTH);
+    writer.push(escaped.len() as u32, 2);
+
+    for chunk in escaped.as_bytes().chunks(4) {
+        let mut value = 0;
+        for (i, character) in chunk.iter().enumerate() {
+            let index = COMMENT_TABLE
+                .iter()
+                .position(|c| c == character)
+                .unwrap_or(0) as u32;
+            value += index * COMMENT_CHAR_RANGE.pow(i as u32);
+        }
+        writer.push(value, 5);
+    }
+}
+
+//comments go through javascript's escape() so they only use printable ascii. a comment too long
+//to fit is cut before the first character that doesn't fit whole, so no escape is left cut short
+fn escape(text: &str, max_length: usize) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        let mut escaped_char = String::new();
+        if c.is_ascii_alphanumeric() || "@*_+-./".contains(c) {
+            escaped_char.push(c);
+        } else {
+            let mut buffer = [0; 2];
+            for unit in c.encode_utf16(&mut buffer) {
+                if *unit < 256 {
+                    escaped_char.push_str(&format!("%{:02X}", unit));
+                } else {
+                    escaped_char.push_str(&format!("%u{:04X}", unit));
+                }
+            }
+        }
+        if escaped.len() + escaped_char.len() > max_length {
+            break;
+        }
+        escaped.push_str(&escaped_char);
+    }
+    escaped
+}
+
+fn unescape(text: &str) -> String {
+    let mut units: Vec<u16> = vec![];
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let unicode = rest.strip_prefix("%u").and_then(|hex| parse_hex(hex, 4));
+        let byte = rest.strip_prefix('%').and_then(|hex| parse_hex(hex, 2));
+
+        if let Some(unit) = unicode {
+            units.push(unit);
+            rest = &rest[6..];
+        } else if let Some(unit) = byte {
+            units.push(unit);
+            rest = &rest[3..];
+        } else {
+            let mut buffer = [0; 2];
+            units.extend_from_slice(c.encode_utf16(&mut buffer));
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    String::from_utf16_lossy(&units)
+}
+
+fn parse_hex(text: &str, digits: usize) -> Option<u16> {
+    let hex = text.get(..digits)?;
+    if hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        u16::from_str_radix(hex, 16).ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //fields as the fumen editor writes them, which encode has to give back exactly
+    const KNOWN_FUMENS: [&str; 3] = [
+        "v115@vhAAgH",
+        "v115@9gF8DeF8DeF8DeF8NeAgH",
+        "v115@bhD8hlD8JeAgH",
+    ];
+
+    #[test]
+    fn known_fumens_round_trip() {
+        for fumen in KNOWN_FUMENS {
+            let pages = decode(fumen).unwrap();
+            assert_eq!(encode(&pages), fumen);
+        }
+    }
+
+    #[test]
+    fn decodes_a_known_field() {
+        let pages = decode("v115@9gF8DeF8DeF8DeF8NeAgH").unwrap();
+        assert_eq!(pages.len(), 1);
+        let field = &pages[0].field;
+        for y in 0..FUMEN_FIELD_TOP {
+            for x in 0..FUMEN_FIELD_WIDTH {
+                let expected = if y < 4 && x < 6 {
+                    FumenBlock::Gray
+                } else {
+                    FumenBlock::Empty
+                };
+                assert_eq!(field.get(x, y), expected, "at {}, {}", x, y);
+            }
+        }
+        assert_eq!(pages[0].piece, None);
+        assert_eq!(pages[0].comment, "");
+    }
+
+    #[test]
+    fn pieces_and_comments_round_trip() {
+        let mut field = FumenField::default();
+        field.set(0, 0, FumenBlock::Gray);
+        field.set(9, 0, FumenBlock::Gray);
+        let first = FumenPage {
+            field,
+            piece: Some(FumenPiece {
+                block: FumenBlock::T,
+                rotation: FumenRotation::Spawn,
+                x: 4,
+                y: 0,
+            }),
+            comment: "t spin 100%".to_string(),
+            ..default()
+        };
+        let second = FumenPage {
+            field: first.next_field(),
+            piece: Some(FumenPiece {
+                block: FumenBlock::I,
+                rotation: FumenRotation::Right,
+                x: 8,
+                y: 2,
+            }),
+            comment: "next".to_string(),
+            ..default()
+        };
+        let third = FumenPage {
+            field: second.next_field(),
+            comment: second.comment.clone(),
+            ..default()
+        };
+        let pages = vec![first, second, third];
+
+        assert_eq!(decode(&encode(&pages)).unwrap(), pages);
+    }
+
+    #[test]
+    fn long_comments_are_cut_between_escapes() {
+        //one character short of the limit, so the escape after it can't fit whole
+        let ascii = "a".repeat(COMMENT_MAX_LENGTH - 1);
+        for (extra, kept) in [("%", ""), ("\u{3042}", ""), ("\u{1F600}", ""), ("b", "b")] {
+            let page = FumenPage {
+                comment: format!("{}{}", ascii, extra),
+                ..default()
+            };
+            let decoded = decode(&encode(std::slice::from_ref(&page))).unwrap();
+            assert_eq!(decoded[0].comment, format!("{}{}", ascii, kept));
+        }
+
+        //comments that fit come back whole, escapes and all
+        let page = FumenPage {
+            comment: "100% \u{3042} \u{1F600}".repeat(10),
+            ..default()
+        };
+        assert_eq!(
+            decode(&encode(std::slice::from_ref(&page))).unwrap(),
+            vec![page]
+        );
+    }
+
+    #[test]
+    fn bad_fumens_are_rejected() {
+        assert_eq!(decode("vhAAgH"), Err(FumenError::MissingPrefix));
+        assert!(matches!(
+            decode("v110@vhAAgH"),
+            Err(FumenError::UnsupportedVersion(_))
+        ));
+    }
+}
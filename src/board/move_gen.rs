@@ -0,0 +1,333 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+
+use crate::board::board_core::{kicked_rotation, PieceRotation, PieceType, RotationDirection};
+
+//the inputs a path to a placement is made of. soft drop takes the piece all the way down, and
+//the hard drop that locks the piece at the end isn't part of the path
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MoveInput {
+    Left,
+    Right,
+    SoftDrop,
+    RotateClockwise,
+    RotateCounterClockwise,
+    Rotate180,
+}
+
+impl MoveInput {
+    pub const ALL: [MoveInput; 6] = [
+        MoveInput::Left,
+        MoveInput::Right,
+        MoveInput::SoftDrop,
+        MoveInput::RotateClockwise,
+        MoveInput::RotateCounterClockwise,
+        MoveInput::Rotate180,
+    ];
+
+    fn rotation_direction(&self) -> Option<RotationDirection> {
+        match self {
+            MoveInput::RotateClockwise => Some(RotationDirection::Clockwise),
+            MoveInput::RotateCounterClockwise => Some(RotationDirection::CounterClockwise),
+            MoveInput::Rotate180 => Some(RotationDirection::Half),
+            _ => None,
+        }
+    }
+}
+
+//whether the piece was spun into place. t pieces use the three corner rule, every other piece
+//counts as a mini when it was rotated into a spot it can't move out of
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Spin {
+    None,
+    Mini,
+    Full,
+}
+
+//a resting place the piece can reach and the fewest inputs that get it there
+#[derive(Debug, Clone)]
+pub struct ReachablePlacement {
+    pub piece_type: PieceType,
+    pub rotation: PieceRotation,
+    pub origin: IVec2,
+    pub cells: Vec<IVec2>,
+    pub spin: Spin,
+    pub path: Vec<MoveInput>,
+}
+
+//where the piece is, and the spin it would lock with if it locked there
+type SearchState = (IVec2, PieceRotation, Spin);
+
+//every place the piece can come to rest from where it is, found by trying every input from
+//every position it can get to. the same cells reached with and without a spin are listed once
+//for each. fits says whether the piece's blocks can be in a set of cells, so this works on
+//the game's board and on copies of it alike
+pub fn reachable_placements(
+    piece_type: PieceType,
+    origin: IVec2,
+    rotation: PieceRotation,
+    fits: impl Fn(&[IVec2]) -> bool,
+) -> Vec<ReachablePlacement> {
+    if !fits(&piece_type.cells(rotation, origin)) {
+        return vec![];
+    }
+
+    let start = (origin, rotation, Spin::None);
+    let mut paths: HashMap<SearchState, Vec<MoveInput>> = HashMap::from([(start, vec![])]);
+    let mut queue = VecDeque::from([start]);
+    let mut placements = vec![];
+
+    while let Some(state) = queue.pop_front() {
+        let (origin, rotation, spin) = state;
+        let path = paths[&state].clone();
+
+        if !fits(&piece_type.cells(rotation, origin - IVec2::Y)) {
+            placements.push(ReachablePlacement {
+                piece_type,
+                rotation,
+                origin,
+                cells: piece_type.cells(rotation, origin),
+                spin,
+                path: path.clone(),
+            });
+        }
+
+        for input in MoveInput::ALL {
+            let next = match apply_input(piece_type, origin, rotation, input, &fits) {
+                Some(next) => next,
+                None => continue,
+            };
+            if let Entry::Vacant(entry) = paths.entry(next) {
+                let mut next_path = path.clone();
+                next_path.push(input);
+                entry.insert(next_path);
+                queue.push_back(next);
+            }
+        }
+    }
+    placements
+}
+
+//where an input takes the piece, if it can move at all
+fn apply_input(
+    piece_type: PieceType,
+    origin: IVec2,
+    rotation: PieceRotation,
+    input: MoveInput,
+    fits: &impl Fn(&[IVec2]) -> bool,
+) -> Option<SearchState> {
+    let shifted = |shift: IVec2| {
+        if fits(&piece_type.cells(rotation, origin + shift)) {
+            Some((origin + shift, rotation, Spin::None))
+        } else {
+            None
+        }
+    };
+
+    match input {
+        MoveInput::Left => shifted(-IVec2::X),
+        MoveInput::Right => shifted(IVec2::X),
+        MoveInput::SoftDrop => {
+            let mut dropped_origin = origin;
+            while fits(&piece_type.cells(rotation, dropped_origin - IVec2::Y)) {
+                dropped_origin -= IVec2::Y;
+            }
+            if dropped_origin == origin {
+                None
+            } else {
+                Some((dropped_origin, rotation, Spin::None))
+            }
+        }
+        _ => {
+            let direction = input.rotation_direction()?;
+            let new_rotation = rotation.rotated(direction);
+            let (kick_index, new_origin) =
+                kicked_rotation(piece_type, rotation, new_rotation, origin, fits)?;
+            let spin = spin_after_rotation(piece_type, new_origin, new_rotation, kick_index, fits);
+            Some((new_origin, new_rotation, spin))
+        }
+    }
+}
+
+//the spin a piece that was just rotated would lock with
+pub fn spin_after_rotation(
+    piece_type: PieceType,
+    origin: IVec2,
+    rotation: PieceRotation,
+    kick_index: usize,
+    fits: &impl Fn(&[IVec2]) -> bool,
+) -> Spin {
+    if piece_type != PieceType::T {
+        let cells = piece_type.cells(rotation, origin);
+        let immobile = [IVec2::X, -IVec2::X, IVec2::Y]
+            .iter()
+            .all(|shift| !fits(&cells.iter().map(|cell| *cell + *shift).collect::<Vec<_>>()));
+        return if immobile { Spin::Mini } else { Spin::None };
+    }
+
+    //corners of the t's rotation box, the two on the side it points to first
+    let (front, back) = match rotation {
+        PieceRotation::Zero => ([(0, 2), (2, 2)], [(0, 0), (2, 0)]),
+        PieceRotation::Ninety => ([(2, 2), (2, 0)], [(0, 0), (0, 2)]),
+        PieceRotation::OneEighty => ([(0, 0), (2, 0)], [(0, 2), (2, 2)]),
+        PieceRotation::TwoSeventy => ([(0, 0), (0, 2)], [(2, 2), (2, 0)]),
+    };
+    let filled = |corners: [(i32, i32); 2]| {
+        corners
+            .iter()
+            .filter(|(x, y)| !fits(&[origin + IVec2 { x: *x, y: *y }]))
+            .count()
+    };
+    let front_filled = filled(front);
+    let back_filled = filled(back);
+
+    if front_filled + back_filled < 3 {
+        Spin::None
+    } else if front_filled == 2 || kick_index == 4 {
+        //the last kick of a quarter turn always counts as a full spin
+        Spin::Full
+    } else {
+        Spin::Mini
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const SPAWN: IVec2 = IVec2 { x: 3, y: 20 };
+
+    //a ten wide board with the given cells filled
+    fn board(filled: &[(i32, i32)]) -> impl Fn(&[IVec2]) -> bool {
+        let filled: HashSet<IVec2> = filled.iter().map(|&(x, y)| IVec2 { x, y }).collect();
+        move |cells: &[IVec2]| {
+            cells.iter().all(|cell| {
+                (0..10).contains(&cell.x) && (0..24).contains(&cell.y) && !filled.contains(cell)
+            })
+        }
+    }
+
+    fn cell_set(cells: &[(i32, i32)]) -> HashSet<IVec2> {
+        cells.iter().map(|&(x, y)| IVec2 { x, y }).collect()
+    }
+
+    fn placements_at(
+        placements: &[ReachablePlacement],
+        cells: &[(i32, i32)],
+    ) -> Vec<ReachablePlacement> {
+        let cells = cell_set(cells);
+        placements
+            .iter()
+            .filter(|placement| placement.cells.iter().copied().collect::<HashSet<_>>() == cells)
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn soft_drop_tucks_under_an_overhang() {
+        let fits = board(&[(0, 2), (1, 2), (2, 2), (3, 2)]);
+        let placements = reachable_placements(PieceType::O, SPAWN, PieceRotation::Zero, &fits);
+
+        //the o can be listed in each of its rotations, and every one of them needs the soft drop
+        let tucked = placements_at(&placements, &[(0, 0), (1, 0), (0, 1), (1, 1)]);
+        assert!(!tucked.is_empty());
+        assert!(tucked
+            .iter()
+            .all(|placement| placement.path.contains(&MoveInput::SoftDrop)));
+        let unrotated = tucked
+            .iter()
+            .find(|placement| placement.rotation == PieceRotation::Zero)
+            .unwrap();
+        assert_eq!(unrotated.path.last(), Some(&MoveInput::Left));
+        //and it can still be dropped onto the roof
+        let on_the_roof = placements_at(&placements, &[(0, 3), (1, 3), (0, 4), (1, 4)]);
+        assert!(on_the_roof.iter().any(|placement| placement.path
+            == vec![
+                MoveInput::Left,
+                MoveInput::Left,
+                MoveInput::Left,
+                MoveInput::Left,
+                MoveInput::SoftDrop
+            ]));
+    }
+
+    #[test]
+    fn t_spin_double_slot_is_a_full_spin() {
+        let mut filled = vec![(0, 2), (1, 2), (2, 2), (3, 2), (4, 2)];
+        filled.extend((0..10).filter(|&x| x != 5).map(|x| (x, 0)));
+        filled.extend((0..10).filter(|&x| !(4..=6).contains(&x)).map(|x| (x, 1)));
+        let fits = board(&filled);
+        let placements = reachable_placements(PieceType::T, SPAWN, PieceRotation::Zero, &fits);
+
+        let slotted = placements_at(&placements, &[(4, 1), (5, 1), (6, 1), (5, 0)]);
+        assert_eq!(slotted.len(), 1);
+        assert_eq!(slotted[0].spin, Spin::Full);
+        assert_eq!(slotted[0].rotation, PieceRotation::OneEighty);
+        assert_eq!(slotted[0].path.last(), Some(&MoveInput::RotateClockwise));
+    }
+
+    #[test]
+    fn rotating_in_without_a_kick_can_be_a_mini() {
+        //a t sitting over a one wide hole, with a block over its left side
+        let fits = board(&[(3, 0), (5, 0), (3, 2)]);
+        let placements = reachable_placements(PieceType::T, SPAWN, PieceRotation::Zero, &fits);
+
+        let flat = placements_at(&placements, &[(3, 1), (4, 1), (5, 1), (4, 2)]);
+        let mini = flat
+            .iter()
+            .find(|placement| placement.spin == Spin::Mini)
+            .unwrap();
+        let (kick_index, _) = kicked_rotation(
+            PieceType::T,
+            PieceRotation::Ninety,
+            PieceRotation::Zero,
+            mini.origin,
+            &fits,
+        )
+        .unwrap();
+        assert_eq!(kick_index, 0);
+        assert_eq!(mini.path.last(), Some(&MoveInput::RotateCounterClockwise));
+        //sliding in from the side gets there with no spin at all
+        assert!(flat.iter().any(|placement| placement.spin == Spin::None));
+        assert!(flat.iter().all(|placement| placement.spin != Spin::Full));
+    }
+
+    #[test]
+    fn paths_reach_their_placements() {
+        let mut filled = vec![(0, 2), (1, 2), (2, 2), (3, 2), (4, 2), (8, 4)];
+        filled.extend((0..10).filter(|&x| x != 5).map(|x| (x, 0)));
+        filled.extend((0..10).filter(|&x| !(4..=6).contains(&x)).map(|x| (x, 1)));
+        let fits = board(&filled);
+
+        for piece_type in [
+            PieceType::I,
+            PieceType::J,
+            PieceType::L,
+            PieceType::O,
+            PieceType::S,
+            PieceType::T,
+            PieceType::Z,
+        ] {
+            let placements = reachable_placements(piece_type, SPAWN, PieceRotation::Zero, &fits);
+            assert!(!placements.is_empty());
+            for placement in placements {
+                let mut state = (SPAWN, PieceRotation::Zero, Spin::None);
+                for input in &placement.path {
+                    state = apply_input(piece_type, state.0, state.1, *input, &fits).unwrap();
+                }
+                assert_eq!(
+                    state,
+                    (placement.origin, placement.rotation, placement.spin),
+                    "{:?} {:?}",
+                    piece_type,
+                    placement.path
+                );
+                assert_eq!(placement.cells, piece_type.cells(state.1, state.0));
+                assert!(!fits(&piece_type.cells(state.1, state.0 - IVec2::Y)));
+            }
+        }
+    }
+}
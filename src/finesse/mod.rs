@@ -0,0 +1,530 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+
+use crate::board::board_core::{
+    kicked_origin, BoardData, BoardSize, PieceRotation, PieceType, RotationDirection,
+};
+use crate::game_state_machine::{GamePlayState, GameStateInfo};
+use crate::input::GameAction;
+use crate::piece_queue::PieceQueue;
+use crate::stats::GameStats;
+use crate::versus::VersusSettings;
+use crate::{GameSettings, PieceLockedEvent, SimulationClock, FONT_ASSET_PATH, SCORE_COLOR};
+
+const FINESSE_FONT_SIZE: f32 = 28.0;
+const FINESSE_TEXT_LEFT: Val = Val::Px(5.0);
+const FINESSE_TEXT_TOP: Val = Val::Px(520.0);
+//how long a fault stays on screen
+const FAULT_DISPLAY_FRAMES: u32 = 120;
+
+const ALL_PIECE_TYPES: [PieceType; 7] = [
+    PieceType::I,
+    PieceType::J,
+    PieceType::L,
+    PieceType::O,
+    PieceType::S,
+    PieceType::T,
+    PieceType::Z,
+];
+
+//the inputs finesse is counted in. a das move is held until the piece reaches the wall
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FinesseInput {
+    Left,
+    Right,
+    DasLeft,
+    DasRight,
+    RotateClockwise,
+    RotateCounterClockwise,
+    Rotate180,
+}
+
+impl FinesseInput {
+    const ALL: [FinesseInput; 7] = [
+        FinesseInput::Left,
+        FinesseInput::Right,
+        FinesseInput::DasLeft,
+        FinesseInput::DasRight,
+        FinesseInput::RotateClockwise,
+        FinesseInput::RotateCounterClockwise,
+        FinesseInput::Rotate180,
+    ];
+
+    fn name(&self) -> &str {
+        match self {
+            FinesseInput::Left => "LEFT",
+            FinesseInput::Right => "RIGHT",
+            FinesseInput::DasLeft => "DAS LEFT",
+            FinesseInput::DasRight => "DAS RIGHT",
+            FinesseInput::RotateClockwise => "CW",
+            FinesseInput::RotateCounterClockwise => "CCW",
+            FinesseInput::Rotate180 => "180",
+        }
+    }
+
+    //where the input takes a piece on an empty board, if it can move at all
+    fn apply(
+        &self,
+        size: BoardSize,
+        piece_type: PieceType,
+        origin: IVec2,
+        rotation: PieceRotation,
+    ) -> Option<(IVec2, PieceRotation)> {
+        let fits_in_empty_board = |cells: &[IVec2]| cells.iter().all(|cell| size.contains(*cell));
+        let shift = |step: i32, repeat: bool| {
+            let mut shifted_origin = origin;
+            while fits_in_empty_board(&piece_type.cells(rotation, shifted_origin + IVec2::X * step))
+            {
+                shifted_origin += IVec2::X * step;
+                if !repeat {
+                    break;
+                }
+            }
+            if shifted_origin == origin {
+                None
+            } else {
+                Some((shifted_origin, rotation))
+            }
+        };
+        let rotate = |direction: RotationDirection| {
+            let new_rotation = rotation.rotated(direction);
+            kicked_origin(
+                piece_type,
+                rotation,
+                new_rotation,
+                origin,
+                fits_in_empty_board,
+            )
+            .map(|new_origin| (new_origin, new_rotation))
+        };
+
+        match self {
+            FinesseInput::Left => shift(-1, false),
+            FinesseInput::Right => shift(1, false),
+            FinesseInput::DasLeft => shift(-1, true),
+            FinesseInput::DasRight => shift(1, true),
+            FinesseInput::RotateClockwise => rotate(RotationDirection::Clockwise),
+            FinesseInput::RotateCounterClockwise => rotate(RotationDirection::CounterClockwise),
+            FinesseInput::Rotate180 => rotate(RotationDirection::Half),
+        }
+    }
+}
+
+//the blocks' columns and shape, without how high they are. pieces dropped with the same
+//placement end up in the same place
+fn placement_key(cells: &[IVec2]) -> Vec<IVec2> {
+    let bottom = cells.iter().map(|cell| cell.y).min().unwrap_or(0);
+    let mut key: Vec<IVec2> = cells
+        .iter()
+        .map(|cell| IVec2 {
+            x: cell.x,
+            y: cell.y - bottom,
+        })
+        .collect();
+    key.sort_by_key(|cell| (cell.x, cell.y));
+    key
+}
+
+//the fewest inputs to get every piece from where it spawns to each placement it can be dropped
+//from, worked out by searching every input from spawn on an empty board of the size being played
+pub struct FinesseTable {
+    size: BoardSize,
+    paths: HashMap<PieceType, HashMap<Vec<IVec2>, Vec<FinesseInput>>>,
+}
+
+impl FromWorld for FinesseTable {
+    fn from_world(world: &mut World) -> Self {
+        world.init_resource::<GameSettings>();
+        FinesseTable::new(world.resource::<GameSettings>().board_size)
+    }
+}
+
+impl FinesseTable {
+    fn new(size: BoardSize) -> FinesseTable {
+        FinesseTable {
+            size,
+            paths: ALL_PIECE_TYPES
+                .iter()
+                .map(|piece_type| (*piece_type, FinesseTable::search(size, *piece_type)))
+                .collect(),
+        }
+    }
+
+    fn search(size: BoardSize, piece_type: PieceType) -> HashMap<Vec<IVec2>, Vec<FinesseInput>> {
+        let start = (
+            piece_type.return_spawn_coord_base(size),
+            PieceRotation::Zero,
+        );
+        let mut visited = HashMap::from([(start, vec![])]);
+        let mut queue = VecDeque::from([start]);
+        let mut paths = HashMap::new();
+
+        while let Some((origin, rotation)) = queue.pop_front() {
+            let path = visited[&(origin, rotation)].clone();
+            paths
+                .entry(placement_key(&piece_type.cells(rotation, origin)))
+                .or_insert_with(|| path.clone());
+
+            for input in FinesseInput::ALL {
+                if let Some(next) = input.apply(size, piece_type, origin, rotation) {
+                    if let Entry::Vacant(entry) = visited.entry(next) {
+                        let mut next_path = path.clone();
+                        next_path.push(input);
+                        entry.insert(next_path);
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+        paths
+    }
+
+    pub fn best_path(&self, piece_type: PieceType, cells: &[IVec2]) -> Option<&Vec<FinesseInput>> {
+        self.paths.get(&piece_type)?.get(&placement_key(cells))
+    }
+}
+
+//a piece that took more inputs than it needed
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FinesseFault {
+    pub piece_type: PieceType,
+    pub inputs_used: u32,
+    pub best_path: Vec<FinesseInput>,
+    pub frame: u32,
+}
+
+impl FinesseFault {
+    fn describe(&self) -> String {
+        let best_path: Vec<&str> = self.best_path.iter().map(|input| input.name()).collect();
+        format!(
+            "{:?}: {} keys, best {}: {}",
+            self.piece_type,
+            self.inputs_used,
+            self.best_path.len(),
+            if best_path.is_empty() {
+                "DROP".to_string()
+            } else {
+                best_path.join(", ")
+            }
+        )
+    }
+}
+
+//counts the inputs spent on the current piece and keeps every fault for the report after the game
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FinesseTracker {
+    inputs_this_piece: u32,
+    pieces_checked: u32,
+    faults: Vec<FinesseFault>,
+    report_logged: bool,
+}
+
+impl FromWorld for FinesseTracker {
+    fn from_world(_world: &mut World) -> Self {
+        FinesseTracker {
+            inputs_this_piece: 0,
+            pieces_checked: 0,
+            faults: vec![],
+            report_logged: false,
+        }
+    }
+}
+
+impl FinesseTracker {
+    fn report(&self) -> String {
+        let clean_pieces = self.pieces_checked - self.faults.len() as u32;
+        let clean_percent = if self.pieces_checked == 0 {
+            100.
+        } else {
+            clean_pieces as f32 * 100. / self.pieces_checked as f32
+        };
+        let extra_inputs: u32 = self
+            .faults
+            .iter()
+            .map(|fault| fault.inputs_used - fault.best_path.len() as u32)
+            .sum();
+
+        let mut report = format!(
+            "FINESSE REPORT\n{}/{} pieces clean ({:.0}%)\n{} extra keys\n",
+            clean_pieces, self.pieces_checked, clean_percent, extra_inputs
+        );
+        for piece_type in ALL_PIECE_TYPES {
+            let piece_faults = self
+                .faults
+                .iter()
+                .filter(|fault| fault.piece_type == piece_type)
+                .count();
+            if piece_faults > 0 {
+                report.push_str(&format!("{:?} faults {}\n", piece_type, piece_faults));
+            }
+        }
+        report
+    }
+}
+
+//moves and rotations the player presses, counted against the piece they end up placing. a
+//hold puts the piece away, so what was spent on it doesn't count against the next one
+pub fn count_finesse_inputs(
+    game_state: Res<GameStateInfo>,
+    game_input: Res<Input<GameAction>>,
+    piece_queue: Res<PieceQueue>,
+    board_data: Res<BoardData>,
+    mut finesse_tracker: ResMut<FinesseTracker>,
+) {
+    if game_state.game_state != GamePlayState::Playing {
+        return;
+    }
+    let holding = game_input.just_pressed(GameAction::Hold)
+        && !piece_queue.hold_used
+        && board_data.active_piece.is_some();
+    if holding {
+        finesse_tracker.inputs_this_piece = 0;
+        return;
+    }
+    let inputs = game_input
+        .get_just_pressed()
+        .filter(|action| {
+            !matches!(
+                action,
+                GameAction::SoftDrop | GameAction::HardDrop | GameAction::Hold
+            )
+        })
+        .count();
+    finesse_tracker.inputs_this_piece += inputs as u32;
+}
+
+//compares the inputs spent on each locked piece with the fewest it could have taken. pieces
+//tucked under something are skipped since they can't be reached by dropping. the table is
+//searched again whenever a game is played on a board of another size
+pub fn check_finesse(
+    mut piece_locked_reader: EventReader<PieceLockedEvent>,
+    board_data: Res<BoardData>,
+    mut finesse_table: ResMut<FinesseTable>,
+    clock: Res<SimulationClock>,
+    mut finesse_tracker: ResMut<FinesseTracker>,
+    mut game_stats: ResMut<GameStats>,
+) {
+    if finesse_table.size != board_data.size() {
+        *finesse_table = FinesseTable::new(board_data.size());
+    }
+
+    for event in piece_locked_reader.iter() {
+        //a cascade's clears aren't pieces, so there's nothing to check
+        if event.chain > 1 {
+            continue;
+        }
+        let inputs_used = finesse_tracker.inputs_this_piece;
+        finesse_tracker.inputs_this_piece = 0;
+
+        let piece_type = match event.piece_type {
+            Some(piece_type) if event.dropped_straight => piece_type,
+            _ => continue,
+        };
+        let best_path = match finesse_table.best_path(piece_type, &event.cells) {
+            Some(best_path) => best_path,
+            None => continue,
+        };
+
+        finesse_tracker.pieces_checked += 1;
+        if inputs_used > best_path.len() as u32 {
+            game_stats.finesse_faults += inputs_used - best_path.len() as u32;
+            finesse_tracker.faults.push(FinesseFault {
+                piece_type,
+                inputs_used,
+                best_path: best_path.clone(),
+                frame: clock.frame,
+            });
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct FinesseText {}
+
+pub fn setup_finesse_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load(FONT_ASSET_PATH),
+                    font_size: FINESSE_FONT_SIZE,
+                    color: SCORE_COLOR,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: FINESSE_TEXT_TOP,
+                    left: FINESSE_TEXT_LEFT,
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(FinesseText {});
+}
+
+//shows each fault for a moment as it happens, then the report once the game is over. versus
+//has no room for it
+pub fn update_finesse_text(
+    game_state: Res<GameStateInfo>,
+    clock: Res<SimulationClock>,
+    versus_settings: Res<VersusSettings>,
+    mut finesse_tracker: ResMut<FinesseTracker>,
+    mut text_query: Query<&mut Text, With<FinesseText>>,
+) {
+    if versus_settings.enabled {
+        return;
+    }
+
+    let game_over =
+        game_state.game_state == GamePlayState::Win || game_state.game_state == GamePlayState::Lose;
+
+    let text = if game_over {
+        let report = finesse_tracker.report();
+        if !finesse_tracker.report_logged {
+            finesse_tracker.report_logged = true;
+            info!("{}", report);
+            for fault in &finesse_tracker.faults {
+                info!(
+                    "finesse fault at {}: {}",
+                    crate::format_time(fault.frame),
+                    fault.describe()
+                );
+            }
+        }
+        report
+    } else {
+        match finesse_tracker.faults.last() {
+            Some(fault)
+                if game_state.game_state == GamePlayState::Playing
+                    && clock.frame < fault.frame + FAULT_DISPLAY_FRAMES =>
+            {
+                format!("FINESSE FAULT\n{}", fault.describe())
+            }
+            _ => String::new(),
+        }
+    };
+
+    let mut finesse_text = text_query.single_mut();
+    if finesse_text.sections[0].value != text {
+        finesse_text.sections[0].value = text;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{headless_app, run_frames, set_key};
+    use FinesseInput::*;
+
+    fn cells(cells: [(i32, i32); 4]) -> Vec<IVec2> {
+        cells.iter().map(|&(x, y)| IVec2 { x, y }).collect()
+    }
+
+    fn best_path(piece_type: PieceType, placement: [(i32, i32); 4]) -> Vec<FinesseInput> {
+        FinesseTable::new(BoardSize::default())
+            .best_path(piece_type, &cells(placement))
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn known_best_paths() {
+        assert_eq!(
+            best_path(PieceType::I, [(3, 0), (4, 0), (5, 0), (6, 0)]),
+            vec![]
+        );
+        assert_eq!(
+            best_path(PieceType::I, [(0, 0), (0, 1), (0, 2), (0, 3)]),
+            vec![RotateClockwise, DasLeft]
+        );
+        assert_eq!(
+            best_path(PieceType::I, [(9, 0), (9, 1), (9, 2), (9, 3)]),
+            vec![RotateClockwise, DasRight]
+        );
+        assert_eq!(
+            best_path(PieceType::O, [(0, 0), (1, 0), (0, 1), (1, 1)]),
+            vec![DasLeft]
+        );
+        assert_eq!(
+            best_path(PieceType::T, [(2, 0), (3, 0), (4, 0), (3, 1)]),
+            vec![Left]
+        );
+        assert_eq!(
+            best_path(PieceType::T, [(3, 1), (4, 1), (5, 1), (4, 0)]),
+            vec![Rotate180]
+        );
+        assert_eq!(
+            best_path(PieceType::L, [(0, 0), (0, 1), (0, 2), (1, 0)]),
+            vec![RotateClockwise, DasLeft]
+        );
+    }
+
+    #[test]
+    fn placements_are_the_same_at_any_height() {
+        let low = cells([(0, 0), (0, 1), (0, 2), (0, 3)]);
+        let high = cells([(0, 7), (0, 8), (0, 9), (0, 10)]);
+        assert_eq!(placement_key(&low), placement_key(&high));
+
+        let table = FinesseTable::new(BoardSize::default());
+        assert_eq!(
+            table.best_path(PieceType::I, &low),
+            table.best_path(PieceType::I, &high)
+        );
+        //a shape the piece can't make has no path
+        assert_eq!(
+            table.best_path(PieceType::I, &cells([(0, 0), (1, 0), (2, 0), (2, 1)])),
+            None
+        );
+    }
+
+    //every placement on a standard board takes at most three inputs
+    #[test]
+    fn no_placement_needs_more_than_three_inputs() {
+        let table = FinesseTable::new(BoardSize::default());
+        for piece_type in ALL_PIECE_TYPES {
+            let paths = &table.paths[&piece_type];
+            assert!(!paths.is_empty());
+            for path in paths.values() {
+                assert!(path.len() <= 3, "{:?} {:?}", piece_type, path);
+            }
+        }
+    }
+
+    fn press(app: &mut App, key: KeyCode) {
+        set_key(app, key, true);
+        run_frames(app, 1);
+        set_key(app, key, false);
+        run_frames(app, 1);
+    }
+
+    #[test]
+    fn holding_starts_the_count_over() {
+        let mut app = headless_app();
+        press(&mut app, KeyCode::Space);
+        for _ in 0..60 {
+            if app.world.resource::<BoardData>().active_piece.is_some() {
+                break;
+            }
+            run_frames(&mut app, 1);
+        }
+        assert!(app.world.resource::<BoardData>().active_piece.is_some());
+
+        press(&mut app, KeyCode::D);
+        press(&mut app, KeyCode::K);
+        assert_eq!(app.world.resource::<FinesseTracker>().inputs_this_piece, 2);
+
+        press(&mut app, KeyCode::C);
+        assert!(app.world.resource::<PieceQueue>().held_piece.is_some());
+        assert_eq!(app.world.resource::<FinesseTracker>().inputs_this_piece, 0);
+
+        press(&mut app, KeyCode::A);
+        //hold can't be used again until a piece locks, so this one doesn't start over
+        press(&mut app, KeyCode::C);
+        assert_eq!(app.world.resource::<FinesseTracker>().inputs_this_piece, 1);
+    }
+}
@@ -1,14 +1,18 @@
 ﻿use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 //controls game state from menu or playing
 
 //controls the actual flow of the playing game. when blocks fall, when the rows are checked, etc
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum GameFlow {
     Menu,
     PlayerMovingBlock,
-    CheckingRows,
+    //full rows flash for the line clear delay before they're taken out
+    ClearingRows,
     BlocksMovingAfterRowBreak,
+    //the wait before the next piece spawns, the entry delay or are
+    EntryDelay,
 }
 
 impl FromWorld for GameFlow {
@@ -18,7 +22,7 @@ impl FromWorld for GameFlow {
 }
 
 //controls the state of the game when its playing
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Hash)]
 pub enum GamePlayState {
     Menu,
     Win,
@@ -112,42 +116,62 @@ impl GameStateInfo {
                 GameFlow::PlayerMovingBlock => {
                     self.game_flow_state = GameFlow::PlayerMovingBlock;
                     event_writer.send(GameFlow::PlayerMovingBlock);
-                } // game is starting handle this until block is done then change state to checking rows
-                GameFlow::CheckingRows => {}
-                GameFlow::BlocksMovingAfterRowBreak => {} // move blocks until none can move then go back to checking rows
+                } // game is starting handle this until block is done then change state to clearing rows
+                GameFlow::ClearingRows => {}
+                GameFlow::BlocksMovingAfterRowBreak => {} // move blocks until none can move then wait for the next piece
+                GameFlow::EntryDelay => {}
             },
             GameFlow::PlayerMovingBlock => match flow_state {
                 GameFlow::Menu => {}
                 GameFlow::PlayerMovingBlock => {}
-                GameFlow::CheckingRows => {
-                    self.game_flow_state = GameFlow::CheckingRows;
-                    event_writer.send(GameFlow::CheckingRows)
-                } //check rows to see if any are filled. if so break them then send to blocks moving
+                GameFlow::ClearingRows => {
+                    self.game_flow_state = GameFlow::ClearingRows;
+                    event_writer.send(GameFlow::ClearingRows)
+                } //the piece filled some rows. they flash for the line clear delay then break
                 GameFlow::BlocksMovingAfterRowBreak => {}
+                GameFlow::EntryDelay => {
+                    self.game_flow_state = GameFlow::EntryDelay;
+                    event_writer.send(GameFlow::EntryDelay)
+                } //nothing was cleared, wait for the next piece
             },
-            GameFlow::CheckingRows => match flow_state {
+            GameFlow::ClearingRows => match flow_state {
                 GameFlow::Menu => {}
-                GameFlow::PlayerMovingBlock => {
-                    self.game_flow_state = GameFlow::PlayerMovingBlock;
-                    event_writer.send(GameFlow::PlayerMovingBlock)
-                }
-                GameFlow::CheckingRows => {}
+                GameFlow::PlayerMovingBlock => {}
+                GameFlow::ClearingRows => {}
                 GameFlow::BlocksMovingAfterRowBreak => {
                     self.game_flow_state = GameFlow::BlocksMovingAfterRowBreak;
                     event_writer.send(GameFlow::BlocksMovingAfterRowBreak)
                 }
+                GameFlow::EntryDelay => {
+                    self.game_flow_state = GameFlow::EntryDelay;
+                    event_writer.send(GameFlow::EntryDelay)
+                }
             },
             GameFlow::BlocksMovingAfterRowBreak => match flow_state {
                 GameFlow::Menu => {}
-                GameFlow::PlayerMovingBlock => {
-                    self.game_flow_state = GameFlow::PlayerMovingBlock;
-                    event_writer.send(GameFlow::PlayerMovingBlock)
-                }
-                GameFlow::CheckingRows => {}
+                GameFlow::PlayerMovingBlock => {}
+                GameFlow::ClearingRows => {
+                    self.game_flow_state = GameFlow::ClearingRows;
+                    event_writer.send(GameFlow::ClearingRows)
+                } //the blocks landed and filled more rows
                 GameFlow::BlocksMovingAfterRowBreak => {
                     self.game_flow_state = GameFlow::BlocksMovingAfterRowBreak;
                     event_writer.send(GameFlow::BlocksMovingAfterRowBreak)
                 }
+                GameFlow::EntryDelay => {
+                    self.game_flow_state = GameFlow::EntryDelay;
+                    event_writer.send(GameFlow::EntryDelay)
+                }
+            },
+            GameFlow::EntryDelay => match flow_state {
+                GameFlow::Menu => {}
+                GameFlow::PlayerMovingBlock => {
+                    self.game_flow_state = GameFlow::PlayerMovingBlock;
+                    event_writer.send(GameFlow::PlayerMovingBlock)
+                } //the next piece spawns
+                GameFlow::ClearingRows => {}
+                GameFlow::BlocksMovingAfterRowBreak => {}
+                GameFlow::EntryDelay => {}
             },
         }
     }
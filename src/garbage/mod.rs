@@ -0,0 +1,588 @@
+use bevy::prelude::*;
+use bevy::sprite::Anchor;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::board::board_core::{self, BlockId, BoardData, BoardSize};
+use crate::board::move_gen::Spin;
+use crate::game_state_machine::{GamePlayState, GameStateInfo};
+use crate::stats::{ClearType, GameStats};
+use crate::versus::VersusSettings;
+use crate::{GameSettings, PieceLockedEvent, SimulationClock};
+
+const GARBAGE_METER_COLOR: Color = Color::rgb(1.0, 0.2, 0.2);
+
+//lines sent to an opponent for a single, double, triple and tetris
+const CLEAR_ATTACK: [u32; 4] = [0, 1, 2, 4];
+//for a t-spin single, double and triple
+const T_SPIN_ATTACK: [u32; 3] = [2, 4, 6];
+//for a t-spin mini single and double
+const T_SPIN_MINI_ATTACK: [u32; 2] = [0, 1];
+const BACK_TO_BACK_BONUS: u32 = 1;
+//extra lines sent for each combo count, the last one is used for anything longer
+const COMBO_ATTACK: [u32; 12] = [0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+const JSTRIS_COMBO_ATTACK: [u32; 13] = [0, 0, 1, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+
+//how the empty column of incoming garbage moves
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum HoleRule {
+    //the hole stays in one column all game
+    Fixed,
+    //every attack gets a column of its own
+    PerAttack,
+    //every row gets a column of its own
+    PerLine,
+    //the hole moves before each row with this chance
+    Messiness(f32),
+}
+
+impl HoleRule {
+    fn from_argument(argument: &str) -> Option<HoleRule> {
+        match argument {
+            "fixed" => Some(HoleRule::Fixed),
+            "attack" => Some(HoleRule::PerAttack),
+            "line" => Some(HoleRule::PerLine),
+            _ => match argument.parse::<f32>() {
+                Ok(chance) if (0.0..=1.0).contains(&chance) => Some(HoleRule::Messiness(chance)),
+                _ => None,
+            },
+        }
+    }
+}
+
+//--garbage-holes fixed, attack, line or a chance from 0 to 1 picks the hole rule
+pub struct GarbageSettings {
+    pub hole_rule: HoleRule,
+}
+
+impl FromWorld for GarbageSettings {
+    fn from_world(_world: &mut World) -> Self {
+        let arguments: Vec<String> = std::env::args().collect();
+        let hole_rule = match arguments
+            .iter()
+            .position(|argument| argument == "--garbage-holes")
+        {
+            Some(index) => match arguments
+                .get(index + 1)
+                .and_then(|argument| HoleRule::from_argument(argument))
+            {
+                Some(hole_rule) => hole_rule,
+                None => {
+                    warn!("--garbage-holes needs fixed, attack, line or a chance from 0 to 1");
+                    HoleRule::PerAttack
+                }
+            },
+            None => HoleRule::PerAttack,
+        };
+        GarbageSettings { hole_rule }
+    }
+}
+
+#[derive(Debug)]
+pub enum AttackTableError {
+    Io(io::Error),
+    Format(serde_json::Error),
+}
+
+impl fmt::Display for AttackTableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttackTableError::Io(error) => write!(f, "{}", error),
+            AttackTableError::Format(error) => write!(f, "attack table is invalid: {}", error),
+        }
+    }
+}
+
+impl From<io::Error> for AttackTableError {
+    fn from(error: io::Error) -> Self {
+        AttackTableError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for AttackTableError {
+    fn from(error: serde_json::Error) -> Self {
+        AttackTableError::Format(error)
+    }
+}
+
+//how many lines each clear sends. --attack-table picks one of the built in tables by name or
+//loads one from a json file, where anything left out keeps the guideline numbers
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AttackTable {
+    //lines sent for a single, double, triple and tetris
+    pub clears: [u32; 4],
+    //for a t-spin single, double and triple
+    pub t_spin_clears: [u32; 3],
+    //for a t-spin mini single and double
+    pub t_spin_mini_clears: [u32; 2],
+    pub back_to_back_bonus: u32,
+    //extra lines sent for each combo count, the last one is used for anything longer
+    pub combos: Vec<u32>,
+}
+
+impl Default for AttackTable {
+    fn default() -> Self {
+        AttackTable {
+            clears: CLEAR_ATTACK,
+            t_spin_clears: T_SPIN_ATTACK,
+            t_spin_mini_clears: T_SPIN_MINI_ATTACK,
+            back_to_back_bonus: BACK_TO_BACK_BONUS,
+            combos: COMBO_ATTACK.to_vec(),
+        }
+    }
+}
+
+impl AttackTable {
+    pub fn from_arguments() -> AttackTable {
+        let arguments: Vec<String> = std::env::args().collect();
+        let path = match arguments
+            .iter()
+            .position(|argument| argument == "--attack-table")
+        {
+            Some(index) => match arguments.get(index + 1) {
+                Some(path) => path,
+                None => {
+                    warn!("--attack-table needs a table name or a file to load");
+                    return AttackTable::default();
+                }
+            },
+            None => return AttackTable::default(),
+        };
+
+        if let Some(table) = AttackTable::preset(path) {
+            info!("using the {} attack table", path);
+            return table;
+        }
+        match AttackTable::load(Path::new(path)) {
+            Ok(table) => {
+                info!("loaded attack table {}", path);
+                table
+            }
+            Err(error) => {
+                warn!("couldn't load attack table {}: {}", path, error);
+                AttackTable::default()
+            }
+        }
+    }
+
+    //the built in tables
+    pub fn preset(name: &str) -> Option<AttackTable> {
+        match name {
+            "guideline" => Some(AttackTable::default()),
+            //clears only, with nothing extra for spins or chains
+            "classic" => Some(AttackTable {
+                t_spin_clears: [0, 1, 2],
+                t_spin_mini_clears: [0, 1],
+                back_to_back_bonus: 0,
+                combos: vec![],
+                ..default()
+            }),
+            "jstris" => Some(AttackTable {
+                combos: JSTRIS_COMBO_ATTACK.to_vec(),
+                ..default()
+            }),
+            _ => None,
+        }
+    }
+
+    fn load(path: &Path) -> Result<AttackTable, AttackTableError> {
+        let text = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    //lines a clear sends, with the chains it was part of. only t pieces send extra for spins
+    pub fn attack_for_clear(
+        &self,
+        clear_type: ClearType,
+        t_spin: Spin,
+        back_to_back: bool,
+        combo: u32,
+    ) -> u32 {
+        let clear_attack = match (clear_type, t_spin) {
+            (ClearType::None, _) => return 0,
+            (ClearType::Single, Spin::Full) => self.t_spin_clears[0],
+            (ClearType::Double, Spin::Full) => self.t_spin_clears[1],
+            (ClearType::Triple, Spin::Full) => self.t_spin_clears[2],
+            (ClearType::Single, Spin::Mini) => self.t_spin_mini_clears[0],
+            (ClearType::Double, Spin::Mini) => self.t_spin_mini_clears[1],
+            (ClearType::Single, _) => self.clears[0],
+            (ClearType::Double, _) => self.clears[1],
+            (ClearType::Triple, _) => self.clears[2],
+            (ClearType::Tetris, _) => self.clears[3],
+        };
+        let back_to_back_attack = if back_to_back {
+            self.back_to_back_bonus
+        } else {
+            0
+        };
+        let combo_attack = match self.combos.last() {
+            Some(longest_combo) => *self.combos.get(combo as usize).unwrap_or(longest_combo),
+            None => 0,
+        };
+        clear_attack + back_to_back_attack + combo_attack
+    }
+}
+
+//the garbage a player is sending and being sent
+pub struct GarbageQueue {
+    //attacks waiting to be put on the board, oldest first
+    pub pending: VecDeque<u32>,
+    //lines sent that nobody has taken yet
+    pub outgoing: u32,
+    //lines sent this game, after cancelling
+    pub lines_sent: u32,
+    //garbage rows put on this player's board this game
+    pub lines_received: u32,
+    //the game stats attack that has been used already
+    attack_counted: u32,
+    //picked when the first garbage comes in, on a board as wide as that game's
+    hole_column: Option<i32>,
+    //seeded so both sides of a match can be played back the same way
+    hole_rng: StdRng,
+}
+
+impl FromWorld for GarbageQueue {
+    fn from_world(world: &mut World) -> Self {
+        GarbageQueue::new(world.resource::<VersusSettings>().match_seed)
+    }
+}
+
+impl GarbageQueue {
+    pub fn new(seed: u64) -> GarbageQueue {
+        GarbageQueue {
+            pending: VecDeque::new(),
+            outgoing: 0,
+            lines_sent: 0,
+            lines_received: 0,
+            attack_counted: 0,
+            hole_column: None,
+            hole_rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn pending_lines(&self) -> u32 {
+        self.pending.iter().sum()
+    }
+
+    pub fn receive(&mut self, lines: u32) {
+        if lines > 0 {
+            self.pending.push_back(lines);
+        }
+    }
+
+    //attack takes garbage off the meter before any of it is sent, oldest garbage first.
+    //returns what's left to send
+    fn cancel(&mut self, mut attack: u32) -> u32 {
+        while attack > 0 {
+            let oldest = match self.pending.front_mut() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            let cancelled = attack.min(*oldest);
+            *oldest -= cancelled;
+            attack -= cancelled;
+            if *oldest == 0 {
+                self.pending.pop_front();
+            }
+        }
+        attack
+    }
+
+    //moves the hole to another column, returning where it is now
+    fn move_hole(&mut self, width: i32) -> i32 {
+        let hole_column = (self.current_hole(width) + self.hole_rng.gen_range(1..width)) % width;
+        self.hole_column = Some(hole_column);
+        hole_column
+    }
+
+    fn current_hole(&mut self, width: i32) -> i32 {
+        match self.hole_column {
+            Some(hole_column) => hole_column,
+            None => {
+                let hole_column = self.hole_rng.gen_range(0..width);
+                self.hole_column = Some(hole_column);
+                hole_column
+            }
+        }
+    }
+
+    //empties the meter into rows for a board this wide, giving the hole column of each, top row
+    //first
+    fn take_rows(&mut self, hole_rule: HoleRule, width: i32) -> Vec<i32> {
+        let mut hole_columns = vec![];
+        while let Some(lines) = self.pending.pop_front() {
+            if hole_rule == HoleRule::PerAttack {
+                self.move_hole(width);
+            }
+            for _ in 0..lines {
+                match hole_rule {
+                    HoleRule::PerLine => {
+                        self.move_hole(width);
+                    }
+                    HoleRule::Messiness(chance) => {
+                        if self.hole_rng.gen_bool(chance as f64) {
+                            self.move_hole(width);
+                        }
+                    }
+                    HoleRule::Fixed | HoleRule::PerAttack => {}
+                }
+                hole_columns.push(self.current_hole(width));
+            }
+        }
+        hole_columns
+    }
+}
+
+//turns the attack of each clear into garbage, cancelling what's on the meter first, and puts
+//the meter on the board once a piece locks without clearing anything
+#[allow(clippy::too_many_arguments)]
+pub fn exchange_garbage(
+    garbage_settings: Res<GarbageSettings>,
+    game_stats: Res<GameStats>,
+    mut garbage_queue: ResMut<GarbageQueue>,
+    mut game_state_info: ResMut<GameStateInfo>,
+    game_play_writer: EventWriter<GamePlayState>,
+    mut piece_locked_reader: EventReader<PieceLockedEvent>,
+    mut board_data: ResMut<BoardData>,
+    mut highest_block_id: ResMut<BlockId>,
+) {
+    let attack = game_stats.attack - garbage_queue.attack_counted;
+    garbage_queue.attack_counted = game_stats.attack;
+    let sent = garbage_queue.cancel(attack);
+    garbage_queue.outgoing += sent;
+    garbage_queue.lines_sent += sent;
+
+    let locked_without_clear = piece_locked_reader
+        .iter()
+        .any(|event| event.lines_cleared == 0);
+    if !locked_without_clear || garbage_queue.pending.is_empty() {
+        return;
+    }
+
+    let width = board_data.size().width;
+    let hole_columns = garbage_queue.take_rows(garbage_settings.hole_rule, width);
+    garbage_queue.lines_received += hole_columns.len() as u32;
+    let fits = board_core::add_garbage_rows(&mut board_data, &mut highest_block_id, &hole_columns);
+    if !fits {
+        game_state_info.change_game_play_state(GamePlayState::Lose, game_play_writer);
+    }
+}
+
+//the bar beside a player's board
+#[derive(Component)]
+pub struct GarbageMeter {
+    pub player: usize,
+    //where the player's board is drawn from the middle of the screen
+    pub offset: Vec3,
+}
+
+pub fn spawn_garbage_meter(commands: &mut Commands, size: BoardSize, player: usize, offset: Vec3) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: GARBAGE_METER_COLOR,
+                anchor: Anchor::BottomCenter,
+                ..default()
+            },
+            transform: Transform {
+                translation: board_core::garbage_meter_position(size, offset),
+                scale: board_core::garbage_meter_scale(0),
+                ..default()
+            },
+            ..default()
+        })
+        .insert(GarbageMeter { player, offset });
+}
+
+pub fn setup_garbage_meter(mut commands: Commands, game_settings: Res<GameSettings>) {
+    spawn_garbage_meter(&mut commands, game_settings.board_size, 0, Vec3::ZERO);
+}
+
+//runs in the simulation, so it sees the queue and board of whichever player's frame it is. the
+//meter follows the board's edge in case a game started on a board of another size
+pub fn update_garbage_meter(
+    clock: Res<SimulationClock>,
+    garbage_queue: Res<GarbageQueue>,
+    board_data: Res<BoardData>,
+    mut meter_query: Query<(&GarbageMeter, &mut Transform)>,
+) {
+    let scale = board_core::garbage_meter_scale(garbage_queue.pending_lines());
+    for (meter, mut transform) in meter_query.iter_mut() {
+        if meter.player != clock.player_pass {
+            continue;
+        }
+        let translation = board_core::garbage_meter_position(board_data.size(), meter.offset);
+        if transform.scale != scale || transform.translation != translation {
+            transform.scale = scale;
+            transform.translation = translation;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: u64 = 37;
+    const WIDTH: i32 = 10;
+
+    fn queue_with(attacks: &[u32]) -> GarbageQueue {
+        let mut queue = GarbageQueue::new(SEED);
+        for &lines in attacks {
+            queue.receive(lines);
+        }
+        queue
+    }
+
+    #[test]
+    fn cancel_takes_the_oldest_garbage_first() {
+        let mut queue = queue_with(&[3, 2, 4]);
+        assert_eq!(queue.cancel(4), 0);
+        assert_eq!(queue.pending, VecDeque::from(vec![1, 4]));
+        assert_eq!(queue.cancel(2), 0);
+        assert_eq!(queue.pending, VecDeque::from(vec![3]));
+        assert_eq!(queue.cancel(5), 2);
+        assert!(queue.pending.is_empty());
+        assert_eq!(queue.cancel(3), 3);
+    }
+
+    #[test]
+    fn empty_attacks_are_not_queued() {
+        let queue = queue_with(&[0, 2, 0]);
+        assert_eq!(queue.pending, VecDeque::from(vec![2]));
+        assert_eq!(queue.pending_lines(), 2);
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_holes() {
+        for hole_rule in [
+            HoleRule::Fixed,
+            HoleRule::PerAttack,
+            HoleRule::PerLine,
+            HoleRule::Messiness(0.3),
+        ] {
+            let first = queue_with(&[2, 3, 1, 4]).take_rows(hole_rule, WIDTH);
+            let second = queue_with(&[2, 3, 1, 4]).take_rows(hole_rule, WIDTH);
+            assert_eq!(first, second, "{:?}", hole_rule);
+            assert_eq!(first.len(), 10);
+            assert!(first.iter().all(|column| (0..WIDTH).contains(column)));
+        }
+    }
+
+    #[test]
+    fn fixed_holes_never_move() {
+        let mut queue = queue_with(&[2, 3]);
+        let mut hole_columns = queue.take_rows(HoleRule::Fixed, WIDTH);
+        queue.receive(4);
+        hole_columns.extend(queue.take_rows(HoleRule::Fixed, WIDTH));
+        assert!(hole_columns.iter().all(|&column| column == hole_columns[0]));
+    }
+
+    #[test]
+    fn per_attack_holes_move_between_attacks() {
+        let hole_columns = queue_with(&[2, 3, 1]).take_rows(HoleRule::PerAttack, WIDTH);
+        assert_eq!(hole_columns[0], hole_columns[1]);
+        assert_eq!(hole_columns[2], hole_columns[3]);
+        assert_eq!(hole_columns[3], hole_columns[4]);
+        assert_ne!(hole_columns[1], hole_columns[2]);
+        assert_ne!(hole_columns[4], hole_columns[5]);
+    }
+
+    #[test]
+    fn per_line_holes_move_every_row() {
+        let hole_columns = queue_with(&[2, 3, 1]).take_rows(HoleRule::PerLine, WIDTH);
+        assert!(hole_columns.windows(2).all(|pair| pair[0] != pair[1]));
+    }
+
+    #[test]
+    fn messiness_goes_from_fixed_to_per_line() {
+        let tidy = queue_with(&[2, 3, 1]).take_rows(HoleRule::Messiness(0.0), WIDTH);
+        assert!(tidy.iter().all(|&column| column == tidy[0]));
+        let messy = queue_with(&[2, 3, 1]).take_rows(HoleRule::Messiness(1.0), WIDTH);
+        assert!(messy.windows(2).all(|pair| pair[0] != pair[1]));
+    }
+
+    #[test]
+    fn hole_rules_are_read_from_arguments() {
+        assert_eq!(HoleRule::from_argument("fixed"), Some(HoleRule::Fixed));
+        assert_eq!(HoleRule::from_argument("attack"), Some(HoleRule::PerAttack));
+        assert_eq!(HoleRule::from_argument("line"), Some(HoleRule::PerLine));
+        assert_eq!(
+            HoleRule::from_argument("0.25"),
+            Some(HoleRule::Messiness(0.25))
+        );
+        assert_eq!(HoleRule::from_argument("1.5"), None);
+        assert_eq!(HoleRule::from_argument("holes"), None);
+    }
+
+    #[test]
+    fn guideline_attack() {
+        let table = AttackTable::default();
+        let attack = |clear_type, spin| table.attack_for_clear(clear_type, spin, false, 0);
+
+        assert_eq!(attack(ClearType::None, Spin::None), 0);
+        assert_eq!(attack(ClearType::Single, Spin::None), 0);
+        assert_eq!(attack(ClearType::Double, Spin::None), 1);
+        assert_eq!(attack(ClearType::Triple, Spin::None), 2);
+        assert_eq!(attack(ClearType::Tetris, Spin::None), 4);
+        assert_eq!(attack(ClearType::Single, Spin::Full), 2);
+        assert_eq!(attack(ClearType::Double, Spin::Full), 4);
+        assert_eq!(attack(ClearType::Triple, Spin::Full), 6);
+        assert_eq!(attack(ClearType::Single, Spin::Mini), 0);
+        assert_eq!(attack(ClearType::Double, Spin::Mini), 1);
+        //nothing is sent without a clear, whatever the chains
+        assert_eq!(
+            table.attack_for_clear(ClearType::None, Spin::Full, true, 5),
+            0
+        );
+    }
+
+    #[test]
+    fn back_to_back_and_combos_add_to_the_attack() {
+        let table = AttackTable::default();
+
+        assert_eq!(
+            table.attack_for_clear(ClearType::Tetris, Spin::None, true, 0),
+            5
+        );
+        assert_eq!(
+            table.attack_for_clear(ClearType::Double, Spin::Full, true, 0),
+            5
+        );
+        let combo_attack: Vec<u32> = (0..14)
+            .map(|combo| table.attack_for_clear(ClearType::Single, Spin::None, false, combo))
+            .collect();
+        assert_eq!(combo_attack, [0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5, 5, 5]);
+        assert_eq!(
+            table.attack_for_clear(ClearType::Double, Spin::Full, true, 4),
+            4 + 1 + 2
+        );
+    }
+
+    #[test]
+    fn preset_attack_tables() {
+        let classic = AttackTable::preset("classic").unwrap();
+        assert_eq!(
+            classic.attack_for_clear(ClearType::Tetris, Spin::None, true, 10),
+            4
+        );
+        assert_eq!(
+            classic.attack_for_clear(ClearType::Double, Spin::Full, false, 0),
+            1
+        );
+
+        let jstris = AttackTable::preset("jstris").unwrap();
+        assert_eq!(
+            jstris.attack_for_clear(ClearType::Single, Spin::None, false, 4),
+            1
+        );
+        assert!(AttackTable::preset("nothing").is_none());
+    }
+}
@@ -0,0 +1,437 @@
+use bevy::prelude::*;
+use bevy::window::ReceivedCharacter;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use crate::game_state_machine::{GamePlayState, GameStateInfo};
+use crate::replay::ReplayPlayer;
+use crate::versus::VersusSettings;
+use crate::{
+    format_time, GameMode, GameSettings, Score, SimulationClock, FONT_ASSET_PATH, TEXT_COLOR,
+};
+
+//bump this whenever the tables change layout. tables from other versions are started over
+pub const HIGH_SCORES_VERSION: u32 = 1;
+pub const APP_DIRECTORY: &str = "tetris_rs";
+const HIGH_SCORES_FILE_NAME: &str = "high_scores.dat";
+
+const TABLE_SIZE: usize = 10;
+const MAX_NAME_LENGTH: usize = 12;
+
+const HIGH_SCORE_FONT_SIZE: f32 = 32.0;
+const HIGH_SCORE_TEXT_LEFT: Val = Val::Px(1250.0);
+const HIGH_SCORE_TEXT_TOP: Val = Val::Px(100.0);
+
+#[derive(Debug)]
+pub enum HighScoresError {
+    Io(io::Error),
+    Format(bincode::Error),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for HighScoresError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HighScoresError::Io(error) => write!(f, "{}", error),
+            HighScoresError::Format(error) => write!(f, "high score file is corrupt: {}", error),
+            HighScoresError::UnsupportedVersion(version) => write!(
+                f,
+                "high scores are from version {}, this build reads version {}",
+                version, HIGH_SCORES_VERSION
+            ),
+        }
+    }
+}
+
+impl From<io::Error> for HighScoresError {
+    fn from(error: io::Error) -> Self {
+        HighScoresError::Io(error)
+    }
+}
+
+impl From<bincode::Error> for HighScoresError {
+    fn from(error: bincode::Error) -> Self {
+        HighScoresError::Format(error)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HighScoreEntry {
+    pub name: String,
+    pub score: u64,
+    pub lines: u32,
+    //how long the game took, which is what sprints are ranked by
+    pub frames: u32,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct HighScoreTables {
+    marathon: Vec<HighScoreEntry>,
+    sprint: Vec<HighScoreEntry>,
+}
+
+//the top scores for every mode, kept in the user's data directory
+pub struct HighScores {
+    tables: HighScoreTables,
+    path: Option<PathBuf>,
+}
+
+impl FromWorld for HighScores {
+    fn from_world(_world: &mut World) -> Self {
+        let path = dirs::data_dir()
+            .map(|directory| directory.join(APP_DIRECTORY).join(HIGH_SCORES_FILE_NAME));
+
+        let tables = match &path {
+            Some(path) if path.exists() => match HighScoreTables::load(path) {
+                Ok(tables) => tables,
+                Err(error) => {
+                    warn!("couldn't load high scores: {}", error);
+                    HighScoreTables::default()
+                }
+            },
+            Some(_) => HighScoreTables::default(),
+            None => {
+                warn!("no user data directory, high scores won't be kept");
+                HighScoreTables::default()
+            }
+        };
+
+        HighScores { tables, path }
+    }
+}
+
+impl HighScoreTables {
+    fn save(&self, path: &Path) -> Result<(), HighScoresError> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        bincode::serialize_into(&mut writer, &HIGH_SCORES_VERSION)?;
+        bincode::serialize_into(&mut writer, self)?;
+        Ok(())
+    }
+
+    fn load(path: &Path) -> Result<HighScoreTables, HighScoresError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let version: u32 = bincode::deserialize_from(&mut reader)?;
+        if version != HIGH_SCORES_VERSION {
+            return Err(HighScoresError::UnsupportedVersion(version));
+        }
+        Ok(bincode::deserialize_from(&mut reader)?)
+    }
+}
+
+impl HighScores {
+    pub fn table(&self, mode: GameMode) -> &Vec<HighScoreEntry> {
+        match mode {
+            GameMode::Marathon => &self.tables.marathon,
+            GameMode::Sprint => &self.tables.sprint,
+        }
+    }
+
+    fn table_mut(&mut self, mode: GameMode) -> &mut Vec<HighScoreEntry> {
+        match mode {
+            GameMode::Marathon => &mut self.tables.marathon,
+            GameMode::Sprint => &mut self.tables.sprint,
+        }
+    }
+
+    //true when the entry would make it onto the mode's table
+    pub fn qualifies(&self, mode: GameMode, entry: &HighScoreEntry) -> bool {
+        let table = self.table(mode);
+        match table.last() {
+            Some(last) => table.len() < TABLE_SIZE || ranks_above(mode, entry, last),
+            None => true,
+        }
+    }
+
+    pub fn insert(&mut self, mode: GameMode, entry: HighScoreEntry) {
+        let table = self.table_mut(mode);
+        let position = table
+            .iter()
+            .position(|existing| ranks_above(mode, &entry, existing))
+            .unwrap_or(table.len());
+        table.insert(position, entry);
+        table.truncate(TABLE_SIZE);
+
+        if let Some(path) = &self.path {
+            if let Err(error) = self.tables.save(path) {
+                warn!("couldn't save high scores: {}", error);
+            }
+        }
+    }
+}
+
+//ties keep the older entry in front
+fn ranks_above(mode: GameMode, entry: &HighScoreEntry, other: &HighScoreEntry) -> bool {
+    match mode {
+        GameMode::Marathon => entry.score > other.score,
+        GameMode::Sprint => entry.frames < other.frames,
+    }
+}
+
+//what the high score text is showing on top of the game
+pub enum HighScoreScreen {
+    Hidden,
+    Table,
+    NameEntry(HighScoreEntry),
+}
+
+impl FromWorld for HighScoreScreen {
+    fn from_world(_world: &mut World) -> Self {
+        HighScoreScreen::Hidden
+    }
+}
+
+#[derive(Component)]
+pub struct HighScoreText {}
+
+pub fn setup_high_score_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load(FONT_ASSET_PATH),
+                    font_size: HIGH_SCORE_FONT_SIZE,
+                    color: TEXT_COLOR,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: HIGH_SCORE_TEXT_TOP,
+                    left: HIGH_SCORE_TEXT_LEFT,
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(HighScoreText {});
+}
+
+//m changes the mode the next game is played in and h shows or hides its high scores
+pub fn handle_menu_input(
+    keyboard_input: Res<Input<KeyCode>>,
+    game_state: Res<GameStateInfo>,
+    versus_settings: Res<VersusSettings>,
+    mut game_settings: ResMut<GameSettings>,
+    mut screen: ResMut<HighScoreScreen>,
+) {
+    if game_state.game_state != GamePlayState::Menu {
+        return;
+    }
+
+    //in netplay the host picked the mode for both sides
+    if keyboard_input.just_pressed(KeyCode::M) && !versus_settings.lockstep {
+        game_settings.game_mode = game_settings.game_mode.next();
+    }
+    if keyboard_input.just_pressed(KeyCode::H) {
+        *screen = match *screen {
+            HighScoreScreen::Hidden => HighScoreScreen::Table,
+            _ => HighScoreScreen::Hidden,
+        };
+    }
+}
+
+//once a game is over, asks for a name if it made the table, otherwise shows the table.
+//a sprint only counts if it was finished, and versus matches never do
+#[allow(clippy::too_many_arguments)]
+pub fn check_for_high_score(
+    game_state: Res<GameStateInfo>,
+    game_settings: Res<GameSettings>,
+    replay_player: Res<ReplayPlayer>,
+    versus_settings: Res<VersusSettings>,
+    score: Res<Score>,
+    clock: Res<SimulationClock>,
+    high_scores: Res<HighScores>,
+    mut screen: ResMut<HighScoreScreen>,
+) {
+    let game_over =
+        game_state.game_state == GamePlayState::Win || game_state.game_state == GamePlayState::Lose;
+    if !game_over || replay_player.is_playing() || !matches!(*screen, HighScoreScreen::Hidden) {
+        return;
+    }
+
+    let mode = game_settings.game_mode;
+    let entry = HighScoreEntry {
+        name: String::new(),
+        score: score.score,
+        lines: score.lines_cleared,
+        frames: clock.frame,
+    };
+    let finished = mode != GameMode::Sprint || game_state.game_state == GamePlayState::Win;
+
+    *screen = if finished && !versus_settings.enabled && high_scores.qualifies(mode, &entry) {
+        HighScoreScreen::NameEntry(entry)
+    } else {
+        HighScoreScreen::Table
+    };
+}
+
+//typed characters go into the name, backspace removes one and enter puts it on the table
+pub fn handle_name_entry(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut character_reader: EventReader<ReceivedCharacter>,
+    game_settings: Res<GameSettings>,
+    mut high_scores: ResMut<HighScores>,
+    mut screen: ResMut<HighScoreScreen>,
+) {
+    let entry = match &mut *screen {
+        HighScoreScreen::NameEntry(entry) => entry,
+        _ => {
+            character_reader.clear();
+            return;
+        }
+    };
+
+    for character in character_reader.iter() {
+        let character = character.char;
+        if (character.is_alphanumeric() || character == ' ')
+            && entry.name.chars().count() < MAX_NAME_LENGTH
+        {
+            entry.name.push(character);
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Back) {
+        entry.name.pop();
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Return) {
+        let mut entry = entry.clone();
+        let trimmed_name = entry.name.trim();
+        entry.name = if trimmed_name.is_empty() {
+            "PLAYER".to_string()
+        } else {
+            trimmed_name.to_string()
+        };
+        high_scores.insert(game_settings.game_mode, entry);
+        *screen = HighScoreScreen::Table;
+    }
+}
+
+//enter on the table after a game goes back to the menu. runs before name entry so the enter
+//that confirms a name doesn't also leave. netplay goes straight to the next match instead
+pub fn leave_finished_game(world: &mut World) {
+    let game_state = &world.resource::<GameStateInfo>().game_state;
+    let game_over = *game_state == GamePlayState::Win || *game_state == GamePlayState::Lose;
+    if !game_over
+        || world.resource::<VersusSettings>().lockstep
+        || !matches!(*world.resource::<HighScoreScreen>(), HighScoreScreen::Table)
+        || !world
+            .resource::<Input<KeyCode>>()
+            .just_pressed(KeyCode::Return)
+    {
+        return;
+    }
+
+    crate::reset_game(world);
+}
+
+fn table_text(mode: GameMode, table: &[HighScoreEntry]) -> String {
+    let mut text = format!("{} HIGH SCORES\n", mode.name());
+    if table.is_empty() {
+        text.push_str("no scores yet\n");
+    }
+    for (rank, entry) in table.iter().enumerate() {
+        let result = match mode {
+            GameMode::Marathon => format!("{}  {} lines", entry.score, entry.lines),
+            GameMode::Sprint => format_time(entry.frames),
+        };
+        text.push_str(&format!("{:>2}. {:<12} {}\n", rank + 1, entry.name, result));
+    }
+    text
+}
+
+pub fn update_high_score_text(
+    game_state: Res<GameStateInfo>,
+    game_settings: Res<GameSettings>,
+    replay_player: Res<ReplayPlayer>,
+    versus_settings: Res<VersusSettings>,
+    high_scores: Res<HighScores>,
+    screen: Res<HighScoreScreen>,
+    mut text_query: Query<&mut Text, With<HighScoreText>>,
+) {
+    let mode = game_settings.game_mode;
+    let text = if replay_player.is_playing() || game_state.game_state == GamePlayState::Playing {
+        String::new()
+    } else {
+        match &*screen {
+            HighScoreScreen::NameEntry(entry) => {
+                format!("NEW HIGH SCORE\nENTER YOUR NAME\n{}_", entry.name)
+            }
+            HighScoreScreen::Table if game_state.game_state == GamePlayState::Menu => {
+                format!("{}\nH to close", table_text(mode, high_scores.table(mode)))
+            }
+            HighScoreScreen::Table if versus_settings.lockstep => {
+                "VERSUS OVER\n\nSPACE for a rematch".to_string()
+            }
+            HighScoreScreen::Table if versus_settings.enabled => {
+                "VERSUS OVER\n\nENTER for menu".to_string()
+            }
+            HighScoreScreen::Table => format!(
+                "GAME OVER\n\n{}\nENTER for menu",
+                table_text(mode, high_scores.table(mode))
+            ),
+            HighScoreScreen::Hidden if versus_settings.enabled => {
+                "VERSUS\nSPACE to start".to_string()
+            }
+            HighScoreScreen::Hidden => format!(
+                "{}\nM to change mode\nSPACE to start\nH for high scores",
+                mode.name()
+            ),
+        }
+    };
+
+    let mut high_score_text = text_query.single_mut();
+    if high_score_text.sections[0].value != text {
+        high_score_text.sections[0].value = text;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{headless_app, run_frames, set_key};
+
+    #[test]
+    fn a_game_started_with_the_table_open_still_asks_for_a_name() {
+        let mut app = headless_app();
+        app.insert_resource(HighScores {
+            tables: HighScoreTables::default(),
+            path: None,
+        })
+        .add_system(handle_menu_input)
+        .add_system(check_for_high_score);
+
+        set_key(&mut app, KeyCode::H, true);
+        run_frames(&mut app, 1);
+        set_key(&mut app, KeyCode::H, false);
+        assert!(matches!(
+            *app.world.resource::<HighScoreScreen>(),
+            HighScoreScreen::Table
+        ));
+
+        set_key(&mut app, KeyCode::Space, true);
+        run_frames(&mut app, 1);
+        set_key(&mut app, KeyCode::Space, false);
+        assert_eq!(
+            app.world.resource::<GameStateInfo>().game_state,
+            GamePlayState::Playing
+        );
+        assert!(matches!(
+            *app.world.resource::<HighScoreScreen>(),
+            HighScoreScreen::Hidden
+        ));
+
+        app.world.resource_mut::<GameStateInfo>().game_state = GamePlayState::Lose;
+        run_frames(&mut app, 1);
+        assert!(matches!(
+            *app.world.resource::<HighScoreScreen>(),
+            HighScoreScreen::NameEntry(_)
+        ));
+    }
+}
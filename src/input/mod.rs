@@ -0,0 +1,118 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+//gameplay actions, separate from the keys that trigger them so the simulation only ever sees
+//actions. this is what replays record and play back
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GameAction {
+    MoveLeft,
+    MoveRight,
+    SoftDrop,
+    RotateClockwise,
+    RotateCounterClockwise,
+    Rotate180,
+    HardDrop,
+    Hold,
+}
+
+impl GameAction {
+    pub const ALL: [GameAction; 8] = [
+        GameAction::MoveLeft,
+        GameAction::MoveRight,
+        GameAction::SoftDrop,
+        GameAction::RotateClockwise,
+        GameAction::RotateCounterClockwise,
+        GameAction::Rotate180,
+        GameAction::HardDrop,
+        GameAction::Hold,
+    ];
+}
+
+//where the simulation gets its actions from
+#[derive(Debug, PartialEq, Eq)]
+pub enum InputSource {
+    Keyboard,
+    Replay,
+    Bot,
+    Ai,
+    //both players in netplay, with the keys sent between the two games
+    Network,
+}
+
+impl FromWorld for InputSource {
+    fn from_world(_world: &mut World) -> Self {
+        InputSource::Keyboard
+    }
+}
+
+pub struct KeyBindings {
+    bindings: Vec<(KeyCode, GameAction)>,
+}
+
+impl FromWorld for KeyBindings {
+    fn from_world(_world: &mut World) -> Self {
+        KeyBindings {
+            bindings: vec![
+                (KeyCode::A, GameAction::MoveLeft),
+                (KeyCode::D, GameAction::MoveRight),
+                (KeyCode::S, GameAction::SoftDrop),
+                (KeyCode::K, GameAction::RotateClockwise),
+                (KeyCode::J, GameAction::RotateCounterClockwise),
+                (KeyCode::L, GameAction::Rotate180),
+                (KeyCode::Space, GameAction::HardDrop),
+                (KeyCode::LShift, GameAction::Hold),
+                (KeyCode::C, GameAction::Hold),
+            ],
+        }
+    }
+}
+
+impl KeyBindings {
+    //the second player's keys in versus, on the other side of the keyboard
+    pub fn arrows() -> KeyBindings {
+        KeyBindings {
+            bindings: vec![
+                (KeyCode::Left, GameAction::MoveLeft),
+                (KeyCode::Right, GameAction::MoveRight),
+                (KeyCode::Down, GameAction::SoftDrop),
+                (KeyCode::Period, GameAction::RotateClockwise),
+                (KeyCode::Comma, GameAction::RotateCounterClockwise),
+                (KeyCode::Slash, GameAction::Rotate180),
+                (KeyCode::Up, GameAction::HardDrop),
+                (KeyCode::RShift, GameAction::Hold),
+            ],
+        }
+    }
+
+    pub fn action_held(&self, keyboard_input: &Input<KeyCode>, action: GameAction) -> bool {
+        self.bindings
+            .iter()
+            .any(|(key, bound_action)| *bound_action == action && keyboard_input.pressed(*key))
+    }
+}
+
+//clears last frame's just pressed/released actions. runs at the start of every simulation frame
+pub fn clear_game_input(mut game_input: ResMut<Input<GameAction>>) {
+    game_input.clear();
+}
+
+//samples the keyboard once per simulation frame so the actions line up with the frames they happen on
+pub fn read_keyboard_input(
+    keyboard_input: Res<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    input_source: Res<InputSource>,
+    mut game_input: ResMut<Input<GameAction>>,
+) {
+    if *input_source != InputSource::Keyboard {
+        return;
+    }
+
+    for action in GameAction::ALL {
+        let held = key_bindings.action_held(&keyboard_input, action);
+        if held && !game_input.pressed(action) {
+            game_input.press(action);
+        } else if !held && game_input.pressed(action) {
+            game_input.release(action);
+        }
+    }
+}
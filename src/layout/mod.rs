@@ -0,0 +1,228 @@
+use bevy::prelude::*;
+use bevy::render::camera::ScalingMode;
+use bevy::window::{WindowCloseRequested, WindowMode, WindowResizeConstraints, WindowResized};
+
+use crate::board::board_core::BoardSize;
+use crate::settings::{Settings, UserSettings};
+use crate::GameSettings;
+
+//the board and the hud are laid out for a window this size, then scaled to fit the real one.
+//whatever the aspect ratio, all of it stays on screen with the leftover space split evenly
+//around it
+pub const LAYOUT_WIDTH: f32 = 1920.;
+pub const LAYOUT_HEIGHT: f32 = 1080.;
+
+//windows smaller than this can't be read, so the settings never open one
+const MIN_WINDOW_WIDTH: f32 = 640.;
+const MIN_WINDOW_HEIGHT: f32 = 360.;
+
+//how long the window has to keep the same size before the size is saved
+const WINDOW_SIZE_SETTLE_SECONDS: f64 = 0.5;
+
+//the window the settings ask for when the game starts
+pub fn window_descriptor(settings: &Settings) -> WindowDescriptor {
+    WindowDescriptor {
+        title: "tetris.rs".to_string(),
+        width: settings.window_width.max(MIN_WINDOW_WIDTH),
+        height: settings.window_height.max(MIN_WINDOW_HEIGHT),
+        resize_constraints: WindowResizeConstraints {
+            min_width: MIN_WINDOW_WIDTH,
+            min_height: MIN_WINDOW_HEIGHT,
+            ..default()
+        },
+        mode: window_mode(settings.fullscreen),
+        ..default()
+    }
+}
+
+fn window_mode(fullscreen: bool) -> WindowMode {
+    if fullscreen {
+        WindowMode::BorderlessFullscreen
+    } else {
+        WindowMode::Windowed
+    }
+}
+
+//a camera that always shows the whole layout, however big the window is
+pub fn layout_camera() -> Camera2dBundle {
+    let mut camera = Camera2dBundle::default();
+    camera.projection.scaling_mode = ScalingMode::Auto {
+        min_width: LAYOUT_WIDTH,
+        min_height: LAYOUT_HEIGHT,
+    };
+    camera
+}
+
+//how far the camera zooms out so a board bigger than the usual one still fits
+fn board_zoom(size: BoardSize) -> f32 {
+    let board = size.pixels();
+    let usual = BoardSize::default().pixels();
+    (board.x / usual.x).max(board.y / usual.y).max(1.)
+}
+
+//a board bigger than the usual one zooms the camera out so it still fits, with everything around
+//it kept the same size next to it. the hud zooms out along with it
+pub fn fit_camera_to_board(
+    game_settings: Res<GameSettings>,
+    mut projection_query: Query<&mut OrthographicProjection, With<Camera2d>>,
+) {
+    if !game_settings.is_changed() {
+        return;
+    }
+
+    let zoom = board_zoom(game_settings.board_size);
+    for mut projection in projection_query.iter_mut() {
+        projection.scaling_mode = ScalingMode::Auto {
+            min_width: LAYOUT_WIDTH * zoom,
+            min_height: LAYOUT_HEIGHT * zoom,
+        };
+    }
+}
+
+//how the layout fits in a window: how much bigger it's drawn and where its top left corner is.
+//when the camera zooms out for a big board the layout is drawn that much smaller
+struct LayoutFit {
+    scale: f32,
+    left: f32,
+    top: f32,
+}
+
+impl LayoutFit {
+    fn new(window_width: f32, window_height: f32, zoom: f32) -> LayoutFit {
+        let scale = (window_width / LAYOUT_WIDTH).min(window_height / LAYOUT_HEIGHT) / zoom;
+        LayoutFit {
+            scale,
+            left: (window_width - LAYOUT_WIDTH * scale) / 2.,
+            top: (window_height - LAYOUT_HEIGHT * scale) / 2.,
+        }
+    }
+
+    fn place(&self, value: Val, corner: f32) -> Val {
+        match value {
+            Val::Px(pixels) => Val::Px(corner + pixels * self.scale),
+            other => other,
+        }
+    }
+}
+
+//where a piece of the hud sits and how big its text is in the layout, before any scaling
+#[derive(Component)]
+pub struct HudLayout {
+    position: UiRect<Val>,
+    font_sizes: Vec<f32>,
+}
+
+impl HudLayout {
+    fn apply(&self, fit: &LayoutFit, style: &mut Style, text: Option<Mut<Text>>) {
+        style.position = UiRect {
+            left: fit.place(self.position.left, fit.left),
+            top: fit.place(self.position.top, fit.top),
+            ..self.position
+        };
+        if let Some(mut text) = text {
+            for (section, font_size) in text.sections.iter_mut().zip(&self.font_sizes) {
+                section.style.font_size = font_size * fit.scale;
+            }
+        }
+    }
+}
+
+//top level ui nodes that haven't been scaled yet
+type NewHudFilter = (With<Node>, Without<HudLayout>, Without<Parent>);
+
+//the hud is positioned in layout pixels like the board. each piece of it is scaled along with
+//the board as it's spawned and again whenever the window or the board changes size
+pub fn scale_hud(
+    mut commands: Commands,
+    windows: Res<Windows>,
+    game_settings: Res<GameSettings>,
+    mut resized_reader: EventReader<WindowResized>,
+    mut new_node_query: Query<(Entity, &mut Style, Option<&mut Text>), NewHudFilter>,
+    mut node_query: Query<(&HudLayout, &mut Style, Option<&mut Text>)>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let zoom = board_zoom(game_settings.board_size);
+    let fit = LayoutFit::new(window.width(), window.height(), zoom);
+
+    for (entity, mut style, text) in new_node_query.iter_mut() {
+        let layout = HudLayout {
+            position: style.position,
+            font_sizes: text
+                .as_ref()
+                .map(|text| {
+                    text.sections
+                        .iter()
+                        .map(|section| section.style.font_size)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        };
+        layout.apply(&fit, &mut style, text);
+        commands.entity(entity).insert(layout);
+    }
+
+    let resized = resized_reader.iter().last().is_some();
+    if !(resized || game_settings.is_changed()) {
+        return;
+    }
+    for (layout, mut style, text) in node_query.iter_mut() {
+        layout.apply(&fit, &mut style, text);
+    }
+}
+
+//f11 switches between a window and fullscreen, and it's kept for next time
+pub fn handle_fullscreen_input(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut windows: ResMut<Windows>,
+    mut user_settings: ResMut<UserSettings>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::F11) {
+        return;
+    }
+    let window = match windows.get_primary_mut() {
+        Some(window) => window,
+        None => return,
+    };
+
+    let fullscreen = !user_settings.settings.fullscreen;
+    window.set_mode(window_mode(fullscreen));
+    user_settings.settings.fullscreen = fullscreen;
+    user_settings.save();
+}
+
+//remembers the size the window was left at so it opens the same way next time. dragging the
+//window's edge resizes it every frame, so the size is only saved once it has settled, or when the
+//window closes. going fullscreen resizes the window too, which isn't kept
+pub fn remember_window_size(
+    time: Res<Time>,
+    mut resized_reader: EventReader<WindowResized>,
+    mut close_reader: EventReader<WindowCloseRequested>,
+    windows: Res<Windows>,
+    mut unsaved_size: Local<Option<(Vec2, f64)>>,
+    mut user_settings: ResMut<UserSettings>,
+) {
+    let now = time.seconds_since_startup();
+    if let Some(resized) = resized_reader.iter().last() {
+        let windowed = windows
+            .get(resized.id)
+            .is_some_and(|window| window.mode() == WindowMode::Windowed);
+        if windowed && !user_settings.settings.fullscreen {
+            *unsaved_size = Some((Vec2::new(resized.width, resized.height), now));
+        }
+    }
+
+    let closing = close_reader.iter().count() > 0;
+    let size = match *unsaved_size {
+        Some((size, resized_at)) if closing || now - resized_at >= WINDOW_SIZE_SETTLE_SECONDS => {
+            size
+        }
+        _ => return,
+    };
+    *unsaved_size = None;
+    user_settings.settings.window_width = size.x;
+    user_settings.settings.window_height = size.y;
+    user_settings.save();
+}
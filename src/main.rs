@@ -1,20 +1,50 @@
+use bevy::ecs::schedule::ShouldRun;
+use bevy::input::InputSystem;
 use bevy::prelude::*;
-use bevy::time::FixedTimestep;
+use bevy::ui::UiSystem;
 use bevy::utils::tracing::event;
-use bevy::window::{close_on_esc, WindowMode};
-use rand::Rng;
+use bevy::window::close_on_esc;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 
+pub mod ai;
 pub mod board;
+pub mod finesse;
 pub mod game_state_machine;
+pub mod garbage;
+pub mod high_scores;
+pub mod input;
+pub mod layout;
+pub mod netplay;
+pub mod piece_queue;
+pub mod replay;
+pub mod save_game;
+pub mod settings;
+pub mod sound;
+pub mod spectator;
+pub mod stats;
+pub mod tbp;
+pub mod theme;
+pub mod versus;
 
 use crate::board::board_core;
+use crate::board::fumen::{self, FumenPage};
+use crate::board::move_gen::Spin;
 use crate::board_core::{
-    BlockID, BlockId, BoardData, BoardPointCoordinates, CurrentPlayerControlled, PieceType,
+    BlockId, BoardData, BoardSize, LineClearGravity, PieceType, RotationDirection,
 };
 use crate::game_state_machine::{GameFlow, GamePlayState, GameStateInfo};
+use crate::input::{GameAction, InputSource, KeyBindings};
+use crate::replay::{ReplayPlayer, ReplayRecorder};
+use crate::sound::SoundEffect;
 
-const TIME_STEP: f32 = 1.0 / 60.0;
-const BLOCK_FALL_SPEED_UP: f32 = 0.1;
+//the game simulates in whole frames at this rate no matter how fast it's drawn
+const FRAMES_PER_SECOND: u32 = 60;
+const TIME_STEP: f32 = 1.0 / FRAMES_PER_SECOND as f32;
+//how many frames a replay seek can simulate before letting the app draw again
+const MAX_FRAMES_PER_UPDATE: u32 = 600;
+const BLOCK_FALL_SPEED_UP: u32 = 6;
 const FONT_ASSET_PATH: &str = ("OpenSans-Regular.ttf");
 
 const SCOREBOARD_FONT_SIZE: f32 = 40.0;
@@ -25,13 +55,23 @@ const TEXT_COLOR: Color = Color::rgb(0.5, 0.5, 1.0);
 static CHECK_BLOCKS: &str = "check_blocks";
 
 const SCORE_AMOUNT: u64 = 100;
+//lines a sprint has to clear to finish
+const SPRINT_LINES: u32 = 40;
+//lines cleared for each level up
+const LINES_PER_LEVEL: u32 = 10;
+const PAUSED_TEXT: &str = "  PAUSED";
 
 struct SpawnController {
     spawn_piece: bool,
+    //frames of the entry delay still to wait before the next piece spawns
+    entry_delay_left: u32,
 }
 impl FromWorld for SpawnController {
     fn from_world(world: &mut World) -> Self {
-        SpawnController { spawn_piece: true }
+        SpawnController {
+            spawn_piece: true,
+            entry_delay_left: 0,
+        }
     }
 }
 
@@ -39,25 +79,45 @@ impl FromWorld for SpawnController {
 pub struct ScoreEvent {
     score: u64,
 }
-struct Score {
+
+//sent every time the player's piece locks, after full rows are cleared
+pub struct PieceLockedEvent {
+    pub lines_cleared: u32,
+    pub piece_type: Option<PieceType>,
+    //where the piece's blocks were when it locked
+    pub cells: Vec<IVec2>,
+    //nothing was above the piece, so it could have been dropped straight there
+    pub dropped_straight: bool,
+    pub spin: Spin,
+    //1 for the piece locking, then one more for each clear a cascade makes after it. those
+    //clears come without a piece
+    pub chain: u32,
+}
+pub struct Score {
     score: u64,
+    lines_cleared: u32,
+    //how many clears in a row a cascade has made, the score for a clear is multiplied by it
+    chain: u32,
 }
 #[derive(Component)]
 struct ScoreText {}
 
 impl FromWorld for Score {
     fn from_world(world: &mut World) -> Self {
-        Score { score: 0 }
+        Score {
+            score: 0,
+            lines_cleared: 0,
+            chain: 1,
+        }
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 struct InputController {
     slow_hold_move_finished: bool,
     are_holding_down: bool,
     can_move: bool,
-    time_between_moves_default: f32,
-    time_between_moves_held_down: f32,
-    time_till_next_move: f32,
+    frames_since_last_move: u32,
 }
 impl FromWorld for InputController {
     fn from_world(world: &mut World) -> Self {
@@ -65,44 +125,179 @@ impl FromWorld for InputController {
             slow_hold_move_finished: false,
             are_holding_down: false,
             can_move: true,
-            time_between_moves_default: 0.2,
-            time_between_moves_held_down: 0.05,
-            time_till_next_move: 0.0,
+            frames_since_last_move: 0,
         }
     }
 }
 
-struct GameSettings {
+//how the controls feel, in frames. saved into replays so they play back with the same timings
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HandlingSettings {
+    //frames a direction has to be held before it starts repeating
+    delayed_auto_shift: u32,
+    //frames between repeated moves once it has
+    auto_repeat_rate: u32,
+    soft_drop_frames_between_ticks: u32,
+}
+impl FromWorld for HandlingSettings {
+    fn from_world(_world: &mut World) -> Self {
+        HandlingSettings {
+            delayed_auto_shift: 12,
+            auto_repeat_rate: 3,
+            soft_drop_frames_between_ticks: BLOCK_FALL_SPEED_UP,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GameSettings {
+    game_mode: GameMode,
     game_randomizer: PieceRandomizerType,
+    //frames a piece can rest on something before it locks
+    lock_delay: u32,
+    //how many times moving a resting piece can restart its lock delay
+    max_lock_resets: u32,
+    line_clear_gravity: LineClearGravity,
+    //frames full rows flash before they're cleared
+    line_clear_delay: u32,
+    //frames between a piece locking, or its rows clearing, and the next piece spawning
+    entry_delay: u32,
+    board_size: BoardSize,
+}
+
+//how long the player's piece has been resting on something
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LockController {
+    frames_grounded: u32,
+    lock_resets: u32,
+    //the spin the piece locks with if nothing moves it before then. only rotations set it
+    spin: Spin,
+}
+impl FromWorld for LockController {
+    fn from_world(_world: &mut World) -> Self {
+        LockController {
+            frames_grounded: 0,
+            lock_resets: 0,
+            spin: Spin::None,
+        }
+    }
+}
+
+//what ends a game and how it's ranked on the high score tables
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameMode {
+    //plays until the stack reaches the top, ranked by score
+    Marathon,
+    //clears SPRINT_LINES lines as fast as possible, ranked by time
+    Sprint,
+}
+
+impl GameMode {
+    pub fn name(&self) -> &str {
+        match self {
+            GameMode::Marathon => "MARATHON",
+            GameMode::Sprint => "SPRINT",
+        }
+    }
+
+    pub fn next(&self) -> GameMode {
+        match self {
+            GameMode::Marathon => GameMode::Sprint,
+            GameMode::Sprint => GameMode::Marathon,
+        }
+    }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 enum PieceRandomizerType {
     Bag,
     TrueRandom,
     TrueRandomWithoutRepeats,
 }
 
+//--gravity naive, sticky or cascade picks how the stack settles after a clear.
+//--line-clear-delay and --entry-delay set those delays in frames
 impl FromWorld for GameSettings {
     fn from_world(world: &mut World) -> Self {
+        let arguments: Vec<String> = std::env::args().collect();
+        let line_clear_gravity = match arguments
+            .iter()
+            .position(|argument| argument == "--gravity")
+        {
+            Some(index) => match arguments
+                .get(index + 1)
+                .and_then(|argument| LineClearGravity::from_argument(argument))
+            {
+                Some(line_clear_gravity) => line_clear_gravity,
+                None => {
+                    warn!("--gravity needs naive, sticky or cascade");
+                    LineClearGravity::Naive
+                }
+            },
+            None => LineClearGravity::Naive,
+        };
+
         GameSettings {
+            game_mode: GameMode::Marathon,
             game_randomizer: PieceRandomizerType::Bag,
+            lock_delay: 30,
+            max_lock_resets: 15,
+            line_clear_gravity,
+            line_clear_delay: frames_from_arguments(&arguments, "--line-clear-delay", 20),
+            entry_delay: frames_from_arguments(&arguments, "--entry-delay", 6),
+            board_size: BoardSize::from_arguments(&arguments),
+        }
+    }
+}
+
+//a number of frames given after the flag, or the default when the flag isn't there
+fn frames_from_arguments(arguments: &[String], flag: &str, default: u32) -> u32 {
+    let index = match arguments.iter().position(|argument| argument == flag) {
+        Some(index) => index,
+        None => return default,
+    };
+    match arguments.get(index + 1).map(|frames| frames.parse::<u32>()) {
+        Some(Ok(frames)) => frames,
+        _ => {
+            warn!("{} needs a number of frames", flag);
+            default
         }
     }
 }
 
-struct Randomizer {
+//seeded so a game can be replayed with the same pieces
+pub struct Randomizer {
+    seed: u64,
+    rng: StdRng,
     current_bag: Vec<PieceType>,
+    pieces_dealt: u64,
 }
 
 impl FromWorld for Randomizer {
-    fn from_world(world: &mut World) -> Self {
+    fn from_world(_world: &mut World) -> Self {
+        Randomizer::new(rand::thread_rng().gen())
+    }
+}
+
+impl Randomizer {
+    fn new(seed: u64) -> Randomizer {
         Randomizer {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
             current_bag: Randomizer::new_bag(),
+            pieces_dealt: 0,
         }
     }
-}
 
-impl Randomizer {
+    //rebuilds a randomizer partway through a game by dealing the same pieces again
+    fn resume(seed: u64, pieces_dealt: u64) -> Randomizer {
+        let mut randomizer = Randomizer::new(seed);
+        for _ in 0..pieces_dealt {
+            randomizer.next_block();
+        }
+        randomizer
+    }
+
     //creates a new standard 7 set bag
     fn new_bag() -> Vec<PieceType> {
         let new_bag = vec![
@@ -119,49 +314,180 @@ impl Randomizer {
 
     //returns a random piece type from the current bag
     fn next_block(&mut self) -> PieceType {
-        let mut rng = rand::thread_rng();
-        let index = rng.gen_range(0..self.current_bag.len());
+        let index = self.rng.gen_range(0..self.current_bag.len());
         let piece_to_return: PieceType = self.current_bag[index];
         self.current_bag.remove(index);
         if self.current_bag.len() == 0 {
             self.current_bag = Randomizer::new_bag();
         }
+        self.pieces_dealt += 1;
         piece_to_return
     }
 }
 
+//fumen pages passed in on the command line with --fumen, and pages captured from the board to share
+struct FumenSequence {
+    pages: Vec<FumenPage>,
+    current_page: usize,
+    load_requested: bool,
+    captured_pages: Vec<FumenPage>,
+}
+
+impl FromWorld for FumenSequence {
+    fn from_world(_world: &mut World) -> Self {
+        let arguments: Vec<String> = std::env::args().collect();
+        let mut pages = vec![];
+        if let Some(index) = arguments.iter().position(|argument| argument == "--fumen") {
+            match arguments.get(index + 1) {
+                Some(data) => match fumen::decode(data) {
+                    Ok(decoded_pages) => {
+                        info!("loaded {} fumen pages", decoded_pages.len());
+                        pages = decoded_pages;
+                    }
+                    Err(error) => warn!("couldn't load fumen {}: {}", data, error),
+                },
+                None => warn!("--fumen needs fumen data to load"),
+            }
+        }
+
+        FumenSequence {
+            load_requested: !pages.is_empty(),
+            pages,
+            current_page: 0,
+            captured_pages: vec![],
+        }
+    }
+}
+
+//runs the gameplay systems once per fixed frame. replays change the speed, pause it, or seek to
+//a frame by running as many frames as it takes in one update
+pub struct SimulationClock {
+    //frames since the current game started
+    frame: u32,
+    speed: f32,
+    paused: bool,
+    seek_target: Option<u32>,
+    accumulator: f32,
+    looping: bool,
+    frames_this_update: u32,
+    //versus runs every frame once for each player
+    players: usize,
+    player_pass: usize,
+}
+impl FromWorld for SimulationClock {
+    fn from_world(_world: &mut World) -> Self {
+        SimulationClock {
+            frame: 0,
+            speed: 1.0,
+            paused: false,
+            seek_target: None,
+            accumulator: 0.0,
+            looping: false,
+            frames_this_update: 0,
+            players: 1,
+            player_pass: 0,
+        }
+    }
+}
+
+fn run_simulation_frame(
+    time: Res<Time>,
+    replay_player: Res<ReplayPlayer>,
+    game_state: Res<GameStateInfo>,
+    netplay: Res<netplay::Netplay>,
+    mut clock: ResMut<SimulationClock>,
+) -> ShouldRun {
+    if !clock.looping {
+        clock.frames_this_update = 0;
+        if !clock.paused {
+            clock.accumulator += time.delta_seconds() * clock.speed;
+        }
+    }
+
+    //the rest of the players get the frame the first player just had
+    if clock.looping && clock.player_pass + 1 < clock.players {
+        clock.player_pass += 1;
+        return ShouldRun::YesAndCheckAgain;
+    }
+    clock.player_pass = 0;
+
+    if let Some(target) = clock.seek_target {
+        //the frame stops counting once the game is over, so there's nothing more to catch up on
+        let catching_up = (clock.frame < target && game_state.game_state == GamePlayState::Playing)
+            || replay_player.restart_requested();
+        if catching_up && clock.frames_this_update < MAX_FRAMES_PER_UPDATE {
+            clock.frames_this_update += 1;
+            clock.looping = true;
+            return ShouldRun::YesAndCheckAgain;
+        }
+        if !catching_up {
+            clock.seek_target = None;
+            clock.accumulator = 0.0;
+        }
+        clock.looping = false;
+        return ShouldRun::No;
+    }
+
+    //netplay waits for the opponent's keys without saving up frames to rush through after
+    if !netplay.frame_ready() {
+        clock.accumulator = clock.accumulator.min(TIME_STEP);
+    }
+    if !clock.paused && clock.accumulator >= TIME_STEP && netplay.frame_ready() {
+        clock.accumulator -= TIME_STEP;
+        clock.looping = true;
+        ShouldRun::YesAndCheckAgain
+    } else {
+        clock.looping = false;
+        ShouldRun::No
+    }
+}
+
+//frames as minutes:seconds.hundredths
+pub fn format_time(frames: u32) -> String {
+    let seconds = frames / FRAMES_PER_SECOND;
+    let hundredths = (frames % FRAMES_PER_SECOND) * 100 / FRAMES_PER_SECOND;
+    format!("{}:{:02}.{:02}", seconds / 60, seconds % 60, hundredths)
+}
+
+fn advance_simulation_frame(game_state: Res<GameStateInfo>, mut clock: ResMut<SimulationClock>) {
+    if game_state.game_state == GamePlayState::Playing && clock.player_pass + 1 == clock.players {
+        clock.frame += 1;
+    }
+}
+
 #[derive(Default)]
 struct TickEvent {}
 
+//gravity, counted in frames
+#[derive(Clone, Serialize, Deserialize)]
 struct GameTickInfo {
     do_tick: bool,
-    base_time_between_ticks: f32,
-    time_between_ticks: f32,
-    time_till_next_tick: f32,
-    base_time_between_ticks_when_blocks_falling: f32,
+    base_frames_between_ticks: u32,
+    frames_between_ticks: u32,
+    frames_since_last_tick: u32,
+    base_frames_between_ticks_when_blocks_falling: u32,
 }
 impl FromWorld for GameTickInfo {
     fn from_world(world: &mut World) -> Self {
         GameTickInfo {
             do_tick: false,
-            base_time_between_ticks: 0.5,
-            time_between_ticks: 0.5,
-            time_till_next_tick: 0.5,
-            base_time_between_ticks_when_blocks_falling: 0.1,
+            base_frames_between_ticks: 30,
+            frames_between_ticks: 30,
+            frames_since_last_tick: 30,
+            base_frames_between_ticks_when_blocks_falling: 6,
         }
     }
 }
 
 fn main() {
+    if ai::run_benchmark_from_arguments() {
+        return;
+    }
+
+    let user_settings = settings::UserSettings::load();
     App::new()
         .insert_resource(ClearColor(Color::rgb(0., 0., 0.)))
-        .insert_resource(WindowDescriptor {
-            title: "tetris.rs".to_string(),
-            width: 1920.,
-            height: 1080.,
-            mode: WindowMode::Windowed,
-            ..default()
-        })
+        .insert_resource(layout::window_descriptor(&user_settings.settings))
         .add_plugins(DefaultPlugins)
         .add_startup_system(setup)
         .add_plugin(board_core::BoardPlugin)
@@ -171,6 +497,13 @@ fn main() {
         .add_event::<GameFlow>()
         .add_event::<TickEvent>()
         .add_event::<ScoreEvent>()
+        .add_event::<PieceLockedEvent>()
+        .add_event::<SoundEffect>()
+        .insert_resource(user_settings)
+        .init_resource::<theme::Theme>()
+        .init_resource::<sound::SoundLibrary>()
+        .init_resource::<sound::MusicPlayer>()
+        .add_startup_system(sound::setup_volume_text)
         .init_resource::<GameStateInfo>()
         .init_resource::<GameTickInfo>()
         .init_resource::<SpawnController>()
@@ -178,33 +511,152 @@ fn main() {
         .init_resource::<GameSettings>()
         .init_resource::<Randomizer>()
         .init_resource::<Score>()
+        .init_resource::<FumenSequence>()
+        .init_resource::<HandlingSettings>()
+        .init_resource::<SimulationClock>()
+        .init_resource::<LockController>()
+        .init_resource::<Input<GameAction>>()
+        .init_resource::<InputSource>()
+        .init_resource::<KeyBindings>()
+        .init_resource::<ReplayRecorder>()
+        .init_resource::<ReplayPlayer>()
+        .init_resource::<save_game::WindowClosing>()
+        .init_resource::<high_scores::HighScores>()
+        .init_resource::<high_scores::HighScoreScreen>()
+        .init_resource::<stats::GameStats>()
+        .init_resource::<finesse::FinesseTable>()
+        .init_resource::<finesse::FinesseTracker>()
+        .init_resource::<piece_queue::PieceQueue>()
+        .init_resource::<tbp::TbpBot>()
+        .add_startup_system(tbp::setup_bot)
+        .init_resource::<ai::AiSettings>()
+        .init_resource::<ai::AiPlayer>()
+        .add_startup_system(ai::setup_ai)
+        .insert_resource(garbage::AttackTable::from_arguments())
+        .init_resource::<garbage::GarbageSettings>()
+        //netplay sends the settings above to the opponent, and versus takes its seed from it
+        .init_resource::<netplay::Netplay>()
+        .add_startup_system(netplay::setup_netplay.exclusive_system())
+        .add_startup_system(netplay::setup_netplay_text)
+        .add_system_to_stage(
+            CoreStage::PreUpdate,
+            netplay::exchange_net_inputs.after(InputSystem),
+        )
+        .init_resource::<versus::VersusSettings>()
+        .init_resource::<garbage::GarbageQueue>()
+        .init_resource::<spectator::SpectatorFeed>()
+        .add_startup_system(garbage::setup_garbage_meter)
+        .add_startup_system_to_stage(StartupStage::PostStartup, versus::setup_versus_boards)
+        .add_startup_system_to_stage(
+            StartupStage::PostStartup,
+            versus::setup_players.exclusive_system(),
+        )
+        .add_startup_system(finesse::setup_finesse_text)
+        .add_startup_system(stats::setup_stats_text)
+        .add_startup_system(high_scores::setup_high_score_text)
+        .add_startup_system(replay::setup_replay_playback)
+        .add_startup_system_to_stage(
+            StartupStage::PostStartup,
+            save_game::resume_saved_game.exclusive_system(),
+        )
         //
         .add_stage_after(
             CoreStage::Update,
             CHECK_BLOCKS,
             SystemStage::single_threaded(),
         )
-        .add_system_to_stage(CHECK_BLOCKS, board_core::update_board_data)
+        .add_system_to_stage(CHECK_BLOCKS, handle_fumen_input)
+        .add_system_set(simulation_systems())
+        .add_system(update_score_text)
+        .add_system(theme::handle_theme_input)
+        .add_system(theme::apply_theme.after(theme::handle_theme_input))
+        .add_system(layout::handle_fullscreen_input)
+        .add_system(layout::remember_window_size)
+        .add_system(layout::fit_camera_to_board)
         .add_system_to_stage(
-            CHECK_BLOCKS,
-            handle_blocks_falling.after(board_core::update_board_data),
+            CoreStage::PostUpdate,
+            layout::scale_hud.before(UiSystem::Flex),
         )
-        .add_system_set(
-            SystemSet::new()
-                .with_run_criteria(FixedTimestep::step(TIME_STEP as f64))
-                .with_system(game_start_input)
-                .with_system(game_tick_manager)
-                .with_system(game_loop_control.after(game_tick_manager))
-                .with_system(handle_game_input.after(game_loop_control)),
+        .add_system(sound::play_sound_effects)
+        .add_system(sound::play_music)
+        .add_system(sound::handle_volume_input)
+        .add_system(sound::update_volume_text.after(sound::handle_volume_input))
+        .add_system(board_core::respawn_resized_boards)
+        .add_system(board_core::draw_board)
+        .add_system(versus::draw_versus_boards)
+        .add_system(
+            board_core::draw_block_glyphs
+                .after(board_core::draw_board)
+                .after(versus::draw_versus_boards),
+        )
+        .add_system(versus::update_versus_previews)
+        .add_system(versus::update_versus_text)
+        .add_system(netplay::update_netplay_text)
+        .add_system(replay::handle_replay_controls)
+        .add_system(replay::save_replay)
+        .add_system(handle_pause_input)
+        .add_system(stats::update_stats_text)
+        .add_system(finesse::update_finesse_text)
+        .add_system(piece_queue::update_piece_previews)
+        .add_system(high_scores::handle_menu_input)
+        .add_system(high_scores::check_for_high_score)
+        .add_system(high_scores::handle_name_entry.after(high_scores::check_for_high_score))
+        .add_system(high_scores::update_high_score_text.after(high_scores::handle_name_entry))
+        .add_system(
+            high_scores::leave_finished_game
+                .exclusive_system()
+                .at_start(),
+        )
+        .add_system(save_game::notice_window_closing)
+        .add_system(
+            save_game::save_game_on_pause_or_quit
+                .exclusive_system()
+                .at_end(),
         )
-        .add_system(handle_game_state_events)
-        .add_system(handle_score_events)
         //
         .run();
 }
 
+//everything that changes the game runs in one ordered chain per frame so replays simulate the
+//same way no matter how many frames run in one update
+fn simulation_systems() -> SystemSet {
+    SystemSet::new()
+        .with_run_criteria(run_simulation_frame)
+        .with_system(netplay::start_networked_match.exclusive_system())
+        .with_system(versus::begin_player_pass.exclusive_system())
+        .with_system(replay::restart_replay.exclusive_system())
+        .with_system(game_start_input)
+        .with_system(handle_game_state_events.after(game_start_input))
+        .with_system(input::clear_game_input.after(handle_game_state_events))
+        .with_system(input::read_keyboard_input.after(input::clear_game_input))
+        .with_system(netplay::apply_net_input.after(input::read_keyboard_input))
+        .with_system(replay::play_replay_input.after(netplay::apply_net_input))
+        .with_system(replay::record_replay_input.after(replay::play_replay_input))
+        .with_system(stats::count_key_presses.after(replay::record_replay_input))
+        .with_system(finesse::count_finesse_inputs.after(stats::count_key_presses))
+        .with_system(handle_flow_delays.after(finesse::count_finesse_inputs))
+        .with_system(game_tick_manager.after(handle_flow_delays))
+        .with_system(handle_blocks_falling.after(game_tick_manager))
+        .with_system(game_loop_control.after(handle_blocks_falling))
+        .with_system(handle_game_input.after(game_loop_control))
+        .with_system(tbp::run_bot.after(handle_game_input))
+        .with_system(ai::run_ai.after(tbp::run_bot))
+        .with_system(piece_queue::handle_hold.after(ai::run_ai))
+        .with_system(handle_piece_locking.after(piece_queue::handle_hold))
+        .with_system(add_score.after(handle_piece_locking))
+        .with_system(stats::record_locked_piece.after(add_score))
+        .with_system(sound::queue_locked_piece_sounds.after(stats::record_locked_piece))
+        .with_system(finesse::check_finesse.after(sound::queue_locked_piece_sounds))
+        .with_system(garbage::exchange_garbage.after(finesse::check_finesse))
+        .with_system(garbage::update_garbage_meter.after(garbage::exchange_garbage))
+        .with_system(spectator::publish_game_events.after(garbage::update_garbage_meter))
+        .with_system(netplay::finish_net_frame.after(spectator::publish_game_events))
+        .with_system(advance_simulation_frame.after(netplay::finish_net_frame))
+        .with_system(versus::end_player_pass.exclusive_system().at_end())
+}
+
 fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
-    commands.spawn_bundle(Camera2dBundle::default());
+    commands.spawn_bundle(layout::layout_camera());
 
     commands
         .spawn_bundle(
@@ -246,52 +698,153 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                 ..default()
             }),
         )
-        .insert(ScoreText{});
+        .insert(ScoreText {});
 }
 
 fn game_tick_manager(
-    time: Res<Time>,
     mut game_tick_time: ResMut<GameTickInfo>,
     mut event_writer: EventWriter<TickEvent>,
 ) {
     if game_tick_time.do_tick {
-        game_tick_time.time_till_next_tick += time.delta().as_secs_f32();
-        if game_tick_time.time_till_next_tick >= game_tick_time.time_between_ticks {
-            game_tick_time.time_till_next_tick -= game_tick_time.time_between_ticks;
+        game_tick_time.frames_since_last_tick += 1;
+        if game_tick_time.frames_since_last_tick >= game_tick_time.frames_between_ticks {
+            game_tick_time.frames_since_last_tick = 0;
             event_writer.send(default());
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn game_start_input(
     keyboard_input: Res<Input<KeyCode>>,
+    input_source: Res<InputSource>,
+    game_settings: Res<GameSettings>,
+    handling_settings: Res<HandlingSettings>,
+    versus_settings: Res<versus::VersusSettings>,
+    netplay: Res<netplay::Netplay>,
+    mut randomizer: ResMut<Randomizer>,
+    mut clock: ResMut<SimulationClock>,
+    mut recorder: ResMut<ReplayRecorder>,
+    mut high_score_screen: ResMut<high_scores::HighScoreScreen>,
     mut game_state: ResMut<GameStateInfo>,
-    mut event_writer: EventWriter<GamePlayState>,
+    event_writer: EventWriter<GamePlayState>,
     mut event_flow_writer: EventWriter<GameFlow>,
 ) {
-    if game_state.game_state == GamePlayState::Menu {
-        if keyboard_input.pressed(KeyCode::Space) {
-            game_state.change_game_play_state(GamePlayState::Playing, event_writer);
-            game_state.change_flow_state(GameFlow::PlayerMovingBlock, &mut event_flow_writer);
+    //in netplay space is pressed on a frame like any other key
+    let start_pressed = if *input_source == InputSource::Network {
+        netplay.start_pressed()
+    } else {
+        keyboard_input.pressed(KeyCode::Space)
+    };
+    if game_state.game_state == GamePlayState::Menu
+        && *input_source != InputSource::Replay
+        && start_pressed
+    {
+        game_state.change_game_play_state(GamePlayState::Playing, event_writer);
+        game_state.change_flow_state(GameFlow::PlayerMovingBlock, &mut event_flow_writer);
+        //the table may have been left open in the menu, and the game needs it hidden to ask for
+        //a name at the end
+        *high_score_screen = high_scores::HighScoreScreen::Hidden;
+
+        //both players in versus get the same pieces
+        *randomizer = if versus_settings.enabled {
+            Randomizer::new(versus_settings.match_seed)
+        } else {
+            Randomizer::new(rand::thread_rng().gen())
+        };
+        clock.frame = 0;
+        //bots place pieces without pressing keys, so only keyboard games can be replayed. a
+        //replay only has room for one player
+        if *input_source == InputSource::Keyboard && !versus_settings.enabled {
+            recorder.start(randomizer.seed, &game_settings, &handling_settings);
         }
     }
 }
 
-fn handle_score_events(
-    mut event_reader: EventReader<ScoreEvent>,
-    mut score: ResMut<Score>,
-    mut text_query: Query<(&mut Text, &ScoreText)>,
+//puts every piece of game state back to how it is when the app starts
+pub fn reset_game(world: &mut World) {
+    board_core::clear_board(world);
+
+    let game_tick_info = GameTickInfo::from_world(world);
+    world.insert_resource(game_tick_info);
+    let spawn_controller = SpawnController::from_world(world);
+    world.insert_resource(spawn_controller);
+    let input_controller = InputController::from_world(world);
+    world.insert_resource(input_controller);
+    let lock_controller = LockController::from_world(world);
+    world.insert_resource(lock_controller);
+    let game_state_info = GameStateInfo::from_world(world);
+    world.insert_resource(game_state_info);
+    let score = Score::from_world(world);
+    world.insert_resource(score);
+    let game_stats = stats::GameStats::from_world(world);
+    world.insert_resource(game_stats);
+    let finesse_tracker = finesse::FinesseTracker::from_world(world);
+    world.insert_resource(finesse_tracker);
+    let piece_queue = piece_queue::PieceQueue::from_world(world);
+    world.insert_resource(piece_queue);
+    let ai_player = ai::AiPlayer::from_world(world);
+    world.insert_resource(ai_player);
+    let garbage_queue = garbage::GarbageQueue::from_world(world);
+    world.insert_resource(garbage_queue);
+    world.insert_resource(Input::<GameAction>::default());
+    world.insert_resource(high_scores::HighScoreScreen::Hidden);
+    world.resource_mut::<SimulationClock>().frame = 0;
+    versus::reset_players(world);
+}
+
+//starts a game the same way pressing space in the menu does
+pub fn start_game(world: &mut World) {
+    let mut game_state_info = world.resource_mut::<GameStateInfo>();
+    game_state_info.game_state = GamePlayState::Playing;
+    game_state_info.game_flow_state = GameFlow::PlayerMovingBlock;
+    world.send_event(GamePlayState::Playing);
+    world.send_event(GameFlow::PlayerMovingBlock);
+}
+
+//p pauses and unpauses a game being played. replays have their own controls
+fn handle_pause_input(
+    keyboard_input: Res<Input<KeyCode>>,
+    replay_player: Res<ReplayPlayer>,
+    game_state: Res<GameStateInfo>,
+    mut clock: ResMut<SimulationClock>,
+    mut text_query: Query<&mut Text, With<ScoreText>>,
 ) {
-    
-    let (mut score_text, _score_text_component) = text_query.single_mut();
-    
+    if replay_player.is_playing() || game_state.game_state != GamePlayState::Playing {
+        return;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::P) {
+        clock.paused = !clock.paused;
+    }
+
+    //a resumed save starts paused, so the text follows the clock rather than the key
+    let mut score_text = text_query.single_mut();
+    if score_text.sections[2].value.is_empty() == clock.paused {
+        score_text.sections[2].value = if clock.paused {
+            PAUSED_TEXT.to_string()
+        } else {
+            String::new()
+        };
+    }
+}
+
+//runs in the simulation so each player's clears go to their own score
+fn add_score(mut event_reader: EventReader<ScoreEvent>, mut score: ResMut<Score>) {
     for event in event_reader.iter() {
-        score.score += event.score * SCORE_AMOUNT;
-        info!("{}", score.score );
-        score_text.sections[1].value = format!("{}", score.score);
+        score.score += event.score * SCORE_AMOUNT * score.chain.max(1) as u64;
     }
 }
 
+fn update_score_text(score: Res<Score>, mut text_query: Query<(&mut Text, &ScoreText)>) {
+    if !score.is_changed() {
+        return;
+    }
+
+    let (mut score_text, _score_text_component) = text_query.single_mut();
+    score_text.sections[1].value = format!("{}", score.score);
+}
+
 fn handle_game_state_events(
     mut event_reader: EventReader<GamePlayState>,
     mut game_flow_event_reader: EventReader<GameFlow>,
@@ -302,6 +855,9 @@ fn handle_game_state_events(
         if *event == GamePlayState::Playing {
             game_tick_time.do_tick = true;
         }
+        if *event == GamePlayState::Win || *event == GamePlayState::Lose {
+            game_tick_time.do_tick = false;
+        }
     }
 
     /*
@@ -314,184 +870,509 @@ fn handle_game_state_events(
     }*/
 }
 
+#[allow(clippy::too_many_arguments)]
 fn game_loop_control(
-    asset_server: Res<AssetServer>,
-
     mut randomizer: ResMut<Randomizer>,
+    mut piece_queue: ResMut<piece_queue::PieceQueue>,
     game_settings: Res<GameSettings>,
     mut game_tick_time: ResMut<GameTickInfo>,
 
     mut spawn_controller: ResMut<SpawnController>,
+    mut lock_controller: ResMut<LockController>,
     mut game_state_info: ResMut<GameStateInfo>,
-    mut event_flow_writer: EventWriter<GameFlow>,
-    mut score_writer: EventWriter<ScoreEvent>,
+    game_play_writer: EventWriter<GamePlayState>,
 
     mut tick_reader: EventReader<TickEvent>,
     mut board_data: ResMut<BoardData>,
-    mut commands: Commands,
-    mut blocks_query: Query<(
-        Entity,
-        &BlockID,
-        &mut BoardPointCoordinates,
-        &mut Transform,
-        Option<&CurrentPlayerControlled>,
-    )>,
     mut highest_block_id: ResMut<BlockId>,
 ) {
     for tick in tick_reader.iter() {
-
-        if game_state_info.game_flow_state == GameFlow::PlayerMovingBlock {
-            game_tick_time.time_between_ticks = game_tick_time.base_time_between_ticks;
+        if game_state_info.game_state == GamePlayState::Playing
+            && game_state_info.game_flow_state == GameFlow::PlayerMovingBlock
+        {
+            game_tick_time.frames_between_ticks = game_tick_time.base_frames_between_ticks;
 
             if spawn_controller.spawn_piece == true {
                 //info!("spawned piece");
-                board_core::spawn_new_block(
-                    &mut commands,
-                    &mut board_data,
-                    match game_settings.game_randomizer {
-                        PieceRandomizerType::Bag => randomizer.next_block(),
-                        PieceRandomizerType::TrueRandom => randomizer.next_block(),
-                        PieceRandomizerType::TrueRandomWithoutRepeats => randomizer.next_block(),
-                    },
-                    &asset_server,
-                    &mut highest_block_id,
-                );
-                spawn_controller.spawn_piece = false;
-                board_core::move_all_pieces(&mut board_data, &mut commands, &mut blocks_query);
-            } else {
-                let something_moved =
-                    board_core::move_all_pieces(&mut board_data, &mut commands, &mut blocks_query);
-
-                if something_moved == false {
-                    game_state_info
-                        .change_flow_state(GameFlow::CheckingRows, &mut event_flow_writer);
-
-                    let row_deleted = board_core::check_each_row(
-                        &mut score_writer,
-                        &mut board_data,
-                        &mut commands,
-                    );
-                    if row_deleted {
-                        game_state_info.change_flow_state(
-                            GameFlow::BlocksMovingAfterRowBreak,
-                            &mut event_flow_writer,
-                        );
-                    } else {
-                        game_state_info
-                            .change_flow_state(GameFlow::PlayerMovingBlock, &mut event_flow_writer);
-                    }
-                    spawn_controller.spawn_piece = !something_moved;
+                let piece_type = piece_queue.next_piece(&mut randomizer, &game_settings);
+
+                //the stack has reached the top, so the game is over
+                if !board_core::piece_fits_at_spawn(&board_data, piece_type) {
+                    game_state_info.change_game_play_state(GamePlayState::Lose, game_play_writer);
+                    return;
                 }
+
+                board_core::spawn_new_block(&mut board_data, piece_type, &mut highest_block_id);
+                spawn_controller.spawn_piece = false;
+                lock_controller.frames_grounded = 0;
+                lock_controller.lock_resets = 0;
+                lock_controller.spin = Spin::None;
+                board_core::gravity_tick(&mut board_data, game_settings.line_clear_gravity);
+            } else if board_core::gravity_tick(&mut board_data, game_settings.line_clear_gravity) {
+                //falling to a new row gives the piece a fresh lock delay
+                lock_controller.frames_grounded = 0;
+                lock_controller.lock_resets = 0;
+                lock_controller.spin = Spin::None;
             }
         }
 
         if (game_state_info.game_flow_state == GameFlow::BlocksMovingAfterRowBreak) {
-            game_tick_time.time_between_ticks =
-                game_tick_time.base_time_between_ticks_when_blocks_falling;
+            game_tick_time.frames_between_ticks =
+                game_tick_time.base_frames_between_ticks_when_blocks_falling;
         }
     }
 }
 
 fn set_game_tick_time() {}
 
+//drops what's left after a clear a row every tick until it has all landed. with cascade gravity
+//the rows that fills are cleared too, and the blocks keep falling for the next link of the chain.
+//each link goes out like a locked piece so it counts for stats, attack and sounds
+#[allow(clippy::too_many_arguments)]
 fn handle_blocks_falling(
     mut tick_reader: EventReader<TickEvent>,
+    game_settings: Res<GameSettings>,
+    mut score: ResMut<Score>,
+    mut spawn_controller: ResMut<SpawnController>,
+    mut game_state_info: ResMut<GameStateInfo>,
+    mut event_flow_writer: EventWriter<GameFlow>,
+    mut piece_locked_writer: EventWriter<PieceLockedEvent>,
+    mut board_data: ResMut<BoardData>,
+) {
+    for _tick in tick_reader.iter() {
+        if game_state_info.game_flow_state != GameFlow::BlocksMovingAfterRowBreak {
+            continue;
+        }
+
+        let gravity = game_settings.line_clear_gravity;
+        let something_moved = if gravity == LineClearGravity::Cascade {
+            board_core::move_all_groups(&mut board_data)
+        } else {
+            board_core::move_all_pieces(&mut board_data)
+        };
+        if something_moved {
+            continue;
+        }
+
+        let rows_deleted = if gravity == LineClearGravity::Cascade {
+            board_core::begin_row_clear(&mut board_data, game_settings.line_clear_delay)
+        } else {
+            0
+        };
+        if rows_deleted > 0 {
+            score.chain += 1;
+            game_state_info.change_flow_state(GameFlow::ClearingRows, &mut event_flow_writer);
+            piece_locked_writer.send(PieceLockedEvent {
+                lines_cleared: rows_deleted,
+                piece_type: None,
+                cells: vec![],
+                dropped_straight: false,
+                spin: Spin::None,
+                chain: score.chain,
+            });
+            continue;
+        }
+        begin_entry_delay(
+            &game_settings,
+            &mut spawn_controller,
+            &mut game_state_info,
+            &mut event_flow_writer,
+        );
+    }
+}
 
+//waits out the entry delay before the next piece spawns
+fn begin_entry_delay(
+    game_settings: &GameSettings,
+    spawn_controller: &mut SpawnController,
+    game_state_info: &mut GameStateInfo,
+    event_flow_writer: &mut EventWriter<GameFlow>,
+) {
+    spawn_controller.entry_delay_left = game_settings.entry_delay;
+    game_state_info.change_flow_state(GameFlow::EntryDelay, event_flow_writer);
+}
+
+//counts down the line clear delay and the entry delay a frame at a time. once the rows have
+//flashed away they're cleared and the stack settles, and once the entry delay is over the next
+//piece spawns straight away instead of waiting for the next tick
+#[allow(clippy::too_many_arguments)]
+fn handle_flow_delays(
+    game_settings: Res<GameSettings>,
+    mut score: ResMut<Score>,
+    mut game_tick_time: ResMut<GameTickInfo>,
     mut spawn_controller: ResMut<SpawnController>,
     mut game_state_info: ResMut<GameStateInfo>,
+    game_play_writer: EventWriter<GamePlayState>,
     mut event_flow_writer: EventWriter<GameFlow>,
+    mut score_writer: EventWriter<ScoreEvent>,
+    mut sound_writer: EventWriter<SoundEffect>,
     mut board_data: ResMut<BoardData>,
-    mut commands: Commands,
-    mut blocks_query: Query<(
-        Entity,
-        &BlockID,
-        &mut BoardPointCoordinates,
-        &mut Transform,
-        Option<&CurrentPlayerControlled>,
-    )>,
 ) {
-    for tick in tick_reader.iter() {
+    if game_state_info.game_state != GamePlayState::Playing {
+        return;
+    }
 
-        if game_state_info.game_flow_state == GameFlow::BlocksMovingAfterRowBreak {
-            if game_state_info.game_flow_state == GameFlow::BlocksMovingAfterRowBreak {
-                let mut something_moved =
-                    board_core::move_all_pieces(&mut board_data, &mut commands, &mut blocks_query);
-                if something_moved == false {
-                    game_state_info
-                        .change_flow_state(GameFlow::PlayerMovingBlock, &mut event_flow_writer);
-                }
-            }
+    if game_state_info.game_flow_state == GameFlow::ClearingRows
+        && board_core::row_clear_done(&mut board_data)
+    {
+        let gravity = game_settings.line_clear_gravity;
+        let rows_deleted = board_core::check_each_row(&mut score_writer, &mut board_data, gravity);
+        let level = score.lines_cleared / LINES_PER_LEVEL;
+        if count_cleared_lines(rows_deleted, &game_settings, &mut score) {
+            game_state_info.change_game_play_state(GamePlayState::Win, game_play_writer);
+            return;
+        }
+        if score.lines_cleared / LINES_PER_LEVEL > level {
+            sound_writer.send(SoundEffect::LevelUp);
+        }
+        if gravity == LineClearGravity::Naive {
+            begin_entry_delay(
+                &game_settings,
+                &mut spawn_controller,
+                &mut game_state_info,
+                &mut event_flow_writer,
+            );
+        } else {
+            game_state_info
+                .change_flow_state(GameFlow::BlocksMovingAfterRowBreak, &mut event_flow_writer);
         }
     }
+
+    if game_state_info.game_flow_state == GameFlow::EntryDelay {
+        spawn_controller.entry_delay_left = spawn_controller.entry_delay_left.saturating_sub(1);
+        if spawn_controller.entry_delay_left == 0 {
+            game_state_info.change_flow_state(GameFlow::PlayerMovingBlock, &mut event_flow_writer);
+            game_tick_time.frames_since_last_tick = game_tick_time.frames_between_ticks;
+        }
+    }
+}
+
+//adds cleared rows to the line count. returns true once a sprint has cleared enough to be won
+fn count_cleared_lines(rows_deleted: u32, game_settings: &GameSettings, score: &mut Score) -> bool {
+    score.lines_cleared += rows_deleted;
+    game_settings.game_mode == GameMode::Sprint && score.lines_cleared >= SPRINT_LINES
 }
 
+#[allow(clippy::too_many_arguments)]
 fn handle_game_input(
-    time: Res<Time>,
+    handling_settings: Res<HandlingSettings>,
+    game_settings: Res<GameSettings>,
     mut tick_info: ResMut<GameTickInfo>,
-    keyboard_input: Res<Input<KeyCode>>,
+    game_input: Res<Input<GameAction>>,
     mut input_controller: ResMut<InputController>,
-    mut game_state: ResMut<GameStateInfo>,
+    mut lock_controller: ResMut<LockController>,
+    game_state: Res<GameStateInfo>,
+    mut sound_writer: EventWriter<SoundEffect>,
     mut board_data: ResMut<BoardData>,
-    mut commands: Commands,
-    mut blocks_query: Query<
-        (Entity, &BlockID, &mut BoardPointCoordinates, &mut Transform),
-        With<CurrentPlayerControlled>,
-    >,
 ) {
     if input_controller.are_holding_down {
+        input_controller.frames_since_last_move += 1;
         if input_controller.slow_hold_move_finished {
-            input_controller.time_till_next_move += time.delta().as_secs_f32();
-            if input_controller.time_till_next_move >= input_controller.time_between_moves_held_down
-            {
-                input_controller.time_till_next_move -=
-                    input_controller.time_between_moves_held_down;
+            if input_controller.frames_since_last_move >= handling_settings.auto_repeat_rate {
+                input_controller.frames_since_last_move = 0;
                 input_controller.can_move = true;
             }
-        } else {
-            input_controller.time_till_next_move += time.delta().as_secs_f32();
-            if input_controller.time_till_next_move >= input_controller.time_between_moves_default {
-                input_controller.time_till_next_move -= input_controller.time_between_moves_default;
-                input_controller.can_move = true;
-                input_controller.slow_hold_move_finished = true;
-            }
+        } else if input_controller.frames_since_last_move >= handling_settings.delayed_auto_shift {
+            input_controller.frames_since_last_move = 0;
+            input_controller.can_move = true;
+            input_controller.slow_hold_move_finished = true;
         }
     }
 
+    let mut moved = false;
+    let mut spin = None;
     if game_state.game_state == GamePlayState::Playing && input_controller.can_move {
-        if keyboard_input.pressed(KeyCode::A) && input_controller.can_move {
-            board_core::move_piece_horizontal(
-                &mut board_data,
-                &commands,
-                &mut blocks_query,
-                IVec2 { x: -1, y: 0 },
-            );
+        if game_input.pressed(GameAction::MoveLeft) && input_controller.can_move {
+            moved = board_core::move_piece_horizontal(&mut board_data, IVec2 { x: -1, y: 0 });
             input_controller.are_holding_down = true;
             input_controller.can_move = false;
         }
 
-        if keyboard_input.pressed(KeyCode::D) && input_controller.can_move {
-            board_core::move_piece_horizontal(
-                &mut board_data,
-                &commands,
-                &mut blocks_query,
-                IVec2 { x: 1, y: 0 },
-            );
+        if game_input.pressed(GameAction::MoveRight) && input_controller.can_move {
+            moved = board_core::move_piece_horizontal(&mut board_data, IVec2 { x: 1, y: 0 });
             input_controller.are_holding_down = true;
             input_controller.can_move = false;
         }
     }
 
-    if keyboard_input.just_released(KeyCode::A) || keyboard_input.just_released(KeyCode::D) {
+    //rotations happen once per press and don't wait on the das timer
+    if game_state.game_state == GamePlayState::Playing {
+        for (action, direction) in [
+            (GameAction::RotateClockwise, RotationDirection::Clockwise),
+            (
+                GameAction::RotateCounterClockwise,
+                RotationDirection::CounterClockwise,
+            ),
+            (GameAction::Rotate180, RotationDirection::Half),
+        ] {
+            if game_input.just_pressed(action) {
+                if let Some(rotation_spin) =
+                    board_core::rotate_player_piece(&mut board_data, direction)
+                {
+                    moved = true;
+                    spin = Some(rotation_spin);
+                }
+            }
+        }
+    }
+
+    if moved {
+        sound_writer.send(if spin.is_some() {
+            SoundEffect::Rotate
+        } else {
+            SoundEffect::Move
+        });
+    }
+
+    //the last thing that moved the piece decides whether it's spun in
+    if moved {
+        lock_controller.spin = spin.unwrap_or(Spin::None);
+    }
+
+    if moved && lock_controller.lock_resets < game_settings.max_lock_resets {
+        lock_controller.frames_grounded = 0;
+        lock_controller.lock_resets += 1;
+    }
+
+    //a hard dropped piece locks straight away
+    if game_state.game_state == GamePlayState::Playing
+        && game_input.just_pressed(GameAction::HardDrop)
+        && board_data.active_piece.is_some()
+    {
+        if board_core::drop_player_piece(&mut board_data) > 0 {
+            lock_controller.spin = Spin::None;
+        }
+        lock_controller.frames_grounded = game_settings.lock_delay;
+        sound_writer.send(SoundEffect::HardDrop);
+    }
+
+    if game_input.just_released(GameAction::MoveLeft)
+        || game_input.just_released(GameAction::MoveRight)
+    {
         input_controller.are_holding_down = false;
         input_controller.slow_hold_move_finished = false;
         input_controller.can_move = true;
-        input_controller.time_till_next_move = 0.0;
+        input_controller.frames_since_last_move = 0;
+    }
+
+    if game_input.just_pressed(GameAction::SoftDrop) && board_data.active_piece.is_some() {
+        sound_writer.send(SoundEffect::SoftDrop);
+    }
+    if game_input.pressed(GameAction::SoftDrop) {
+        tick_info.frames_between_ticks = handling_settings.soft_drop_frames_between_ticks;
+    }
+    if game_input.just_released(GameAction::SoftDrop) {
+        tick_info.frames_between_ticks = tick_info.base_frames_between_ticks;
+    }
+}
+
+//counts the frames the player's piece spends resting on something and locks it once the lock
+//delay runs out, then starts clearing any rows it filled
+#[allow(clippy::too_many_arguments)]
+fn handle_piece_locking(
+    game_settings: Res<GameSettings>,
+    mut lock_controller: ResMut<LockController>,
+    mut spawn_controller: ResMut<SpawnController>,
+    mut score: ResMut<Score>,
+    mut game_state_info: ResMut<GameStateInfo>,
+    mut event_flow_writer: EventWriter<GameFlow>,
+    mut piece_locked_writer: EventWriter<PieceLockedEvent>,
+    mut board_data: ResMut<BoardData>,
+) {
+    if game_state_info.game_flow_state != GameFlow::PlayerMovingBlock
+        || spawn_controller.spawn_piece
+    {
+        return;
+    }
+
+    if !board_core::player_piece_grounded(&board_data) {
+        lock_controller.frames_grounded = 0;
+        return;
+    }
+
+    lock_controller.frames_grounded += 1;
+    if lock_controller.frames_grounded < game_settings.lock_delay {
+        return;
+    }
+
+    let piece_type = board_data.active_piece.map(|piece| piece.piece_type);
+    let cells = board_core::player_piece_cells(&board_data);
+    let dropped_straight = board_core::player_piece_clear_above(&board_data);
+    board_core::lock_player_piece(&mut board_data);
+
+    score.chain = 1;
+    let rows_deleted = board_core::begin_row_clear(&mut board_data, game_settings.line_clear_delay);
+    if rows_deleted > 0 {
+        game_state_info.change_flow_state(GameFlow::ClearingRows, &mut event_flow_writer);
+    } else {
+        begin_entry_delay(
+            &game_settings,
+            &mut spawn_controller,
+            &mut game_state_info,
+            &mut event_flow_writer,
+        );
     }
+    spawn_controller.spawn_piece = true;
+    piece_locked_writer.send(PieceLockedEvent {
+        lines_cleared: rows_deleted,
+        piece_type,
+        cells,
+        dropped_straight,
+        spin: lock_controller.spin,
+        chain: 1,
+    });
+}
 
-    if keyboard_input.pressed(KeyCode::S) {
-        tick_info.time_between_ticks = BLOCK_FALL_SPEED_UP;
+//page down/up steps through the loaded fumen pages. f2 captures the board as a fumen page and
+//f3 prints every captured page as one fumen
+#[allow(clippy::too_many_arguments)]
+fn handle_fumen_input(
+    keyboard_input: Res<Input<KeyCode>>,
+    game_state: Res<GameStateInfo>,
+    input_source: Res<InputSource>,
+    mut fumen_sequence: ResMut<FumenSequence>,
+    mut spawn_controller: ResMut<SpawnController>,
+    mut board_data: ResMut<BoardData>,
+    mut highest_block_id: ResMut<BlockId>,
+) {
+    //a page changes the board from outside the simulation, which replays and the opponent in
+    //netplay never see. so pages only turn while no game is being played
+    let can_turn_pages =
+        game_state.game_state != GamePlayState::Playing && *input_source != InputSource::Replay;
+    if can_turn_pages
+        && keyboard_input.just_pressed(KeyCode::PageDown)
+        && fumen_sequence.current_page + 1 < fumen_sequence.pages.len()
+    {
+        fumen_sequence.current_page += 1;
+        fumen_sequence.load_requested = true;
+    }
+    if can_turn_pages
+        && keyboard_input.just_pressed(KeyCode::PageUp)
+        && fumen_sequence.current_page > 0
+    {
+        fumen_sequence.current_page -= 1;
+        fumen_sequence.load_requested = true;
+    }
+
+    if fumen_sequence.load_requested {
+        fumen_sequence.load_requested = false;
+        if let Some(page) = fumen_sequence.pages.get(fumen_sequence.current_page) {
+            let has_piece =
+                board_core::load_fumen_page(page, &mut board_data, &mut highest_block_id);
+            spawn_controller.spawn_piece = !has_piece;
+            info!(
+                "fumen page {}/{}: {}",
+                fumen_sequence.current_page + 1,
+                fumen_sequence.pages.len(),
+                board_data.comment
+            );
+        }
+    }
+
+    if keyboard_input.just_pressed(KeyCode::F2) {
+        if let Some(page) = board_core::board_to_fumen_page(&board_data) {
+            info!("{}", fumen::encode(std::slice::from_ref(&page)));
+            fumen_sequence.captured_pages.push(page);
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::F3) && !fumen_sequence.captured_pages.is_empty() {
+        info!("{}", fumen::encode(&fumen_sequence.captured_pages));
     }
-    if keyboard_input.just_released(KeyCode::S) {
-        tick_info.time_between_ticks = tick_info.base_time_between_ticks;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //the game's simulation with nothing drawn, for tests to play through a frame at a time
+    pub fn headless_app() -> App {
+        let mut app = App::new();
+        app.insert_resource(Time::default())
+            .insert_resource(Input::<KeyCode>::default())
+            .add_event::<GamePlayState>()
+            .add_event::<GameFlow>()
+            .add_event::<TickEvent>()
+            .add_event::<ScoreEvent>()
+            .add_event::<PieceLockedEvent>()
+            .add_event::<SoundEffect>()
+            .init_resource::<GameSettings>()
+            .init_resource::<BoardData>()
+            .init_resource::<BlockId>()
+            .init_resource::<GameStateInfo>()
+            .init_resource::<GameTickInfo>()
+            .init_resource::<SpawnController>()
+            .init_resource::<InputController>()
+            .init_resource::<Randomizer>()
+            .init_resource::<Score>()
+            .init_resource::<HandlingSettings>()
+            .init_resource::<SimulationClock>()
+            .init_resource::<LockController>()
+            .init_resource::<Input<GameAction>>()
+            .init_resource::<InputSource>()
+            .init_resource::<KeyBindings>()
+            .init_resource::<ReplayRecorder>()
+            .init_resource::<ReplayPlayer>()
+            .init_resource::<high_scores::HighScoreScreen>()
+            .init_resource::<stats::GameStats>()
+            .init_resource::<finesse::FinesseTable>()
+            .init_resource::<finesse::FinesseTracker>()
+            .init_resource::<piece_queue::PieceQueue>()
+            .init_resource::<tbp::TbpBot>()
+            .init_resource::<ai::AiSettings>()
+            .init_resource::<ai::AiPlayer>()
+            .insert_resource(garbage::AttackTable::from_arguments())
+            .init_resource::<garbage::GarbageSettings>()
+            .init_resource::<netplay::Netplay>()
+            .init_resource::<versus::VersusSettings>()
+            .init_resource::<garbage::GarbageQueue>()
+            .init_resource::<spectator::SpectatorFeed>()
+            .add_system_set(simulation_systems());
+        app
+    }
+
+    //runs the simulation for this many frames, one frame an update
+    pub fn run_frames(app: &mut App, frames: u32) {
+        for _ in 0..frames {
+            app.world.resource_mut::<SimulationClock>().accumulator = TIME_STEP;
+            app.update();
+        }
+    }
+
+    //holds the key down, or lets it go
+    pub fn set_key(app: &mut App, key: KeyCode, held: bool) {
+        let mut keyboard_input = app.world.resource_mut::<Input<KeyCode>>();
+        keyboard_input.clear();
+        if held {
+            keyboard_input.press(key);
+        } else {
+            keyboard_input.release(key);
+        }
+    }
+
+    #[test]
+    fn fumen_pages_only_turn_outside_a_game() {
+        let mut app = headless_app();
+        app.add_system(handle_fumen_input);
+        let page = board_core::board_to_fumen_page(&BoardData::new(BoardSize::default())).unwrap();
+        app.insert_resource(FumenSequence {
+            pages: vec![page.clone(), page],
+            current_page: 0,
+            load_requested: false,
+            captured_pages: vec![],
+        });
+
+        set_key(&mut app, KeyCode::PageDown, true);
+        run_frames(&mut app, 1);
+        assert_eq!(app.world.resource::<FumenSequence>().current_page, 1);
+
+        set_key(&mut app, KeyCode::Space, true);
+        run_frames(&mut app, 1);
+        assert_eq!(
+            app.world.resource::<GameStateInfo>().game_state,
+            GamePlayState::Playing
+        );
+        set_key(&mut app, KeyCode::PageUp, true);
+        run_frames(&mut app, 1);
+        assert_eq!(app.world.resource::<FumenSequence>().current_page, 1);
     }
 }
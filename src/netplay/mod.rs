@@ -0,0 +1,726 @@
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::board::board_core::{self, BoardData, InvalidBoardSize};
+use crate::game_state_machine::{GamePlayState, GameStateInfo};
+use crate::garbage::{AttackTable, GarbageQueue, GarbageSettings, HoleRule};
+use crate::input::{GameAction, InputSource, KeyBindings};
+use crate::replay::REPLAY_VERSION;
+use crate::{GameSettings, HandlingSettings, Score, SimulationClock, FONT_ASSET_PATH, TEXT_COLOR};
+
+//frames between a key being pressed and the simulation using it, so the opponent's input for
+//a frame has time to arrive before the frame runs
+const INPUT_DELAY: u32 = 3;
+//frames between the players comparing hashes of their boards
+const HASH_INTERVAL: u32 = 60;
+
+const NETPLAY_FONT_SIZE: f32 = 30.0;
+const NETPLAY_TEXT_TOP: f32 = 20.0;
+const NETPLAY_TEXT_LEFT: f32 = 820.0;
+
+#[derive(Debug)]
+pub enum NetplayError {
+    Io(io::Error),
+    Format(serde_json::Error),
+    UnsupportedVersion(u32),
+    NoHello,
+    NoOpponent,
+    //the host's settings would crash the game if they were played
+    InvalidBoardSize(InvalidBoardSize),
+    InvalidHoleRule(HoleRule),
+}
+
+impl fmt::Display for NetplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetplayError::Io(error) => write!(f, "{}", error),
+            NetplayError::Format(error) => write!(f, "opponent sent a broken message: {}", error),
+            NetplayError::UnsupportedVersion(version) => write!(
+                f,
+                "opponent simulates version {}, this build simulates version {}",
+                version, REPLAY_VERSION
+            ),
+            NetplayError::NoHello => write!(f, "opponent didn't send the match settings"),
+            NetplayError::NoOpponent => write!(f, "stopped waiting for an opponent"),
+            NetplayError::InvalidBoardSize(error) => write!(f, "host sent {}", error),
+            NetplayError::InvalidHoleRule(hole_rule) => {
+                write!(f, "host sent an unplayable hole rule {:?}", hole_rule)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for NetplayError {
+    fn from(error: io::Error) -> Self {
+        NetplayError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for NetplayError {
+    fn from(error: serde_json::Error) -> Self {
+        NetplayError::Format(error)
+    }
+}
+
+//everything both sides need to agree on to simulate the same match. the host picks it
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MatchConfig {
+    pub match_seed: u64,
+    pub settings: GameSettings,
+    pub handling: HandlingSettings,
+    pub hole_rule: HoleRule,
+    pub attack_table: AttackTable,
+}
+
+impl MatchConfig {
+    //the joining side plays whatever the host sends, so it's checked before any of it is used
+    fn validate(&self) -> Result<(), NetplayError> {
+        self.settings
+            .board_size
+            .validate()
+            .map_err(NetplayError::InvalidBoardSize)?;
+        match self.hole_rule {
+            HoleRule::Messiness(chance) if !(0.0..=1.0).contains(&chance) => {
+                Err(NetplayError::InvalidHoleRule(self.hole_rule))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+//one player's keys on one frame
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetInput {
+    //a bit for each action in GameAction::ALL that is held
+    held: u8,
+    //space was pressed, which starts a match
+    start: bool,
+}
+
+impl NetInput {
+    fn holds(&self, action: GameAction) -> bool {
+        match GameAction::ALL.iter().position(|other| *other == action) {
+            Some(index) => self.held & (1 << index) != 0,
+            None => false,
+        }
+    }
+}
+
+//sent as one line of json each
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum NetMessage {
+    Hello { version: u32, config: MatchConfig },
+    Input { frame: u32, input: NetInput },
+    Hash { frame: u32, hash: u64 },
+}
+
+//the host's opponent, sent over once they've connected
+type PendingOpponent = Receiver<Result<BufReader<TcpStream>, NetplayError>>;
+
+//a match against another copy of the game over tcp. --host <port> waits for an opponent and
+//--join <address:port> connects to one. both sides simulate both boards from the same inputs,
+//so every frame waits until the opponent's input for it has arrived
+pub struct Netplay {
+    stream: Option<TcpStream>,
+    messages: Option<Mutex<Receiver<NetMessage>>>,
+    //the host waits for an opponent on another thread, with the match already set up
+    opponent: Option<Mutex<PendingOpponent>>,
+    config: Option<MatchConfig>,
+    //the board this side plays, the host's is the first
+    pub local_player: usize,
+    //the next frame to simulate, counted from when the players connected
+    frame: u32,
+    //the first frame that doesn't have the local keys yet
+    next_local_frame: u32,
+    local_inputs: BTreeMap<u32, NetInput>,
+    remote_inputs: BTreeMap<u32, NetInput>,
+    //whether space was down when the keys were last picked up
+    start_held: bool,
+    //the hash of this frame's boards so far
+    frame_hash: u64,
+    local_hashes: BTreeMap<u32, u64>,
+    remote_hashes: BTreeMap<u32, u64>,
+    pub desync_frame: Option<u32>,
+    pub disconnected: bool,
+}
+
+impl FromWorld for Netplay {
+    fn from_world(world: &mut World) -> Self {
+        let mut netplay = Netplay {
+            stream: None,
+            messages: None,
+            opponent: None,
+            config: None,
+            local_player: 0,
+            frame: 0,
+            next_local_frame: 0,
+            local_inputs: BTreeMap::new(),
+            remote_inputs: BTreeMap::new(),
+            start_held: false,
+            frame_hash: 0,
+            local_hashes: BTreeMap::new(),
+            remote_hashes: BTreeMap::new(),
+            desync_frame: None,
+            disconnected: false,
+        };
+
+        let arguments: Vec<String> = std::env::args().collect();
+        let argument_after = |flag: &str| {
+            arguments
+                .iter()
+                .position(|argument| argument == flag)
+                .map(|index| arguments.get(index + 1))
+        };
+
+        match (argument_after("--host"), argument_after("--join")) {
+            (Some(Some(port)), _) => {
+                let config = MatchConfig {
+                    match_seed: rand::thread_rng().gen(),
+                    settings: world.resource::<GameSettings>().clone(),
+                    handling: world.resource::<HandlingSettings>().clone(),
+                    hole_rule: world.resource::<GarbageSettings>().hole_rule,
+                    attack_table: world.resource::<AttackTable>().clone(),
+                };
+                match host(port, config.clone()) {
+                    Ok(opponent) => {
+                        info!("waiting for an opponent on port {}", port);
+                        netplay.opponent = Some(Mutex::new(opponent));
+                        netplay.config = Some(config);
+                    }
+                    Err(error) => warn!("couldn't start netplay: {}", error),
+                }
+            }
+            (_, Some(Some(address))) => match join(address) {
+                Ok((reader, config)) => {
+                    netplay.start(reader, config, 1);
+                    info!("netplay started as player 2");
+                }
+                Err(error) => warn!("couldn't start netplay: {}", error),
+            },
+            (Some(None), _) => warn!("--host needs a port to listen on"),
+            (_, Some(None)) => warn!("--join needs an address to connect to"),
+            (None, None) => {}
+        }
+        netplay
+    }
+}
+
+//listens for an opponent on its own thread, so the game can open its window and show that it's
+//waiting instead of hanging until someone connects
+fn host(port: &str, config: MatchConfig) -> Result<PendingOpponent, NetplayError> {
+    let listener = TcpListener::bind(format!("0.0.0.0:{}", port))?;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(accept_opponent(&listener, &config));
+    });
+    Ok(receiver)
+}
+
+//waits for an opponent to connect and tells them how the match is played
+fn accept_opponent(
+    listener: &TcpListener,
+    config: &MatchConfig,
+) -> Result<BufReader<TcpStream>, NetplayError> {
+    let (mut stream, address) = listener.accept()?;
+    info!("{} joined", address);
+    let hello = NetMessage::Hello {
+        version: REPLAY_VERSION,
+        config: config.clone(),
+    };
+    writeln!(stream, "{}", serde_json::to_string(&hello)?)?;
+    Ok(BufReader::new(stream))
+}
+
+//connects to a host and takes their settings for the match, as long as they can be played. the
+//reader is kept, since the host's first inputs can arrive along with the hello
+fn join(address: &str) -> Result<(BufReader<TcpStream>, MatchConfig), NetplayError> {
+    let mut reader = BufReader::new(TcpStream::connect(address)?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let config = match serde_json::from_str(&line)? {
+        NetMessage::Hello { version, config } if version == REPLAY_VERSION => config,
+        NetMessage::Hello { version, .. } => return Err(NetplayError::UnsupportedVersion(version)),
+        _ => return Err(NetplayError::NoHello),
+    };
+    config.validate()?;
+    Ok((reader, config))
+}
+
+impl Netplay {
+    fn start(&mut self, reader: BufReader<TcpStream>, config: MatchConfig, local_player: usize) {
+        let stream = reader.get_ref();
+        if let Err(error) = stream.set_nodelay(true) {
+            warn!("couldn't turn off nagle for netplay: {}", error);
+        }
+        let stream = match stream.try_clone() {
+            Ok(stream) => stream,
+            Err(error) => {
+                warn!("couldn't start netplay: {}", error);
+                return;
+            }
+        };
+
+        //the opponent's messages are read on their own thread so waiting on them never holds
+        //up a frame
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in reader.lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                match serde_json::from_str(&line) {
+                    Ok(message) => {
+                        if sender.send(message).is_err() {
+                            break;
+                        }
+                    }
+                    Err(error) => warn!("opponent sent a broken message: {}", error),
+                }
+            }
+        });
+
+        self.stream = Some(stream);
+        self.messages = Some(Mutex::new(receiver));
+        self.config = Some(config);
+        self.local_player = local_player;
+    }
+
+    //starts the match once the host's opponent has connected
+    fn check_for_opponent(&mut self) {
+        let received = match &self.opponent {
+            Some(opponent) => opponent.lock().unwrap().try_recv(),
+            None => return,
+        };
+        let result = match received {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => Err(NetplayError::NoOpponent),
+        };
+        self.opponent = None;
+
+        match (result, self.config.clone()) {
+            (Ok(reader), Some(config)) => {
+                self.start(reader, config, 0);
+                info!("netplay started as player 1");
+            }
+            (Ok(_), None) => {}
+            (Err(error), _) => {
+                warn!("couldn't start netplay: {}", error);
+                self.disconnected = true;
+            }
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.config.is_some()
+    }
+
+    pub fn match_config(&self) -> Option<&MatchConfig> {
+        self.config.as_ref()
+    }
+
+    //whether both players' keys for the next frame are in
+    pub fn frame_ready(&self) -> bool {
+        !self.is_active()
+            || (self.local_inputs.contains_key(&self.frame)
+                && self.remote_inputs.contains_key(&self.frame))
+    }
+
+    fn input(&self, player: usize) -> NetInput {
+        let inputs = if player == self.local_player {
+            &self.local_inputs
+        } else {
+            &self.remote_inputs
+        };
+        inputs.get(&self.frame).copied().unwrap_or_default()
+    }
+
+    //either player pressing space starts the next match
+    pub fn start_pressed(&self) -> bool {
+        self.input(0).start || self.input(1).start
+    }
+
+    fn send(&mut self, message: &NetMessage) {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => return,
+        };
+        let result = serde_json::to_string(message)
+            .map_err(|error| error.to_string())
+            .and_then(|line| writeln!(stream, "{}", line).map_err(|error| error.to_string()));
+        if let Err(error) = result {
+            if !self.disconnected {
+                warn!("lost the opponent: {}", error);
+            }
+            self.disconnected = true;
+        }
+    }
+
+    fn receive_messages(&mut self) {
+        let mut received = vec![];
+        if let Some(messages) = &self.messages {
+            let messages = messages.lock().unwrap();
+            loop {
+                match messages.try_recv() {
+                    Ok(message) => received.push(message),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        if !self.disconnected {
+                            warn!("the opponent left");
+                        }
+                        self.disconnected = true;
+                        break;
+                    }
+                }
+            }
+        }
+
+        for message in received {
+            match message {
+                NetMessage::Input { frame, input } => {
+                    self.remote_inputs.insert(frame, input);
+                }
+                NetMessage::Hash { frame, hash } => {
+                    self.remote_hashes.insert(frame, hash);
+                }
+                NetMessage::Hello { .. } => {}
+            }
+        }
+        self.compare_hashes();
+    }
+
+    fn compare_hashes(&mut self) {
+        let frames: Vec<u32> = self
+            .local_hashes
+            .keys()
+            .filter(|frame| self.remote_hashes.contains_key(frame))
+            .copied()
+            .collect();
+        for frame in frames {
+            let local_hash = self.local_hashes.remove(&frame);
+            let remote_hash = self.remote_hashes.remove(&frame);
+            if local_hash != remote_hash && self.desync_frame.is_none() {
+                warn!(
+                    "the boards stopped matching the opponent's by frame {}",
+                    frame
+                );
+                self.desync_frame = Some(frame);
+            }
+        }
+    }
+
+    //mixes a player's side of the match into this frame's hash
+    fn hash_player(
+        &mut self,
+        player: usize,
+        board_data: &BoardData,
+        score: &Score,
+        garbage_queue: &GarbageQueue,
+        game_state: &GameStateInfo,
+    ) {
+        let mut hasher = DefaultHasher::new();
+        self.frame_hash.hash(&mut hasher);
+        player.hash(&mut hasher);
+        let size = board_data.size();
+        for y in 0..size.height() {
+            for x in 0..size.width {
+                board_data.is_filled(&IVec2 { x, y }).hash(&mut hasher);
+            }
+        }
+        board_data
+            .active_piece
+            .map(|piece| (piece.piece_type, piece.rotation))
+            .hash(&mut hasher);
+        score.score.hash(&mut hasher);
+        score.lines_cleared.hash(&mut hasher);
+        garbage_queue.pending.hash(&mut hasher);
+        garbage_queue.lines_sent.hash(&mut hasher);
+        game_state.game_state.hash(&mut hasher);
+        self.frame_hash = hasher.finish();
+    }
+}
+
+//the reading thread holds the connection open too, so it has to be shut down for the opponent
+//to hear that this side left
+impl Drop for Netplay {
+    fn drop(&mut self) {
+        if let Some(stream) = &self.stream {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+//puts the host's settings in place of this side's and has the simulation take both players'
+//keys from the network. the board is cleared to the host's size straight away
+pub fn setup_netplay(world: &mut World) {
+    let config = match world.resource::<Netplay>().match_config() {
+        Some(config) => config.clone(),
+        None => return,
+    };
+    world.insert_resource(config.settings);
+    world.insert_resource(config.handling);
+    world.insert_resource(GarbageSettings {
+        hole_rule: config.hole_rule,
+    });
+    world.insert_resource(config.attack_table);
+    world.insert_resource(InputSource::Network);
+    board_core::clear_board(world);
+}
+
+//picks up the local keys for the frame the delay puts them on and sends them, and takes in
+//whatever the opponent has sent. until the host's opponent connects there's no one to send to,
+//so the game waits on the first frame
+pub fn exchange_net_inputs(
+    keyboard_input: Res<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut netplay: ResMut<Netplay>,
+) {
+    if !netplay.is_active() {
+        return;
+    }
+    netplay.check_for_opponent();
+    if netplay.stream.is_none() {
+        return;
+    }
+    netplay.receive_messages();
+
+    let mut held = 0;
+    for (index, action) in GameAction::ALL.iter().enumerate() {
+        if key_bindings.action_held(&keyboard_input, *action) {
+            held |= 1 << index;
+        }
+    }
+    let space_held = keyboard_input.pressed(KeyCode::Space);
+    while netplay.next_local_frame <= netplay.frame + INPUT_DELAY {
+        let input = NetInput {
+            held,
+            start: space_held && !netplay.start_held,
+        };
+        netplay.start_held = space_held;
+        let frame = netplay.next_local_frame;
+        netplay.local_inputs.insert(frame, input);
+        netplay.send(&NetMessage::Input { frame, input });
+        netplay.next_local_frame += 1;
+    }
+}
+
+//a new match starts from a fresh game on both sides, on the frame someone pressed space. it
+//only does anything in the first player's pass, while their game is the one in the world
+pub fn start_networked_match(world: &mut World) {
+    if world.resource::<SimulationClock>().player_pass != 0
+        || !world.resource::<Netplay>().start_pressed()
+        || world.resource::<GameStateInfo>().game_state == GamePlayState::Playing
+    {
+        return;
+    }
+
+    crate::reset_game(world);
+}
+
+//holds down the actions the player whose frame it is had held on this frame
+pub fn apply_net_input(
+    netplay: Res<Netplay>,
+    clock: Res<SimulationClock>,
+    input_source: Res<InputSource>,
+    mut game_input: ResMut<Input<GameAction>>,
+) {
+    if *input_source != InputSource::Network {
+        return;
+    }
+
+    let input = netplay.input(clock.player_pass);
+    for action in GameAction::ALL {
+        let held = input.holds(action);
+        if held && !game_input.pressed(action) {
+            game_input.press(action);
+        } else if !held && game_input.pressed(action) {
+            game_input.release(action);
+        }
+    }
+}
+
+//hashes each player's board every so often and sends the hash once both are in, then moves on
+//to the next frame
+pub fn finish_net_frame(
+    clock: Res<SimulationClock>,
+    board_data: Res<BoardData>,
+    score: Res<Score>,
+    garbage_queue: Res<GarbageQueue>,
+    game_state: Res<GameStateInfo>,
+    mut netplay: ResMut<Netplay>,
+) {
+    if !netplay.is_active() {
+        return;
+    }
+
+    let frame = netplay.frame;
+    let hash_frame = frame % HASH_INTERVAL == 0;
+    if hash_frame {
+        netplay.hash_player(
+            clock.player_pass,
+            &board_data,
+            &score,
+            &garbage_queue,
+            &game_state,
+        );
+    }
+    if clock.player_pass + 1 < clock.players {
+        return;
+    }
+
+    if hash_frame {
+        let hash = std::mem::take(&mut netplay.frame_hash);
+        netplay.local_hashes.insert(frame, hash);
+        netplay.send(&NetMessage::Hash { frame, hash });
+        netplay.compare_hashes();
+    }
+    netplay.local_inputs.remove(&frame);
+    netplay.remote_inputs.remove(&frame);
+    netplay.frame += 1;
+}
+
+#[derive(Component)]
+pub struct NetplayText {}
+
+pub fn setup_netplay_text(
+    netplay: Res<Netplay>,
+    asset_server: Res<AssetServer>,
+    mut commands: Commands,
+) {
+    if !netplay.is_active() {
+        return;
+    }
+
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load(FONT_ASSET_PATH),
+                    font_size: NETPLAY_FONT_SIZE,
+                    color: TEXT_COLOR,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(NETPLAY_TEXT_TOP),
+                    left: Val::Px(NETPLAY_TEXT_LEFT),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(NetplayText {});
+}
+
+pub fn update_netplay_text(
+    netplay: Res<Netplay>,
+    mut text_query: Query<&mut Text, With<NetplayText>>,
+) {
+    let value = if netplay.disconnected {
+        "OPPONENT LEFT".to_string()
+    } else if let Some(frame) = netplay.desync_frame {
+        format!("DESYNC BY FRAME {}", frame)
+    } else if !netplay.frame_ready() {
+        "WAITING FOR OPPONENT".to_string()
+    } else {
+        format!("YOU ARE PLAYER {}", netplay.local_player + 1)
+    };
+    for mut text in text_query.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn match_config() -> MatchConfig {
+        let mut world = World::new();
+        MatchConfig {
+            match_seed: 39,
+            settings: GameSettings::from_world(&mut world),
+            handling: HandlingSettings::from_world(&mut world),
+            hole_rule: HoleRule::PerAttack,
+            attack_table: AttackTable::default(),
+        }
+    }
+
+    //hosts a match on a free port that sends the line as its hello, and joins it
+    fn join_host_sending(hello: String) -> Result<MatchConfig, NetplayError> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let host = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            writeln!(stream, "{}", hello).unwrap();
+        });
+        let joined = join(&address).map(|(_, config)| config);
+        host.join().unwrap();
+        joined
+    }
+
+    fn hello(config: MatchConfig) -> String {
+        serde_json::to_string(&NetMessage::Hello {
+            version: REPLAY_VERSION,
+            config,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn join_takes_the_hosts_config() {
+        let config = join_host_sending(hello(match_config())).unwrap();
+        assert_eq!(config.match_seed, 39);
+    }
+
+    #[test]
+    fn join_refuses_a_board_that_cant_be_played() {
+        let mut config = match_config();
+        config.settings.board_size.width = 17;
+        assert!(matches!(
+            join_host_sending(hello(config)),
+            Err(NetplayError::InvalidBoardSize(_))
+        ));
+    }
+
+    #[test]
+    fn join_refuses_a_hole_rule_that_cant_be_played() {
+        let mut config = match_config();
+        config.hole_rule = HoleRule::Messiness(2.0);
+        assert!(matches!(
+            join_host_sending(hello(config)),
+            Err(NetplayError::InvalidHoleRule(_))
+        ));
+    }
+
+    #[test]
+    fn join_refuses_anything_but_a_hello() {
+        let input = NetMessage::Input {
+            frame: 0,
+            input: NetInput::default(),
+        };
+        assert!(matches!(
+            join_host_sending(serde_json::to_string(&input).unwrap()),
+            Err(NetplayError::NoHello)
+        ));
+        assert!(matches!(
+            join_host_sending("{\"type\":\"hello\"".to_string()),
+            Err(NetplayError::Format(_))
+        ));
+    }
+}
@@ -0,0 +1,168 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::board::board_core::{
+    self, BlockId, BoardData, BoardSize, PieceType, PreviewBlock, PreviewSlot,
+};
+use crate::game_state_machine::{GamePlayState, GameStateInfo};
+use crate::input::GameAction;
+use crate::sound::SoundEffect;
+use crate::theme::Theme;
+use crate::versus::VersusSettings;
+use crate::{GameSettings, LockController, PieceRandomizerType, Randomizer};
+
+//how many upcoming pieces are shown
+pub const QUEUE_LENGTH: usize = 5;
+
+//the pieces coming up next and the piece put aside with hold
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PieceQueue {
+    pub next_pieces: VecDeque<PieceType>,
+    pub held_piece: Option<PieceType>,
+    //hold can only be used once for each piece that spawns
+    pub hold_used: bool,
+    //set by bots that want to hold without pressing the key
+    pub hold_requested: bool,
+}
+
+impl FromWorld for PieceQueue {
+    fn from_world(_world: &mut World) -> Self {
+        PieceQueue {
+            next_pieces: VecDeque::new(),
+            held_piece: None,
+            hold_used: false,
+            hold_requested: false,
+        }
+    }
+}
+
+impl PieceQueue {
+    fn fill(&mut self, randomizer: &mut Randomizer, game_settings: &GameSettings) {
+        while self.next_pieces.len() < QUEUE_LENGTH {
+            self.next_pieces
+                .push_back(match game_settings.game_randomizer {
+                    PieceRandomizerType::Bag => randomizer.next_block(),
+                    PieceRandomizerType::TrueRandom => randomizer.next_block(),
+                    PieceRandomizerType::TrueRandomWithoutRepeats => randomizer.next_block(),
+                });
+        }
+    }
+
+    //takes the piece at the front of the queue for the next spawn
+    pub fn next_piece(
+        &mut self,
+        randomizer: &mut Randomizer,
+        game_settings: &GameSettings,
+    ) -> PieceType {
+        self.fill(randomizer, game_settings);
+        let piece_type = self.next_pieces.pop_front().unwrap();
+        self.fill(randomizer, game_settings);
+        self.hold_used = false;
+        piece_type
+    }
+}
+
+//swaps the player's piece with the held one, or with the next piece if nothing is held yet
+#[allow(clippy::too_many_arguments)]
+pub fn handle_hold(
+    game_input: Res<Input<GameAction>>,
+    game_settings: Res<GameSettings>,
+    mut randomizer: ResMut<Randomizer>,
+    mut piece_queue: ResMut<PieceQueue>,
+    mut lock_controller: ResMut<LockController>,
+    mut game_state_info: ResMut<GameStateInfo>,
+    game_play_writer: EventWriter<GamePlayState>,
+    mut sound_writer: EventWriter<SoundEffect>,
+    mut board_data: ResMut<BoardData>,
+    mut highest_block_id: ResMut<BlockId>,
+) {
+    let hold_pressed = game_input.just_pressed(GameAction::Hold) || piece_queue.hold_requested;
+    if piece_queue.hold_requested {
+        piece_queue.hold_requested = false;
+    }
+
+    let current_piece = match board_data.active_piece {
+        Some(piece) => piece.piece_type,
+        None => return,
+    };
+    if !hold_pressed
+        || piece_queue.hold_used
+        || game_state_info.game_state != GamePlayState::Playing
+    {
+        return;
+    }
+
+    board_core::remove_player_piece(&mut board_data);
+    let new_piece = match piece_queue.held_piece.take() {
+        Some(held_piece) => held_piece,
+        None => piece_queue.next_piece(&mut randomizer, &game_settings),
+    };
+    piece_queue.held_piece = Some(current_piece);
+    piece_queue.hold_used = true;
+
+    if !board_core::piece_fits_at_spawn(&board_data, new_piece) {
+        game_state_info.change_game_play_state(GamePlayState::Lose, game_play_writer);
+        return;
+    }
+    board_core::spawn_new_block(&mut board_data, new_piece, &mut highest_block_id);
+    lock_controller.frames_grounded = 0;
+    lock_controller.lock_resets = 0;
+    sound_writer.send(SoundEffect::Hold);
+}
+
+//redraws the queue and hold whenever they change. versus draws both players' itself
+pub fn update_piece_previews(
+    theme: Res<Theme>,
+    game_settings: Res<GameSettings>,
+    piece_queue: Res<PieceQueue>,
+    versus_settings: Res<VersusSettings>,
+    mut commands: Commands,
+    preview_query: Query<Entity, With<PreviewBlock>>,
+) {
+    let changed = piece_queue.is_changed() || theme.is_changed() || game_settings.is_changed();
+    if !changed || versus_settings.enabled {
+        return;
+    }
+
+    for entity in preview_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    spawn_queue_previews(
+        &mut commands,
+        &theme,
+        game_settings.board_size,
+        &piece_queue,
+        Vec3::ZERO,
+    );
+}
+
+//the previews go beside a board of this size
+pub fn spawn_queue_previews(
+    commands: &mut Commands,
+    theme: &Theme,
+    size: BoardSize,
+    piece_queue: &PieceQueue,
+    offset: Vec3,
+) {
+    if let Some(held_piece) = piece_queue.held_piece {
+        board_core::spawn_preview_piece(
+            commands,
+            theme,
+            size,
+            held_piece,
+            PreviewSlot::Hold,
+            offset,
+        );
+    }
+    for (index, piece_type) in piece_queue.next_pieces.iter().enumerate() {
+        board_core::spawn_preview_piece(
+            commands,
+            theme,
+            size,
+            *piece_type,
+            PreviewSlot::Next(index),
+            offset,
+        );
+    }
+}
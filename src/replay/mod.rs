@@ -0,0 +1,500 @@
+use bevy::prelude::*;
+use bevy::window::WindowCloseRequested;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::board::board_core::InvalidBoardSize;
+use crate::high_scores::APP_DIRECTORY;
+use crate::input::{GameAction, InputSource};
+use crate::{GameSettings, HandlingSettings, Randomizer, SimulationClock};
+
+//bump this whenever the simulation or the file layout changes so old replays are refused
+//instead of playing back a different game
+pub const REPLAY_VERSION: u32 = 1;
+const REPLAY_DIRECTORY: &str = "replays";
+const REPLAY_EXTENSION: &str = "replay";
+
+const SEEK_FRAMES: u32 = 300;
+const MIN_PLAYBACK_SPEED: f32 = 0.25;
+const MAX_PLAYBACK_SPEED: f32 = 8.0;
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Format(bincode::Error),
+    UnsupportedVersion(u32),
+    InvalidBoardSize(InvalidBoardSize),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(error) => write!(f, "{}", error),
+            ReplayError::Format(error) => write!(f, "replay file is corrupt: {}", error),
+            ReplayError::UnsupportedVersion(version) => write!(
+                f,
+                "replay was recorded with version {}, this build plays version {}",
+                version, REPLAY_VERSION
+            ),
+            ReplayError::InvalidBoardSize(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(error: io::Error) -> Self {
+        ReplayError::Io(error)
+    }
+}
+
+impl From<bincode::Error> for ReplayError {
+    fn from(error: bincode::Error) -> Self {
+        ReplayError::Format(error)
+    }
+}
+
+//an action changing state on a simulation frame, counted from the start of the game
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct ReplayInput {
+    pub frame: u32,
+    pub action: GameAction,
+    pub pressed: bool,
+}
+
+//everything needed to simulate a game again exactly as it was played
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Replay {
+    pub seed: u64,
+    pub settings: GameSettings,
+    pub handling: HandlingSettings,
+    pub length: u32,
+    pub inputs: Vec<ReplayInput>,
+}
+
+impl Replay {
+    //the version is written on its own first so it can be checked before reading the rest
+    pub fn save(&self, path: &Path) -> Result<(), ReplayError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        bincode::serialize_into(&mut writer, &REPLAY_VERSION)?;
+        bincode::serialize_into(&mut writer, self)?;
+        Ok(())
+    }
+
+    //the settings are checked here, before the replay is played, since a file that's been
+    //edited or corrupted can still read fine
+    pub fn load(path: &Path) -> Result<Replay, ReplayError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let version: u32 = bincode::deserialize_from(&mut reader)?;
+        if version != REPLAY_VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+        let replay: Replay = bincode::deserialize_from(&mut reader)?;
+        replay
+            .settings
+            .board_size
+            .validate()
+            .map_err(ReplayError::InvalidBoardSize)?;
+        Ok(replay)
+    }
+}
+
+//records the game being played so it can be saved when the player leaves
+pub struct ReplayRecorder {
+    replay: Option<Replay>,
+}
+
+impl FromWorld for ReplayRecorder {
+    fn from_world(_world: &mut World) -> Self {
+        ReplayRecorder { replay: None }
+    }
+}
+
+impl ReplayRecorder {
+    pub fn start(&mut self, seed: u64, settings: &GameSettings, handling: &HandlingSettings) {
+        self.replay = Some(Replay {
+            seed,
+            settings: settings.clone(),
+            handling: handling.clone(),
+            length: 0,
+            inputs: vec![],
+        });
+    }
+}
+
+//plays back a replay passed with --replay <file>
+pub struct ReplayPlayer {
+    replay: Option<Replay>,
+    next_input: usize,
+    restart_requested: bool,
+}
+
+impl FromWorld for ReplayPlayer {
+    fn from_world(_world: &mut World) -> Self {
+        let arguments: Vec<String> = std::env::args().collect();
+        let mut replay = None;
+        if let Some(index) = arguments.iter().position(|argument| argument == "--replay") {
+            match arguments.get(index + 1) {
+                Some(path) => match Replay::load(Path::new(path)) {
+                    Ok(loaded_replay) => {
+                        info!("playing replay {}", path);
+                        replay = Some(loaded_replay);
+                    }
+                    Err(error) => warn!("couldn't load replay {}: {}", path, error),
+                },
+                None => warn!("--replay needs a file to play"),
+            }
+        }
+
+        ReplayPlayer {
+            restart_requested: replay.is_some(),
+            replay,
+            next_input: 0,
+        }
+    }
+}
+
+impl ReplayPlayer {
+    pub fn is_playing(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    pub fn restart_requested(&self) -> bool {
+        self.restart_requested
+    }
+}
+
+pub fn setup_replay_playback(
+    replay_player: Res<ReplayPlayer>,
+    mut input_source: ResMut<InputSource>,
+) {
+    if replay_player.is_playing() {
+        *input_source = InputSource::Replay;
+    }
+}
+
+//resets the game back to the replay's first frame. seeking backwards goes through here and then
+//simulates forward to the frame it wanted
+pub fn restart_replay(world: &mut World) {
+    let mut replay_player = world.resource_mut::<ReplayPlayer>();
+    if !replay_player.restart_requested {
+        return;
+    }
+    replay_player.restart_requested = false;
+    replay_player.next_input = 0;
+    let replay = match &replay_player.replay {
+        Some(replay) => replay.clone(),
+        None => return,
+    };
+
+    //the settings go in first so the board is reset to the replay's size
+    world.insert_resource(replay.settings);
+    world.insert_resource(replay.handling);
+    crate::reset_game(world);
+    world.insert_resource(Randomizer::new(replay.seed));
+    crate::start_game(world);
+}
+
+pub fn play_replay_input(
+    mut replay_player: ResMut<ReplayPlayer>,
+    mut clock: ResMut<SimulationClock>,
+    mut game_input: ResMut<Input<GameAction>>,
+) {
+    let replay_player = &mut *replay_player;
+    let replay = match &replay_player.replay {
+        Some(replay) => replay,
+        None => return,
+    };
+
+    while let Some(input) = replay.inputs.get(replay_player.next_input) {
+        if input.frame > clock.frame {
+            break;
+        }
+        if input.pressed {
+            game_input.press(input.action);
+        } else {
+            game_input.release(input.action);
+        }
+        replay_player.next_input += 1;
+    }
+
+    //the replay's length is how many frames were played, so this one is the last
+    if clock.frame + 1 >= replay.length {
+        clock.paused = true;
+    }
+}
+
+pub fn record_replay_input(
+    clock: Res<SimulationClock>,
+    game_input: Res<Input<GameAction>>,
+    mut recorder: ResMut<ReplayRecorder>,
+) {
+    let replay = match &mut recorder.replay {
+        Some(replay) => replay,
+        None => return,
+    };
+    //every frame the game has run, the one that ended it included
+    replay.length = clock.frame + 1;
+
+    for action in game_input.get_just_pressed() {
+        replay.inputs.push(ReplayInput {
+            frame: clock.frame,
+            action: *action,
+            pressed: true,
+        });
+    }
+    for action in game_input.get_just_released() {
+        replay.inputs.push(ReplayInput {
+            frame: clock.frame,
+            action: *action,
+            pressed: false,
+        });
+    }
+}
+
+//p pauses, left/right seek, up/down change speed, home restarts and period steps one frame
+pub fn handle_replay_controls(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut replay_player: ResMut<ReplayPlayer>,
+    mut clock: ResMut<SimulationClock>,
+) {
+    if !replay_player.is_playing() {
+        return;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::P) {
+        clock.paused = !clock.paused;
+    }
+    if keyboard_input.just_pressed(KeyCode::Up) {
+        clock.speed = (clock.speed * 2.).min(MAX_PLAYBACK_SPEED);
+        info!("replay speed {}x", clock.speed);
+    }
+    if keyboard_input.just_pressed(KeyCode::Down) {
+        clock.speed = (clock.speed / 2.).max(MIN_PLAYBACK_SPEED);
+        info!("replay speed {}x", clock.speed);
+    }
+
+    let mut seek_to = None;
+    if keyboard_input.just_pressed(KeyCode::Right) {
+        seek_to = Some(clock.frame + SEEK_FRAMES);
+    }
+    if keyboard_input.just_pressed(KeyCode::Left) {
+        seek_to = Some(clock.frame.saturating_sub(SEEK_FRAMES));
+    }
+    if keyboard_input.just_pressed(KeyCode::Period) {
+        seek_to = Some(clock.frame + 1);
+    }
+    if keyboard_input.just_pressed(KeyCode::Home) {
+        seek_to = Some(0);
+    }
+
+    if let Some(mut frame) = seek_to {
+        if let Some(replay) = &replay_player.replay {
+            frame = frame.min(replay.length);
+        }
+        if frame < clock.frame {
+            replay_player.restart_requested = true;
+        }
+        clock.seek_target = Some(frame);
+    }
+}
+
+//f6 saves the replay of the current game, leaving with escape or by closing the window saves it
+//too
+pub fn save_replay(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut close_reader: EventReader<WindowCloseRequested>,
+    recorder: Res<ReplayRecorder>,
+) {
+    let closing = close_reader.iter().count() > 0;
+    if !(keyboard_input.just_pressed(KeyCode::F6)
+        || keyboard_input.just_pressed(KeyCode::Escape)
+        || closing)
+    {
+        return;
+    }
+    let replay = match &recorder.replay {
+        Some(replay) => replay,
+        None => return,
+    };
+
+    let directory = match replay_directory() {
+        Some(directory) => directory,
+        None => {
+            warn!("no user data directory, the replay can't be saved");
+            return;
+        }
+    };
+    match write_replay(replay, &directory) {
+        Ok(path) => info!("saved replay to {}", path.display()),
+        Err(error) => warn!("couldn't save replay: {}", error),
+    }
+}
+
+//in the user's data directory with the high scores and settings
+fn replay_directory() -> Option<PathBuf> {
+    dirs::data_dir().map(|directory| directory.join(APP_DIRECTORY).join(REPLAY_DIRECTORY))
+}
+
+fn write_replay(replay: &Replay, directory: &Path) -> Result<PathBuf, ReplayError> {
+    fs::create_dir_all(directory)?;
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let path = directory.join(format!("{}.{}", timestamp, REPLAY_EXTENSION));
+    replay.save(&path)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::board_core::{BoardData, BoardSize, LineClearGravity};
+    use crate::game_state_machine::{GamePlayState, GameStateInfo};
+    use crate::tests::{headless_app, run_frames, set_key};
+    use crate::Score;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const KEYS: [KeyCode; 7] = [
+        KeyCode::A,
+        KeyCode::D,
+        KeyCode::S,
+        KeyCode::K,
+        KeyCode::J,
+        KeyCode::L,
+        KeyCode::Space,
+    ];
+
+    //plays a game on the keyboard, holding keys picked from the seed for a few frames at a
+    //time, until it has run for the frames or topped out, and gives back its replay. the board
+    //is narrow so rows get filled, and each kind of gravity gets a turn
+    fn record_game(seed: u64, frames: u32) -> (App, Replay) {
+        let mut app = headless_app();
+        let mut settings = app.world.resource::<GameSettings>().clone();
+        settings.board_size = BoardSize {
+            width: 5,
+            visible_height: 12,
+            buffer_height: 4,
+        };
+        settings.line_clear_gravity = [
+            LineClearGravity::Naive,
+            LineClearGravity::Sticky,
+            LineClearGravity::Cascade,
+        ][seed as usize % 3];
+        app.insert_resource(BoardData::new(settings.board_size))
+            .insert_resource(settings);
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        set_key(&mut app, KeyCode::Space, true);
+        run_frames(&mut app, 1);
+        set_key(&mut app, KeyCode::Space, false);
+        while app.world.resource::<SimulationClock>().frame < frames
+            && app.world.resource::<GameStateInfo>().game_state == GamePlayState::Playing
+        {
+            let key = KEYS[rng.gen_range(0..KEYS.len())];
+            set_key(&mut app, key, true);
+            run_frames(&mut app, rng.gen_range(1..20));
+            set_key(&mut app, key, false);
+            run_frames(&mut app, rng.gen_range(1..4));
+        }
+
+        let replay = app
+            .world
+            .resource::<ReplayRecorder>()
+            .replay
+            .clone()
+            .unwrap();
+        (app, replay)
+    }
+
+    fn play_replay(replay: Replay) -> App {
+        let length = replay.length;
+        let mut app = headless_app();
+        app.insert_resource(ReplayPlayer {
+            replay: Some(replay),
+            next_input: 0,
+            restart_requested: true,
+        })
+        .insert_resource(InputSource::Replay);
+        for _ in 0..length + 1 {
+            run_frames(&mut app, 1);
+        }
+        app
+    }
+
+    #[test]
+    fn replays_play_back_the_same_game() {
+        //the game picks its own piece seed, so play on until some game has cleared lines
+        let mut lines_cleared = 0;
+        for seed in 0..100 {
+            if seed >= 12 && lines_cleared > 0 {
+                break;
+            }
+            let (recorded, replay) = record_game(seed, 1200);
+            assert!(!replay.inputs.is_empty());
+            let played = play_replay(replay.clone());
+
+            assert_eq!(
+                played.world.resource::<SimulationClock>().frame,
+                recorded.world.resource::<SimulationClock>().frame
+            );
+            assert!(
+                recorded.world.resource::<BoardData>().filled()
+                    == played.world.resource::<BoardData>().filled(),
+                "seed {} played back a different board",
+                seed
+            );
+            let recorded_score = recorded.world.resource::<Score>();
+            let played_score = played.world.resource::<Score>();
+            assert_eq!(recorded_score.score, played_score.score);
+            assert_eq!(recorded_score.lines_cleared, played_score.lines_cleared);
+            lines_cleared += recorded_score.lines_cleared;
+            assert_eq!(
+                recorded.world.resource::<GameStateInfo>().game_state,
+                played.world.resource::<GameStateInfo>().game_state
+            );
+        }
+        //otherwise the games didn't get as far as clearing anything to compare
+        assert!(lines_cleared > 0);
+    }
+
+    #[test]
+    fn seeking_back_plays_the_same_game_again() {
+        let (_, replay) = record_game(9, 1200);
+        let mut app = play_replay(replay.clone());
+        let board = app.world.resource::<BoardData>().filled().clone();
+        let frame = app.world.resource::<SimulationClock>().frame;
+
+        //what pressing home and then seeking to the end does
+        app.world.resource_mut::<ReplayPlayer>().restart_requested = true;
+        app.world.resource_mut::<SimulationClock>().seek_target = Some(replay.length);
+        while app
+            .world
+            .resource::<SimulationClock>()
+            .seek_target
+            .is_some()
+        {
+            app.update();
+        }
+
+        assert_eq!(app.world.resource::<SimulationClock>().frame, frame);
+        assert!(app.world.resource::<BoardData>().filled() == &board);
+    }
+
+    #[test]
+    fn replays_with_a_board_that_cant_be_played_are_refused() {
+        let (_, mut replay) = record_game(0, 10);
+        replay.settings.board_size.width = 40;
+        let path =
+            std::env::temp_dir().join(format!("tetris_rs_test_{}.replay", std::process::id()));
+        replay.save(&path).unwrap();
+        let loaded = Replay::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(loaded, Err(ReplayError::InvalidBoardSize(_))));
+    }
+}
@@ -0,0 +1,306 @@
+use bevy::prelude::*;
+use bevy::window::WindowCloseRequested;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use crate::board::board_core::{self, InvalidBoardSize, SavedBoard};
+use crate::finesse::FinesseTracker;
+use crate::game_state_machine::{GameFlow, GamePlayState, GameStateInfo};
+use crate::high_scores::APP_DIRECTORY;
+use crate::piece_queue::PieceQueue;
+use crate::replay::ReplayPlayer;
+use crate::stats::GameStats;
+use crate::versus::VersusSettings;
+use crate::{
+    GameSettings, GameTickInfo, HandlingSettings, InputController, LockController, Randomizer,
+    Score, SimulationClock, SpawnController,
+};
+
+//bump this whenever SavedGame changes. saves from other versions are skipped, not loaded
+pub const SAVE_VERSION: u32 = 1;
+const SAVE_DIRECTORY: &str = "saves";
+const SAVE_FILE_NAME: &str = "game.sav";
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    Format(bincode::Error),
+    UnsupportedVersion(u32),
+    InvalidBoardSize(InvalidBoardSize),
+    //the board was saved at another size than the settings saved with it
+    MismatchedBoardSize,
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(error) => write!(f, "{}", error),
+            SaveError::Format(error) => write!(f, "save file is corrupt: {}", error),
+            SaveError::UnsupportedVersion(version) => write!(
+                f,
+                "save is from version {}, this build loads version {}",
+                version, SAVE_VERSION
+            ),
+            SaveError::InvalidBoardSize(error) => write!(f, "{}", error),
+            SaveError::MismatchedBoardSize => write!(f, "saved board and settings differ in size"),
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(error: io::Error) -> Self {
+        SaveError::Io(error)
+    }
+}
+
+impl From<bincode::Error> for SaveError {
+    fn from(error: bincode::Error) -> Self {
+        SaveError::Format(error)
+    }
+}
+
+//everything needed to carry on a game where it was left
+#[derive(Serialize, Deserialize)]
+struct SavedGame {
+    board: SavedBoard,
+    seed: u64,
+    pieces_dealt: u64,
+    score: u64,
+    lines_cleared: u32,
+    chain: u32,
+    stats: GameStats,
+    finesse: FinesseTracker,
+    piece_queue: PieceQueue,
+    game_flow_state: GameFlow,
+    spawn_piece: bool,
+    entry_delay_left: u32,
+    frame: u32,
+    game_tick_info: GameTickInfo,
+    input_controller: InputController,
+    lock_controller: LockController,
+    game_settings: GameSettings,
+    handling_settings: HandlingSettings,
+}
+
+impl SavedGame {
+    //the game going on in the world
+    fn from_game(world: &World) -> SavedGame {
+        SavedGame {
+            board: board_core::save_board(world),
+            seed: world.resource::<Randomizer>().seed,
+            pieces_dealt: world.resource::<Randomizer>().pieces_dealt,
+            score: world.resource::<Score>().score,
+            lines_cleared: world.resource::<Score>().lines_cleared,
+            chain: world.resource::<Score>().chain,
+            stats: world.resource::<GameStats>().clone(),
+            finesse: world.resource::<FinesseTracker>().clone(),
+            piece_queue: world.resource::<PieceQueue>().clone(),
+            game_flow_state: world.resource::<GameStateInfo>().game_flow_state,
+            spawn_piece: world.resource::<SpawnController>().spawn_piece,
+            entry_delay_left: world.resource::<SpawnController>().entry_delay_left,
+            frame: world.resource::<SimulationClock>().frame,
+            game_tick_info: world.resource::<GameTickInfo>().clone(),
+            input_controller: world.resource::<InputController>().clone(),
+            lock_controller: world.resource::<LockController>().clone(),
+            game_settings: world.resource::<GameSettings>().clone(),
+            handling_settings: world.resource::<HandlingSettings>().clone(),
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<(), SaveError> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        bincode::serialize_into(&mut writer, &SAVE_VERSION)?;
+        bincode::serialize_into(&mut writer, self)?;
+        Ok(())
+    }
+
+    fn load(path: &Path) -> Result<SavedGame, SaveError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let version: u32 = bincode::deserialize_from(&mut reader)?;
+        if version != SAVE_VERSION {
+            return Err(SaveError::UnsupportedVersion(version));
+        }
+        Ok(bincode::deserialize_from(&mut reader)?)
+    }
+
+    //a save that's been edited or corrupted can still read fine, so the board it would be
+    //restored at is checked before anything is made from it
+    fn validate(&self) -> Result<(), SaveError> {
+        let size = self.game_settings.board_size;
+        size.validate().map_err(SaveError::InvalidBoardSize)?;
+        if self.board.size() != size {
+            return Err(SaveError::MismatchedBoardSize);
+        }
+        Ok(())
+    }
+}
+
+//in the user's data directory with the high scores and settings
+fn save_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|directory| {
+        directory
+            .join(APP_DIRECTORY)
+            .join(SAVE_DIRECTORY)
+            .join(SAVE_FILE_NAME)
+    })
+}
+
+//whether the window's close button was pressed this frame. the save runs as an exclusive system,
+//which can't read events, so this is picked up for it first
+#[derive(Default)]
+pub struct WindowClosing {
+    closing: bool,
+}
+
+pub fn notice_window_closing(
+    mut close_reader: EventReader<WindowCloseRequested>,
+    mut window_closing: ResMut<WindowClosing>,
+) {
+    window_closing.closing = close_reader.iter().count() > 0;
+}
+
+//saves the game when it's paused or when the player leaves, with escape or by closing the
+//window. versus matches aren't saved
+pub fn save_game_on_pause_or_quit(world: &mut World) {
+    let keyboard_input = world.resource::<Input<KeyCode>>();
+    let quitting =
+        keyboard_input.just_pressed(KeyCode::Escape) || world.resource::<WindowClosing>().closing;
+    let pausing =
+        keyboard_input.just_pressed(KeyCode::P) && world.resource::<SimulationClock>().paused;
+
+    if !(quitting || pausing)
+        || world.resource::<ReplayPlayer>().is_playing()
+        || world.resource::<VersusSettings>().enabled
+        || world.resource::<GameStateInfo>().game_state != GamePlayState::Playing
+    {
+        return;
+    }
+
+    let path = match save_path() {
+        Some(path) => path,
+        None => {
+            warn!("no user data directory, the game can't be saved");
+            return;
+        }
+    };
+    let saved_game = SavedGame::from_game(world);
+    match saved_game.save(&path) {
+        Ok(()) => info!("saved game to {}", path.display()),
+        Err(error) => warn!("couldn't save game: {}", error),
+    }
+}
+
+//picks up the game saved last time, paused so the player can get ready. a save that can't be
+//read is left alone and a new game starts instead, and one that reads but can't be played is
+//thrown away
+pub fn resume_saved_game(world: &mut World) {
+    let path = match save_path() {
+        Some(path) => path,
+        None => return,
+    };
+    if world.resource::<ReplayPlayer>().is_playing()
+        || world.resource::<VersusSettings>().enabled
+        || !path.exists()
+    {
+        return;
+    }
+
+    let saved_game = match SavedGame::load(&path) {
+        Ok(saved_game) => saved_game,
+        Err(error) => {
+            warn!("couldn't resume saved game: {}", error);
+            return;
+        }
+    };
+    if let Err(error) = fs::remove_file(&path) {
+        warn!("couldn't remove resumed save: {}", error);
+    }
+    if let Err(error) = saved_game.validate() {
+        warn!("discarding saved game: {}", error);
+        return;
+    }
+
+    //the settings go in first so everything reset is made for the saved board's size
+    world.insert_resource(saved_game.game_settings);
+    crate::reset_game(world);
+    board_core::restore_board(world, &saved_game.board);
+    world.insert_resource(Randomizer::resume(saved_game.seed, saved_game.pieces_dealt));
+    world.insert_resource(Score {
+        score: saved_game.score,
+        lines_cleared: saved_game.lines_cleared,
+        chain: saved_game.chain,
+    });
+    world.insert_resource(SpawnController {
+        spawn_piece: saved_game.spawn_piece,
+        entry_delay_left: saved_game.entry_delay_left,
+    });
+    world.insert_resource(saved_game.stats);
+    world.insert_resource(saved_game.finesse);
+    world.insert_resource(saved_game.piece_queue);
+    world.insert_resource(saved_game.game_tick_info);
+    world.insert_resource(saved_game.input_controller);
+    world.insert_resource(saved_game.lock_controller);
+    world.insert_resource(saved_game.handling_settings);
+
+    crate::start_game(world);
+    world.resource_mut::<GameStateInfo>().game_flow_state = saved_game.game_flow_state;
+    let mut clock = world.resource_mut::<SimulationClock>();
+    clock.frame = saved_game.frame;
+    clock.paused = true;
+
+    info!("resumed saved game, press p to continue");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{headless_app, run_frames, set_key};
+
+    fn game_in_progress() -> App {
+        let mut app = headless_app();
+        set_key(&mut app, KeyCode::Space, true);
+        run_frames(&mut app, 1);
+        set_key(&mut app, KeyCode::Space, false);
+        run_frames(&mut app, 60);
+        app
+    }
+
+    #[test]
+    fn saved_games_load_back() {
+        let app = game_in_progress();
+        let path = std::env::temp_dir().join(format!("tetris_rs_test_{}.sav", std::process::id()));
+        SavedGame::from_game(&app.world).save(&path).unwrap();
+        let loaded = SavedGame::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert!(loaded.validate().is_ok());
+        assert_eq!(loaded.frame, app.world.resource::<SimulationClock>().frame);
+        assert_eq!(loaded.seed, app.world.resource::<Randomizer>().seed);
+    }
+
+    #[test]
+    fn saves_with_a_board_that_cant_be_played_are_refused() {
+        let app = game_in_progress();
+
+        let mut saved_game = SavedGame::from_game(&app.world);
+        saved_game.game_settings.board_size.width = 40;
+        assert!(matches!(
+            saved_game.validate(),
+            Err(SaveError::InvalidBoardSize(_))
+        ));
+
+        let mut saved_game = SavedGame::from_game(&app.world);
+        saved_game.game_settings.board_size.width = 12;
+        assert!(matches!(
+            saved_game.validate(),
+            Err(SaveError::MismatchedBoardSize)
+        ));
+    }
+}
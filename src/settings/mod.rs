@@ -0,0 +1,140 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use crate::high_scores::APP_DIRECTORY;
+use crate::theme::{Palette, DEFAULT_THEME};
+
+//bump this whenever Settings changes. settings from other versions are started over
+pub const SETTINGS_VERSION: u32 = 4;
+const SETTINGS_FILE_NAME: &str = "settings.dat";
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Io(io::Error),
+    Format(bincode::Error),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::Io(error) => write!(f, "{}", error),
+            SettingsError::Format(error) => write!(f, "settings file is corrupt: {}", error),
+            SettingsError::UnsupportedVersion(version) => write!(
+                f,
+                "settings are from version {}, this build reads version {}",
+                version, SETTINGS_VERSION
+            ),
+        }
+    }
+}
+
+impl From<io::Error> for SettingsError {
+    fn from(error: io::Error) -> Self {
+        SettingsError::Io(error)
+    }
+}
+
+impl From<bincode::Error> for SettingsError {
+    fn from(error: bincode::Error) -> Self {
+        SettingsError::Format(error)
+    }
+}
+
+//what the player has picked, kept between games
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    //volumes go from 0 for silent to 1 for full
+    pub sfx_volume: f32,
+    pub music_volume: f32,
+    //the directory of the theme in assets/themes
+    pub theme: String,
+    //block colours for colour blind players, on top of the theme
+    pub palette: Palette,
+    //whether blocks show the letter of the piece they came from
+    pub block_glyphs: bool,
+    //the size of the window in logical pixels when it isn't fullscreen
+    pub window_width: f32,
+    pub window_height: f32,
+    pub fullscreen: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            sfx_volume: 0.7,
+            music_volume: 0.5,
+            theme: DEFAULT_THEME.to_string(),
+            palette: Palette::Theme,
+            block_glyphs: false,
+            window_width: 1920.,
+            window_height: 1080.,
+            fullscreen: false,
+        }
+    }
+}
+
+impl Settings {
+    fn save(&self, path: &Path) -> Result<(), SettingsError> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        bincode::serialize_into(&mut writer, &SETTINGS_VERSION)?;
+        bincode::serialize_into(&mut writer, self)?;
+        Ok(())
+    }
+
+    fn load(path: &Path) -> Result<Settings, SettingsError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let version: u32 = bincode::deserialize_from(&mut reader)?;
+        if version != SETTINGS_VERSION {
+            return Err(SettingsError::UnsupportedVersion(version));
+        }
+        Ok(bincode::deserialize_from(&mut reader)?)
+    }
+}
+
+//the settings in the user's data directory, next to the high scores
+pub struct UserSettings {
+    pub settings: Settings,
+    path: Option<PathBuf>,
+}
+
+impl UserSettings {
+    //read before the app is built, since the window is opened from them
+    pub fn load() -> UserSettings {
+        let path = dirs::data_dir()
+            .map(|directory| directory.join(APP_DIRECTORY).join(SETTINGS_FILE_NAME));
+
+        let settings = match &path {
+            Some(path) if path.exists() => match Settings::load(path) {
+                Ok(settings) => settings,
+                Err(error) => {
+                    warn!("couldn't load settings: {}", error);
+                    Settings::default()
+                }
+            },
+            Some(_) => Settings::default(),
+            None => {
+                warn!("no user data directory, settings won't be kept");
+                Settings::default()
+            }
+        };
+
+        UserSettings { settings, path }
+    }
+
+    //writes the settings out straight away, so a change is kept even if the game is closed
+    pub fn save(&self) {
+        if let Some(path) = &self.path {
+            if let Err(error) = self.settings.save(path) {
+                warn!("couldn't save settings: {}", error);
+            }
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use bevy::asset::LoadState;
+use bevy::audio::AudioSink;
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+use crate::board::board_core::PieceType;
+use crate::board::move_gen::Spin;
+use crate::game_state_machine::GamePlayState;
+use crate::settings::UserSettings;
+use crate::stats::GameStats;
+use crate::{PieceLockedEvent, FONT_ASSET_PATH, TEXT_COLOR};
+
+const MUSIC_PATH: &str = "sounds/music.wav";
+
+//how much each press of a volume key turns it up or down
+const VOLUME_STEP: f32 = 0.1;
+
+const VOLUME_FONT_SIZE: f32 = 24.0;
+const VOLUME_TEXT_LEFT: Val = Val::Px(5.0);
+const VOLUME_TEXT_TOP: Val = Val::Px(1040.0);
+
+//something in the game that makes a noise. the simulation sends these as it plays
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SoundEffect {
+    Move,
+    Rotate,
+    SoftDrop,
+    HardDrop,
+    Lock,
+    Single,
+    Double,
+    Triple,
+    Tetris,
+    TSpin,
+    Combo,
+    LevelUp,
+    Hold,
+    GameOver,
+}
+
+const ALL_SOUND_EFFECTS: [SoundEffect; 14] = [
+    SoundEffect::Move,
+    SoundEffect::Rotate,
+    SoundEffect::SoftDrop,
+    SoundEffect::HardDrop,
+    SoundEffect::Lock,
+    SoundEffect::Single,
+    SoundEffect::Double,
+    SoundEffect::Triple,
+    SoundEffect::Tetris,
+    SoundEffect::TSpin,
+    SoundEffect::Combo,
+    SoundEffect::LevelUp,
+    SoundEffect::Hold,
+    SoundEffect::GameOver,
+];
+
+impl SoundEffect {
+    fn path(&self) -> &str {
+        match self {
+            SoundEffect::Move => "sounds/move.wav",
+            SoundEffect::Rotate => "sounds/rotate.wav",
+            SoundEffect::SoftDrop => "sounds/soft_drop.wav",
+            SoundEffect::HardDrop => "sounds/hard_drop.wav",
+            SoundEffect::Lock => "sounds/lock.wav",
+            SoundEffect::Single => "sounds/single.wav",
+            SoundEffect::Double => "sounds/double.wav",
+            SoundEffect::Triple => "sounds/triple.wav",
+            SoundEffect::Tetris => "sounds/tetris.wav",
+            SoundEffect::TSpin => "sounds/t_spin.wav",
+            SoundEffect::Combo => "sounds/combo.wav",
+            SoundEffect::LevelUp => "sounds/level_up.wav",
+            SoundEffect::Hold => "sounds/hold.wav",
+            SoundEffect::GameOver => "sounds/game_over.wav",
+        }
+    }
+
+    //the sound for clearing this many lines with one piece
+    fn line_clear(lines: u32) -> Option<SoundEffect> {
+        match lines {
+            0 => None,
+            1 => Some(SoundEffect::Single),
+            2 => Some(SoundEffect::Double),
+            3 => Some(SoundEffect::Triple),
+            _ => Some(SoundEffect::Tetris),
+        }
+    }
+}
+
+//every sound, loaded when the game starts
+pub struct SoundLibrary {
+    effects: HashMap<SoundEffect, Handle<AudioSource>>,
+    music: Handle<AudioSource>,
+    //files that couldn't be loaded, so each is only warned about once
+    missing: HashSet<String>,
+}
+
+impl FromWorld for SoundLibrary {
+    fn from_world(world: &mut World) -> Self {
+        let asset_server = world.resource::<AssetServer>();
+        SoundLibrary {
+            effects: ALL_SOUND_EFFECTS
+                .iter()
+                .map(|effect| (*effect, asset_server.load(effect.path())))
+                .collect(),
+            music: asset_server.load(MUSIC_PATH),
+            missing: HashSet::new(),
+        }
+    }
+}
+
+impl SoundLibrary {
+    //true once the sound can be played. a sound still loading is skipped rather than queued, and
+    //one whose file is missing is skipped for good
+    fn ready(&mut self, asset_server: &AssetServer, handle: &Handle<AudioSource>) -> bool {
+        match asset_server.get_load_state(handle) {
+            LoadState::Loaded => true,
+            LoadState::Failed => {
+                let path = asset_server
+                    .get_handle_path(handle)
+                    .map(|path| path.path().display().to_string())
+                    .unwrap_or_default();
+                if self.missing.insert(path.clone()) {
+                    warn!("couldn't load sound {}, it won't be played", path);
+                }
+                false
+            }
+            _ => false,
+        }
+    }
+}
+
+//the background music once it's playing
+#[derive(Default)]
+pub struct MusicPlayer {
+    sink: Option<Handle<AudioSink>>,
+}
+
+//turns each locked piece into the sounds for what it did
+pub fn queue_locked_piece_sounds(
+    mut piece_locked_reader: EventReader<PieceLockedEvent>,
+    game_stats: Res<GameStats>,
+    mut sound_writer: EventWriter<SoundEffect>,
+) {
+    for event in piece_locked_reader.iter() {
+        if event.chain == 1 {
+            sound_writer.send(SoundEffect::Lock);
+        }
+        if let Some(line_clear) = SoundEffect::line_clear(event.lines_cleared) {
+            sound_writer.send(line_clear);
+        }
+        if event.piece_type == Some(PieceType::T) && event.spin != Spin::None {
+            sound_writer.send(SoundEffect::TSpin);
+        }
+        //the first clear in a row isn't a combo yet
+        if event.lines_cleared > 0 && game_stats.combo > 1 {
+            sound_writer.send(SoundEffect::Combo);
+        }
+    }
+}
+
+//plays the sounds asked for since the last update. a sound asked for more than once, like when a
+//replay skips ahead, only plays once
+pub fn play_sound_effects(
+    mut sound_reader: EventReader<SoundEffect>,
+    mut game_play_reader: EventReader<GamePlayState>,
+    mut library: ResMut<SoundLibrary>,
+    asset_server: Res<AssetServer>,
+    audio: Res<Audio>,
+    user_settings: Res<UserSettings>,
+) {
+    let mut effects: HashSet<SoundEffect> = sound_reader.iter().copied().collect();
+    if game_play_reader
+        .iter()
+        .any(|state| *state == GamePlayState::Lose)
+    {
+        effects.insert(SoundEffect::GameOver);
+    }
+
+    let volume = user_settings.settings.sfx_volume;
+    if volume <= 0.0 {
+        return;
+    }
+    for effect in effects {
+        let handle = match library.effects.get(&effect) {
+            Some(handle) => handle.clone(),
+            None => continue,
+        };
+        if library.ready(&asset_server, &handle) {
+            audio.play_with_settings(handle, PlaybackSettings::ONCE.with_volume(volume));
+        }
+    }
+}
+
+//starts the music looping as soon as it has loaded. without an audio device it never makes a
+//sound but nothing else minds
+pub fn play_music(
+    mut music_player: ResMut<MusicPlayer>,
+    mut library: ResMut<SoundLibrary>,
+    asset_server: Res<AssetServer>,
+    audio: Res<Audio>,
+    sinks: Res<Assets<AudioSink>>,
+    user_settings: Res<UserSettings>,
+) {
+    if music_player.sink.is_some() {
+        return;
+    }
+    let music = library.music.clone();
+    if !library.ready(&asset_server, &music) {
+        return;
+    }
+
+    let volume = user_settings.settings.music_volume;
+    let sink = audio.play_with_settings(music, PlaybackSettings::LOOP.with_volume(volume));
+    music_player.sink = Some(sinks.get_handle(sink));
+}
+
+//f7 and f8 turn the sound effects down and up, f9 and f10 the music. the volumes are saved as
+//soon as they change
+pub fn handle_volume_input(
+    keyboard_input: Res<Input<KeyCode>>,
+    music_player: Res<MusicPlayer>,
+    sinks: Res<Assets<AudioSink>>,
+    mut user_settings: ResMut<UserSettings>,
+) {
+    let step = |down: KeyCode, up: KeyCode| {
+        if keyboard_input.just_pressed(down) {
+            -VOLUME_STEP
+        } else if keyboard_input.just_pressed(up) {
+            VOLUME_STEP
+        } else {
+            0.0
+        }
+    };
+    let sfx_step = step(KeyCode::F7, KeyCode::F8);
+    let music_step = step(KeyCode::F9, KeyCode::F10);
+    if sfx_step == 0.0 && music_step == 0.0 {
+        return;
+    }
+
+    let settings = &mut user_settings.settings;
+    settings.sfx_volume = (settings.sfx_volume + sfx_step).clamp(0.0, 1.0);
+    settings.music_volume = (settings.music_volume + music_step).clamp(0.0, 1.0);
+    if let Some(sink) = music_player.sink.as_ref().and_then(|sink| sinks.get(sink)) {
+        sink.set_volume(settings.music_volume);
+    }
+    user_settings.save();
+}
+
+#[derive(Component)]
+pub struct VolumeText {}
+
+pub fn setup_volume_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load(FONT_ASSET_PATH),
+                    font_size: VOLUME_FONT_SIZE,
+                    color: TEXT_COLOR,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: VOLUME_TEXT_TOP,
+                    left: VOLUME_TEXT_LEFT,
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(VolumeText {});
+}
+
+pub fn update_volume_text(
+    user_settings: Res<UserSettings>,
+    mut text_query: Query<&mut Text, With<VolumeText>>,
+) {
+    if !user_settings.is_changed() {
+        return;
+    }
+
+    let percent = |volume: f32| (volume * 100.0).round() as u32;
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = format!(
+            "SFX {}% (F7/F8)  MUSIC {}% (F9/F10)",
+            percent(user_settings.settings.sfx_volume),
+            percent(user_settings.settings.music_volume),
+        );
+    }
+}
@@ -0,0 +1,280 @@
+use bevy::prelude::*;
+use serde::Serialize;
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::board::board_core::{BoardData, PieceRotation, PieceType};
+use crate::board::move_gen::Spin;
+use crate::game_state_machine::{GamePlayState, GameStateInfo};
+use crate::{PieceLockedEvent, Score, SimulationClock};
+
+//how long the feed waits for a game event before looking for new spectators again
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+//a spectator that can't take a line in this long is dropped so it can't hold up the others
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+//what happened in the game, sent to spectators as one line of json each. the player is 0
+//outside versus, and frames count from the start of the player's game
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SpectatorEvent {
+    GameStarted {
+        player: usize,
+        frame: u32,
+    },
+    PieceSpawned {
+        player: usize,
+        frame: u32,
+        piece: PieceType,
+        rotation: PieceRotation,
+        cells: Vec<[i32; 2]>,
+    },
+    PieceMoved {
+        player: usize,
+        frame: u32,
+        piece: PieceType,
+        rotation: PieceRotation,
+        cells: Vec<[i32; 2]>,
+    },
+    PieceLocked {
+        player: usize,
+        frame: u32,
+        piece: Option<PieceType>,
+        cells: Vec<[i32; 2]>,
+        spin: Spin,
+    },
+    LinesCleared {
+        player: usize,
+        frame: u32,
+        lines: u32,
+    },
+    ScoreChanged {
+        player: usize,
+        frame: u32,
+        score: u64,
+        lines: u32,
+    },
+    GameOver {
+        player: usize,
+        frame: u32,
+        won: bool,
+    },
+}
+
+//what the feed last told spectators about a player
+#[derive(Default)]
+struct PlayerFeed {
+    piece: Option<(PieceType, PieceRotation, Vec<IVec2>)>,
+    score: (u64, u32),
+    playing: bool,
+}
+
+//--spectator-port <port> publishes the game's events to anything that connects to the port on
+//this machine. the sockets are looked after on their own thread so a slow spectator never
+//holds up a frame
+pub struct SpectatorFeed {
+    sender: Option<Mutex<Sender<String>>>,
+    players: Vec<PlayerFeed>,
+}
+
+impl FromWorld for SpectatorFeed {
+    fn from_world(_world: &mut World) -> Self {
+        let mut feed = SpectatorFeed {
+            sender: None,
+            players: vec![],
+        };
+
+        let arguments: Vec<String> = std::env::args().collect();
+        let port = match arguments
+            .iter()
+            .position(|argument| argument == "--spectator-port")
+        {
+            Some(index) => match arguments.get(index + 1) {
+                Some(port) => port,
+                None => {
+                    warn!("--spectator-port needs a port to publish on");
+                    return feed;
+                }
+            },
+            None => return feed,
+        };
+
+        let listener = match TcpListener::bind(format!("127.0.0.1:{}", port))
+            .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+        {
+            Ok(listener) => listener,
+            Err(error) => {
+                warn!(
+                    "couldn't publish spectator events on port {}: {}",
+                    port, error
+                );
+                return feed;
+            }
+        };
+
+        let (sender, receiver) = mpsc::channel::<String>();
+        thread::spawn(move || {
+            let mut spectators: Vec<TcpStream> = vec![];
+            loop {
+                let line = match receiver.recv_timeout(ACCEPT_INTERVAL) {
+                    Ok(line) => Some(line),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                while let Ok((stream, address)) = listener.accept() {
+                    let ready = stream
+                        .set_nonblocking(false)
+                        .and_then(|_| stream.set_write_timeout(Some(WRITE_TIMEOUT)));
+                    match ready {
+                        Ok(()) => {
+                            info!("spectator {} connected", address);
+                            spectators.push(stream);
+                        }
+                        Err(error) => warn!("couldn't take spectator {}: {}", address, error),
+                    }
+                }
+                if let Some(line) = line {
+                    spectators.retain_mut(|stream| writeln!(stream, "{}", line).is_ok());
+                }
+            }
+        });
+
+        info!("publishing spectator events on port {}", port);
+        feed.sender = Some(Mutex::new(sender));
+        feed
+    }
+}
+
+impl SpectatorFeed {
+    pub fn is_active(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    fn publish(&self, event: &SpectatorEvent) {
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => return,
+        };
+        match serde_json::to_string(event) {
+            Ok(line) => {
+                let _ = sender.lock().unwrap().send(line);
+            }
+            Err(error) => warn!("couldn't write spectator event: {}", error),
+        }
+    }
+}
+
+fn cell_pairs(cells: &[IVec2]) -> Vec<[i32; 2]> {
+    cells.iter().map(|cell| [cell.x, cell.y]).collect()
+}
+
+//compares the player's game with what spectators were last told and sends what changed. runs
+//in the simulation, so in versus it sees each player's game in turn
+pub fn publish_game_events(
+    clock: Res<SimulationClock>,
+    board_data: Res<BoardData>,
+    score: Res<Score>,
+    game_state: Res<GameStateInfo>,
+    mut feed: ResMut<SpectatorFeed>,
+    mut piece_locked_reader: EventReader<PieceLockedEvent>,
+) {
+    if !feed.is_active() {
+        return;
+    }
+
+    let player = clock.player_pass;
+    let frame = clock.frame;
+    if feed.players.len() <= player {
+        feed.players.resize_with(player + 1, PlayerFeed::default);
+    }
+    let mut events = vec![];
+
+    let playing = game_state.game_state == GamePlayState::Playing;
+    if playing && !feed.players[player].playing {
+        feed.players[player] = PlayerFeed {
+            playing: true,
+            ..default()
+        };
+        events.push(SpectatorEvent::GameStarted { player, frame });
+    }
+
+    let mut locked = false;
+    for event in piece_locked_reader.iter() {
+        //a cascade's clears come without a piece of their own
+        if event.chain == 1 {
+            locked = true;
+            events.push(SpectatorEvent::PieceLocked {
+                player,
+                frame,
+                piece: event.piece_type,
+                cells: cell_pairs(&event.cells),
+                spin: event.spin,
+            });
+        }
+        if event.lines_cleared > 0 {
+            events.push(SpectatorEvent::LinesCleared {
+                player,
+                frame,
+                lines: event.lines_cleared,
+            });
+        }
+    }
+
+    //a piece of another type, or any piece after a lock, is a new one. holding swaps the type
+    let mut cells = board_data.player_cells().to_vec();
+    cells.sort_by_key(|cell| (cell.y, cell.x));
+    let piece = board_data
+        .active_piece
+        .filter(|_| !cells.is_empty())
+        .map(|piece| (piece.piece_type, piece.rotation, cells));
+    let player_feed = &mut feed.players[player];
+    if let Some((piece_type, rotation, cells)) = &piece {
+        let same_piece = !locked
+            && matches!(&player_feed.piece, Some((last_type, _, _)) if last_type == piece_type);
+        if !same_piece {
+            events.push(SpectatorEvent::PieceSpawned {
+                player,
+                frame,
+                piece: *piece_type,
+                rotation: *rotation,
+                cells: cell_pairs(cells),
+            });
+        } else if player_feed.piece.as_ref() != piece.as_ref() {
+            events.push(SpectatorEvent::PieceMoved {
+                player,
+                frame,
+                piece: *piece_type,
+                rotation: *rotation,
+                cells: cell_pairs(cells),
+            });
+        }
+    }
+    player_feed.piece = piece;
+
+    let score_now = (score.score, score.lines_cleared);
+    if playing && player_feed.score != score_now {
+        player_feed.score = score_now;
+        events.push(SpectatorEvent::ScoreChanged {
+            player,
+            frame,
+            score: score.score,
+            lines: score.lines_cleared,
+        });
+    }
+
+    if !playing && player_feed.playing {
+        player_feed.playing = false;
+        let won = game_state.game_state == GamePlayState::Win;
+        if won || game_state.game_state == GamePlayState::Lose {
+            events.push(SpectatorEvent::GameOver { player, frame, won });
+        }
+    }
+
+    for event in &events {
+        feed.publish(event);
+    }
+}
@@ -0,0 +1,318 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::board::board_core::PieceType;
+use crate::board::move_gen::Spin;
+use crate::game_state_machine::{GamePlayState, GameStateInfo};
+use crate::garbage::AttackTable;
+use crate::input::GameAction;
+use crate::versus::VersusSettings;
+use crate::{
+    format_time, PieceLockedEvent, SimulationClock, FONT_ASSET_PATH, FRAMES_PER_SECOND, TEXT_COLOR,
+};
+
+const STATS_FONT_SIZE: f32 = 28.0;
+const STATS_TEXT_LEFT: Val = Val::Px(5.0);
+const STATS_TEXT_TOP: Val = Val::Px(60.0);
+
+//how many rows a piece cleared when it locked
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ClearType {
+    None,
+    Single,
+    Double,
+    Triple,
+    Tetris,
+}
+
+impl ClearType {
+    pub fn from_lines(lines_cleared: u32) -> ClearType {
+        match lines_cleared {
+            0 => ClearType::None,
+            1 => ClearType::Single,
+            2 => ClearType::Double,
+            3 => ClearType::Triple,
+            _ => ClearType::Tetris,
+        }
+    }
+
+    //clears that keep a back to back chain going
+    pub fn is_difficult(&self, spin: Spin) -> bool {
+        *self == ClearType::Tetris || (*self != ClearType::None && spin != Spin::None)
+    }
+}
+
+//everything the stats panel shows, built up from the pieces locked and keys pressed this game
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GameStats {
+    pub pieces_placed: u32,
+    pub keys_pressed: u32,
+    pub lines_cleared: u32,
+    pub attack: u32,
+    //pieces in a row that cleared something, the first one doesn't count as a combo
+    pub combo: u32,
+    pub max_combo: u32,
+    //difficult clears in a row
+    pub back_to_back: u32,
+    pub max_back_to_back: u32,
+    pub singles: u32,
+    pub doubles: u32,
+    pub triples: u32,
+    pub tetrises: u32,
+    //t-spins that cleared something, minis included
+    pub t_spins: u32,
+    pub finesse_faults: u32,
+}
+
+impl FromWorld for GameStats {
+    fn from_world(_world: &mut World) -> Self {
+        GameStats {
+            pieces_placed: 0,
+            keys_pressed: 0,
+            lines_cleared: 0,
+            attack: 0,
+            combo: 0,
+            max_combo: 0,
+            back_to_back: 0,
+            max_back_to_back: 0,
+            singles: 0,
+            doubles: 0,
+            triples: 0,
+            tetrises: 0,
+            t_spins: 0,
+            finesse_faults: 0,
+        }
+    }
+}
+
+impl GameStats {
+    fn record_clear(&mut self, clear_type: ClearType, t_spin: Spin, attack_table: &AttackTable) {
+        if clear_type == ClearType::None {
+            self.combo = 0;
+            return;
+        }
+
+        match clear_type {
+            ClearType::None => {}
+            ClearType::Single => self.singles += 1,
+            ClearType::Double => self.doubles += 1,
+            ClearType::Triple => self.triples += 1,
+            ClearType::Tetris => self.tetrises += 1,
+        }
+        if t_spin != Spin::None {
+            self.t_spins += 1;
+        }
+
+        self.combo += 1;
+        let combo_count = self.combo - 1;
+        self.max_combo = self.max_combo.max(combo_count);
+
+        if clear_type.is_difficult(t_spin) {
+            self.back_to_back += 1;
+            self.max_back_to_back = self.max_back_to_back.max(self.back_to_back - 1);
+        } else {
+            self.back_to_back = 0;
+        }
+
+        self.attack +=
+            attack_table.attack_for_clear(clear_type, t_spin, self.back_to_back > 1, combo_count);
+    }
+
+    pub fn pieces_per_second(&self, frames: u32) -> f32 {
+        if frames == 0 {
+            return 0.;
+        }
+        self.pieces_placed as f32 * FRAMES_PER_SECOND as f32 / frames as f32
+    }
+
+    pub fn keys_per_piece(&self) -> f32 {
+        if self.pieces_placed == 0 {
+            return 0.;
+        }
+        self.keys_pressed as f32 / self.pieces_placed as f32
+    }
+
+    pub fn attack_per_minute(&self, frames: u32) -> f32 {
+        if frames == 0 {
+            return 0.;
+        }
+        self.attack as f32 * (FRAMES_PER_SECOND * 60) as f32 / frames as f32
+    }
+}
+
+pub fn count_key_presses(
+    game_state: Res<GameStateInfo>,
+    game_input: Res<Input<GameAction>>,
+    mut game_stats: ResMut<GameStats>,
+) {
+    if game_state.game_state == GamePlayState::Playing {
+        game_stats.keys_pressed += game_input.get_just_pressed().count() as u32;
+    }
+}
+
+pub fn record_locked_piece(
+    mut piece_locked_reader: EventReader<PieceLockedEvent>,
+    attack_table: Res<AttackTable>,
+    mut game_stats: ResMut<GameStats>,
+) {
+    for event in piece_locked_reader.iter() {
+        //a cascade's clears add lines, combo and attack but aren't pieces of their own
+        if event.chain == 1 {
+            game_stats.pieces_placed += 1;
+        }
+        game_stats.lines_cleared += event.lines_cleared;
+        let t_spin = if event.piece_type == Some(PieceType::T) {
+            event.spin
+        } else {
+            Spin::None
+        };
+        game_stats.record_clear(
+            ClearType::from_lines(event.lines_cleared),
+            t_spin,
+            &attack_table,
+        );
+    }
+}
+
+#[derive(Component)]
+pub struct StatsText {}
+
+pub fn setup_stats_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load(FONT_ASSET_PATH),
+                    font_size: STATS_FONT_SIZE,
+                    color: TEXT_COLOR,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: STATS_TEXT_TOP,
+                    left: STATS_TEXT_LEFT,
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(StatsText {});
+}
+
+//versus shows its own text instead
+pub fn update_stats_text(
+    game_state: Res<GameStateInfo>,
+    game_stats: Res<GameStats>,
+    clock: Res<SimulationClock>,
+    versus_settings: Res<VersusSettings>,
+    mut text_query: Query<&mut Text, With<StatsText>>,
+) {
+    if versus_settings.enabled {
+        return;
+    }
+
+    let text = if game_state.game_state == GamePlayState::Menu {
+        String::new()
+    } else {
+        let frames = clock.frame;
+        format!(
+            "TIME {}\nPIECES {}\nPPS {:.2}\nKPP {:.2}\nLINES {}\nAPM {:.1}\nFINESSE {}\n\
+             COMBO {} (MAX {})\nB2B {} (MAX {})\nSINGLE {}\nDOUBLE {}\nTRIPLE {}\nTETRIS {}\n\
+             T-SPIN {}",
+            format_time(frames),
+            game_stats.pieces_placed,
+            game_stats.pieces_per_second(frames),
+            game_stats.keys_per_piece(),
+            game_stats.lines_cleared,
+            game_stats.attack_per_minute(frames),
+            game_stats.finesse_faults,
+            game_stats.combo.saturating_sub(1),
+            game_stats.max_combo,
+            game_stats.back_to_back.saturating_sub(1),
+            game_stats.max_back_to_back,
+            game_stats.singles,
+            game_stats.doubles,
+            game_stats.triples,
+            game_stats.tetrises,
+            game_stats.t_spins,
+        )
+    };
+
+    let mut stats_text = text_query.single_mut();
+    if stats_text.sections[0].value != text {
+        stats_text.sections[0].value = text;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_stats() -> GameStats {
+        GameStats::from_world(&mut World::new())
+    }
+
+    #[test]
+    fn rates_over_a_game() {
+        let mut stats = new_stats();
+        stats.pieces_placed = 30;
+        stats.keys_pressed = 75;
+        stats.attack = 12;
+        let ten_seconds = FRAMES_PER_SECOND * 10;
+        let two_minutes = FRAMES_PER_SECOND * 120;
+
+        assert_eq!(stats.pieces_per_second(ten_seconds), 3.);
+        assert_eq!(stats.keys_per_piece(), 2.5);
+        assert_eq!(stats.attack_per_minute(ten_seconds), 72.);
+        assert_eq!(stats.attack_per_minute(two_minutes), 6.);
+    }
+
+    #[test]
+    fn rates_before_anything_happens_are_zero() {
+        let mut stats = new_stats();
+        assert_eq!(stats.pieces_per_second(0), 0.);
+        assert_eq!(stats.keys_per_piece(), 0.);
+        assert_eq!(stats.attack_per_minute(0), 0.);
+        assert_eq!(stats.pieces_per_second(FRAMES_PER_SECOND), 0.);
+
+        //keys pressed before the first piece locks
+        stats.keys_pressed = 4;
+        assert_eq!(stats.keys_per_piece(), 0.);
+        stats.pieces_placed = 1;
+        stats.attack = 4;
+        assert_eq!(stats.pieces_per_second(0), 0.);
+        assert_eq!(stats.attack_per_minute(0), 0.);
+    }
+
+    #[test]
+    fn clears_build_up_combos_and_back_to_back() {
+        let mut stats = new_stats();
+        let table = AttackTable::default();
+
+        stats.record_clear(ClearType::Tetris, Spin::None, &table);
+        assert_eq!(stats.attack, 4);
+        //back to back tetris, on a combo of one
+        stats.record_clear(ClearType::Tetris, Spin::None, &table);
+        assert_eq!(stats.attack, 4 + 4 + 1);
+        //a t-spin double keeps the chain going, on a combo of two
+        stats.record_clear(ClearType::Double, Spin::Full, &table);
+        assert_eq!(stats.attack, 9 + 4 + 1 + 1);
+        assert_eq!(stats.max_back_to_back, 2);
+        assert_eq!(stats.max_combo, 2);
+        //a single breaks back to back but not the combo
+        stats.record_clear(ClearType::Single, Spin::None, &table);
+        assert_eq!(stats.attack, 15 + 1);
+        assert_eq!(stats.back_to_back, 0);
+        //a piece that clears nothing ends the combo
+        stats.record_clear(ClearType::None, Spin::None, &table);
+        assert_eq!(stats.combo, 0);
+        assert_eq!(stats.max_combo, 3);
+
+        assert_eq!(stats.tetrises, 2);
+        assert_eq!(stats.doubles, 1);
+        assert_eq!(stats.singles, 1);
+        assert_eq!(stats.t_spins, 1);
+    }
+}
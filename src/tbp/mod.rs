@@ -0,0 +1,728 @@
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::ai::Difficulty;
+use crate::board::board_core::{self, BoardData, PieceColor, PieceRotation, PieceType};
+use crate::board::move_gen::Spin;
+use crate::game_state_machine::{GamePlayState, GameStateInfo};
+use crate::garbage::GarbageQueue;
+use crate::input::InputSource;
+use crate::piece_queue::PieceQueue;
+use crate::stats::GameStats;
+use crate::versus::VersusSettings;
+use crate::{GameSettings, LockController, Randomizer, FRAMES_PER_SECOND};
+
+//tbp boards are always 40 rows of 10
+const TBP_BOARD_HEIGHT: usize = 40;
+const TBP_BOARD_WIDTH: usize = 10;
+
+//a piece's blocks around its centre when it faces north, as the protocol defines them
+fn tbp_north_cells(piece_type: PieceType) -> [IVec2; 4] {
+    let cells = match piece_type {
+        PieceType::I => [(-1, 0), (0, 0), (1, 0), (2, 0)],
+        PieceType::O => [(0, 0), (1, 0), (0, 1), (1, 1)],
+        PieceType::T => [(-1, 0), (0, 0), (1, 0), (0, 1)],
+        PieceType::L => [(-1, 0), (0, 0), (1, 0), (1, 1)],
+        PieceType::J => [(-1, 0), (0, 0), (1, 0), (-1, 1)],
+        PieceType::S => [(-1, 0), (0, 0), (0, 1), (1, 1)],
+        PieceType::Z => [(-1, 1), (0, 1), (0, 0), (1, 0)],
+    };
+    cells.map(|(x, y)| IVec2 { x, y })
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TbpOrientation {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl TbpOrientation {
+    fn rotation(&self) -> PieceRotation {
+        match self {
+            TbpOrientation::North => PieceRotation::Zero,
+            TbpOrientation::East => PieceRotation::Ninety,
+            TbpOrientation::South => PieceRotation::OneEighty,
+            TbpOrientation::West => PieceRotation::TwoSeventy,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TbpLocation {
+    #[serde(rename = "type")]
+    pub piece_type: PieceType,
+    pub orientation: TbpOrientation,
+    pub x: i32,
+    pub y: i32,
+}
+
+impl TbpLocation {
+    //the board cells the piece covers. each clockwise turn takes (x, y) to (y, -x)
+    pub fn cells(&self) -> Vec<IVec2> {
+        let turns = match self.orientation {
+            TbpOrientation::North => 0,
+            TbpOrientation::East => 1,
+            TbpOrientation::South => 2,
+            TbpOrientation::West => 3,
+        };
+        tbp_north_cells(self.piece_type)
+            .iter()
+            .map(|cell| {
+                let mut cell = *cell;
+                for _ in 0..turns {
+                    cell = IVec2 {
+                        x: cell.y,
+                        y: -cell.x,
+                    };
+                }
+                cell + IVec2 {
+                    x: self.x,
+                    y: self.y,
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TbpMove {
+    pub location: TbpLocation,
+    #[serde(default = "no_spin")]
+    pub spin: String,
+}
+
+fn no_spin() -> String {
+    "none".to_string()
+}
+
+impl TbpMove {
+    fn spin(&self) -> Spin {
+        match self.spin.as_str() {
+            "mini" => Spin::Mini,
+            "full" => Spin::Full,
+            _ => Spin::None,
+        }
+    }
+}
+
+//messages sent to the bot
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum FrontendMessage {
+    Rules {
+        randomizer: String,
+    },
+    Start {
+        hold: Option<PieceType>,
+        queue: Vec<PieceType>,
+        combo: u32,
+        back_to_back: bool,
+        //rows from the bottom up, each cell is a piece letter, G for garbage, or null
+        board: Vec<Vec<Option<char>>>,
+    },
+    Suggest,
+    Play {
+        #[serde(rename = "move")]
+        tbp_move: TbpMove,
+    },
+    NewPiece {
+        piece: PieceType,
+    },
+    Stop,
+    Quit,
+}
+
+//messages the bot sends back
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BotMessage {
+    Info {
+        name: String,
+        version: String,
+        author: String,
+    },
+    Ready,
+    Error {
+        reason: String,
+    },
+    Suggestion {
+        moves: Vec<TbpMove>,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum BotState {
+    WaitingForInfo,
+    WaitingForReady,
+    Ready,
+    Failed,
+}
+
+//an external bot launched with --bot "<command>", talking the tetris bot protocol over its
+//stdin and stdout. --difficulty slows it down and has it play worse moves now and then
+pub struct TbpBot {
+    child: Option<Child>,
+    stdin: Option<ChildStdin>,
+    messages: Option<Mutex<Receiver<BotMessage>>>,
+    state: BotState,
+    game_started: bool,
+    //pieces the randomizer had dealt when the bot was last told about the queue
+    pieces_known: u64,
+    //garbage rows on the board when the bot was last told about it
+    garbage_known: u32,
+    waiting_for_suggestion: bool,
+    pending_move: Option<TbpMove>,
+    difficulty: Option<Difficulty>,
+    //frames the current piece has been waiting for the bot
+    frames_waited: u32,
+    rng: StdRng,
+}
+
+impl FromWorld for TbpBot {
+    fn from_world(_world: &mut World) -> Self {
+        let mut bot = TbpBot {
+            child: None,
+            stdin: None,
+            messages: None,
+            state: BotState::Failed,
+            game_started: false,
+            pieces_known: 0,
+            garbage_known: 0,
+            waiting_for_suggestion: false,
+            pending_move: None,
+            difficulty: None,
+            frames_waited: 0,
+            rng: StdRng::from_entropy(),
+        };
+
+        let arguments: Vec<String> = std::env::args().collect();
+        bot.difficulty = Difficulty::from_arguments(&arguments);
+        let command = match arguments.iter().position(|argument| argument == "--bot") {
+            Some(index) => match arguments.get(index + 1) {
+                Some(command) => command.clone(),
+                None => {
+                    warn!("--bot needs a command to run");
+                    return bot;
+                }
+            },
+            None => return bot,
+        };
+
+        let mut command_parts = command.split_whitespace();
+        let program = match command_parts.next() {
+            Some(program) => program,
+            None => return bot,
+        };
+        let mut child = match Command::new(program)
+            .args(command_parts)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(error) => {
+                warn!("couldn't start bot {}: {}", command, error);
+                return bot;
+            }
+        };
+
+        //the bot's output is read on its own thread so a slow bot never holds up a frame
+        let (sender, receiver) = mpsc::channel();
+        let stdout = child.stdout.take().unwrap();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                match serde_json::from_str(&line) {
+                    Ok(message) => {
+                        if sender.send(message).is_err() {
+                            break;
+                        }
+                    }
+                    Err(error) => warn!("bot sent a message that isn't tbp: {}", error),
+                }
+            }
+        });
+
+        info!("started bot {}", command);
+        bot.stdin = child.stdin.take();
+        bot.child = Some(child);
+        bot.messages = Some(Mutex::new(receiver));
+        bot.state = BotState::WaitingForInfo;
+        bot
+    }
+}
+
+impl TbpBot {
+    pub fn is_running(&self) -> bool {
+        self.child.is_some()
+    }
+
+    fn send(&mut self, message: &FrontendMessage) {
+        let stdin = match &mut self.stdin {
+            Some(stdin) => stdin,
+            None => return,
+        };
+        let result = serde_json::to_string(message)
+            .map_err(|error| error.to_string())
+            .and_then(|line| writeln!(stdin, "{}", line).map_err(|error| error.to_string()));
+        if let Err(error) = result {
+            warn!("couldn't talk to bot: {}", error);
+            self.state = BotState::Failed;
+        }
+    }
+
+    fn received_messages(&self) -> Vec<BotMessage> {
+        match &self.messages {
+            Some(messages) => messages.lock().unwrap().try_iter().collect(),
+            None => vec![],
+        }
+    }
+
+    fn stop_game(&mut self) {
+        if self.game_started {
+            self.send(&FrontendMessage::Stop);
+        }
+        self.game_started = false;
+        self.waiting_for_suggestion = false;
+        self.pending_move = None;
+    }
+
+    //the best move, or now and then at lower difficulties one of the others
+    fn pick_move(&mut self, mut moves: Vec<TbpMove>) -> Option<TbpMove> {
+        if moves.is_empty() {
+            return None;
+        }
+        let mistake_chance = self
+            .difficulty
+            .map(|difficulty| difficulty.mistake_chance())
+            .unwrap_or(0.);
+        let index = if moves.len() > 1 && self.rng.gen_bool(mistake_chance) {
+            self.rng.gen_range(1..moves.len())
+        } else {
+            0
+        };
+        Some(moves.swap_remove(index))
+    }
+}
+
+impl Drop for TbpBot {
+    fn drop(&mut self) {
+        if self.child.is_none() {
+            return;
+        }
+        self.send(&FrontendMessage::Quit);
+        if let Some(child) = &mut self.child {
+            if let Ok(None) = child.try_wait() {
+                let _ = child.kill();
+            }
+        }
+    }
+}
+
+//in versus the bot plays the second board, against the keyboard. tbp only knows 10 wide
+//boards, so on any other width the bot sees part of the board or columns that aren't there
+pub fn setup_bot(
+    bot: Res<TbpBot>,
+    game_settings: Res<GameSettings>,
+    versus_settings: Res<VersusSettings>,
+    mut input_source: ResMut<InputSource>,
+) {
+    if bot.is_running() && game_settings.board_size.width != TBP_BOARD_WIDTH as i32 {
+        warn!(
+            "tbp bots play on boards {} wide, this one is {}",
+            TBP_BOARD_WIDTH, game_settings.board_size.width
+        );
+    }
+    if bot.is_running() && !versus_settings.enabled {
+        *input_source = InputSource::Bot;
+    }
+}
+
+//the board without the player's piece, the way tbp wants it
+fn tbp_board(board_data: &BoardData) -> Vec<Vec<Option<char>>> {
+    let mut board = vec![vec![None; TBP_BOARD_WIDTH]; TBP_BOARD_HEIGHT];
+    for (y, row) in board.iter_mut().enumerate() {
+        for (x, tbp_cell) in row.iter_mut().enumerate() {
+            let cell = IVec2 {
+                x: x as i32,
+                y: y as i32,
+            };
+            if board_data.player_cells().contains(&cell) {
+                continue;
+            }
+            let color = match board_data.cell(&cell) {
+                Some(block) => block.color,
+                None => continue,
+            };
+            *tbp_cell = Some(match color {
+                PieceColor::LightBlue => 'I',
+                PieceColor::Blue => 'J',
+                PieceColor::Orange => 'L',
+                PieceColor::Yellow => 'O',
+                PieceColor::Green => 'S',
+                PieceColor::Purple => 'T',
+                PieceColor::Red => 'Z',
+                PieceColor::Gray => 'G',
+            });
+        }
+    }
+    board
+}
+
+//talks to the bot: starts it on each game, tells it about new pieces, asks for a move whenever
+//the player's piece spawns and places the piece where it says. tbp has no message for garbage,
+//so the bot is started again on the new board whenever some arrives
+#[allow(clippy::too_many_arguments)]
+pub fn run_bot(
+    game_state: Res<GameStateInfo>,
+    game_settings: Res<GameSettings>,
+    game_stats: Res<GameStats>,
+    randomizer: Res<Randomizer>,
+    input_source: Res<InputSource>,
+    garbage_queue: Res<GarbageQueue>,
+    mut bot: ResMut<TbpBot>,
+    mut piece_queue: ResMut<PieceQueue>,
+    mut lock_controller: ResMut<LockController>,
+    mut board_data: ResMut<BoardData>,
+) {
+    if !bot.is_running() || bot.state == BotState::Failed || *input_source != InputSource::Bot {
+        return;
+    }
+
+    for message in bot.received_messages() {
+        match message {
+            BotMessage::Info {
+                name,
+                version,
+                author,
+            } => {
+                info!("bot {} {} by {}", name, version, author);
+                bot.send(&FrontendMessage::Rules {
+                    randomizer: "seven_bag".to_string(),
+                });
+                bot.state = BotState::WaitingForReady;
+            }
+            BotMessage::Ready => bot.state = BotState::Ready,
+            BotMessage::Error { reason } => {
+                warn!("bot couldn't play: {}", reason);
+                bot.state = BotState::Failed;
+            }
+            BotMessage::Suggestion { moves } => {
+                bot.waiting_for_suggestion = false;
+                bot.pending_move = bot.pick_move(moves);
+                if bot.pending_move.is_none() {
+                    warn!("bot has no moves left");
+                }
+            }
+            BotMessage::Unknown => {}
+        }
+    }
+    if bot.state != BotState::Ready {
+        return;
+    }
+
+    if game_state.game_state != GamePlayState::Playing {
+        bot.stop_game();
+        return;
+    }
+
+    let active_piece = match board_data.active_piece {
+        Some(piece) => piece,
+        None => return,
+    };
+
+    bot.frames_waited += 1;
+
+    if bot.game_started && bot.garbage_known != garbage_queue.lines_received {
+        bot.stop_game();
+    }
+    if !bot.game_started {
+        let mut queue = vec![active_piece.piece_type];
+        queue.extend(piece_queue.next_pieces.iter());
+        bot.send(&FrontendMessage::Start {
+            hold: piece_queue.held_piece,
+            queue,
+            combo: game_stats.combo,
+            back_to_back: game_stats.back_to_back > 0,
+            board: tbp_board(&board_data),
+        });
+        bot.game_started = true;
+        bot.pieces_known = randomizer.pieces_dealt;
+        bot.garbage_known = garbage_queue.lines_received;
+    }
+
+    //every piece dealt since the bot last heard is at the back of the queue
+    let new_pieces = (randomizer.pieces_dealt - bot.pieces_known) as usize;
+    let queue_length = piece_queue.next_pieces.len();
+    for index in queue_length.saturating_sub(new_pieces)..queue_length {
+        let piece = piece_queue.next_pieces[index];
+        bot.send(&FrontendMessage::NewPiece { piece });
+    }
+    bot.pieces_known = randomizer.pieces_dealt;
+
+    let tbp_move = match bot.pending_move.take() {
+        Some(tbp_move) => tbp_move,
+        None => {
+            if !bot.waiting_for_suggestion {
+                bot.send(&FrontendMessage::Suggest);
+                bot.waiting_for_suggestion = true;
+            }
+            return;
+        }
+    };
+
+    //a move for a different piece means the bot wants to hold first. the piece from hold spawns
+    //next frame, so the move waits until then
+    if tbp_move.location.piece_type != active_piece.piece_type {
+        let piece_after_hold = piece_queue
+            .held_piece
+            .or_else(|| piece_queue.next_pieces.front().copied());
+        if !piece_queue.hold_used && piece_after_hold == Some(tbp_move.location.piece_type) {
+            piece_queue.hold_requested = true;
+            bot.pending_move = Some(tbp_move);
+        } else {
+            warn!(
+                "bot asked for a {:?} it can't get",
+                tbp_move.location.piece_type
+            );
+        }
+        return;
+    }
+
+    //held back until the piece has waited as long as the difficulty's pps allows
+    if let Some(difficulty) = bot.difficulty {
+        let frames_per_piece = (FRAMES_PER_SECOND as f32 / difficulty.pieces_per_second()) as u32;
+        if bot.frames_waited < frames_per_piece {
+            bot.pending_move = Some(tbp_move);
+            return;
+        }
+    }
+
+    let rotation = tbp_move.location.orientation.rotation();
+    if board_core::place_player_piece(&mut board_data, rotation, &tbp_move.location.cells()) {
+        lock_controller.frames_grounded = game_settings.lock_delay;
+        lock_controller.spin = tbp_move.spin();
+        bot.frames_waited = 0;
+        bot.send(&FrontendMessage::Play { tbp_move });
+    } else {
+        warn!(
+            "bot's move doesn't fit on the board: {:?}",
+            tbp_move.location
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const ALL_PIECE_TYPES: [PieceType; 7] = [
+        PieceType::I,
+        PieceType::J,
+        PieceType::L,
+        PieceType::O,
+        PieceType::S,
+        PieceType::T,
+        PieceType::Z,
+    ];
+    const ALL_ORIENTATIONS: [TbpOrientation; 4] = [
+        TbpOrientation::North,
+        TbpOrientation::East,
+        TbpOrientation::South,
+        TbpOrientation::West,
+    ];
+
+    fn location(piece_type: PieceType, orientation: TbpOrientation, x: i32, y: i32) -> TbpLocation {
+        TbpLocation {
+            piece_type,
+            orientation,
+            x,
+            y,
+        }
+    }
+
+    //cells moved so the lowest, leftmost of them is at 0, 0, to compare shapes
+    fn shape(cells: &[IVec2]) -> Vec<IVec2> {
+        let corner = IVec2 {
+            x: cells.iter().map(|cell| cell.x).min().unwrap(),
+            y: cells.iter().map(|cell| cell.y).min().unwrap(),
+        };
+        let mut shape: Vec<IVec2> = cells.iter().map(|cell| *cell - corner).collect();
+        shape.sort_by_key(|cell| (cell.y, cell.x));
+        shape
+    }
+
+    #[test]
+    fn locations_cover_the_same_shape_as_the_game_piece() {
+        for piece_type in ALL_PIECE_TYPES {
+            for orientation in ALL_ORIENTATIONS {
+                let cells = location(piece_type, orientation, 4, 20).cells();
+                assert_eq!(
+                    shape(&cells),
+                    shape(&piece_type.cells(orientation.rotation(), IVec2::ZERO)),
+                    "{:?} {:?}",
+                    piece_type,
+                    orientation
+                );
+                //the centre is always one of the piece's cells
+                assert!(
+                    cells.contains(&IVec2 { x: 4, y: 20 }),
+                    "{:?} {:?}",
+                    piece_type,
+                    orientation
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn locations_turn_about_their_centre() {
+        let cells = |piece_type, orientation| {
+            let mut cells = location(piece_type, orientation, 4, 1).cells();
+            cells.sort_by_key(|cell| (cell.y, cell.x));
+            cells
+                .iter()
+                .map(|cell| (cell.x, cell.y))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            cells(PieceType::T, TbpOrientation::North),
+            vec![(3, 1), (4, 1), (5, 1), (4, 2)]
+        );
+        assert_eq!(
+            cells(PieceType::T, TbpOrientation::East),
+            vec![(4, 0), (4, 1), (5, 1), (4, 2)]
+        );
+        assert_eq!(
+            cells(PieceType::T, TbpOrientation::South),
+            vec![(4, 0), (3, 1), (4, 1), (5, 1)]
+        );
+        assert_eq!(
+            cells(PieceType::T, TbpOrientation::West),
+            vec![(4, 0), (3, 1), (4, 1), (4, 2)]
+        );
+        assert_eq!(
+            cells(PieceType::I, TbpOrientation::East),
+            vec![(4, -1), (4, 0), (4, 1), (4, 2)]
+        );
+        assert_eq!(
+            cells(PieceType::O, TbpOrientation::North),
+            vec![(4, 1), (5, 1), (4, 2), (5, 2)]
+        );
+    }
+
+    #[test]
+    fn frontend_messages_serialize_the_way_tbp_expects() {
+        let to_json = |message: &FrontendMessage| serde_json::to_value(message).unwrap();
+
+        assert_eq!(
+            to_json(&FrontendMessage::Rules {
+                randomizer: "seven_bag".to_string()
+            }),
+            json!({"type": "rules", "randomizer": "seven_bag"})
+        );
+        let mut board = vec![vec![None; 2]; 2];
+        board[0][0] = Some('G');
+        board[0][1] = Some('T');
+        assert_eq!(
+            to_json(&FrontendMessage::Start {
+                hold: None,
+                queue: vec![PieceType::S, PieceType::Z],
+                combo: 2,
+                back_to_back: true,
+                board,
+            }),
+            json!({
+                "type": "start",
+                "hold": null,
+                "queue": ["S", "Z"],
+                "combo": 2,
+                "back_to_back": true,
+                "board": [["G", "T"], [null, null]],
+            })
+        );
+        assert_eq!(
+            to_json(&FrontendMessage::Play {
+                tbp_move: TbpMove {
+                    location: location(PieceType::L, TbpOrientation::West, 1, 2),
+                    spin: "mini".to_string(),
+                }
+            }),
+            json!({
+                "type": "play",
+                "move": {
+                    "location": {"type": "L", "orientation": "west", "x": 1, "y": 2},
+                    "spin": "mini",
+                },
+            })
+        );
+        assert_eq!(
+            to_json(&FrontendMessage::NewPiece {
+                piece: PieceType::J
+            }),
+            json!({"type": "new_piece", "piece": "J"})
+        );
+        assert_eq!(
+            to_json(&FrontendMessage::Suggest),
+            json!({"type": "suggest"})
+        );
+        assert_eq!(to_json(&FrontendMessage::Stop), json!({"type": "stop"}));
+        assert_eq!(to_json(&FrontendMessage::Quit), json!({"type": "quit"}));
+    }
+
+    #[test]
+    fn bot_messages_parse() {
+        let parse = |line: &str| serde_json::from_str::<BotMessage>(line).unwrap();
+
+        //fields tbp has that the game doesn't use are skipped
+        assert!(matches!(
+            parse(r#"{"type":"info","name":"bot","version":"1.0","author":"someone","features":[]}"#),
+            BotMessage::Info { name, version, author }
+                if name == "bot" && version == "1.0" && author == "someone"
+        ));
+        assert!(matches!(parse(r#"{"type":"ready"}"#), BotMessage::Ready));
+        assert!(matches!(
+            parse(r#"{"type":"error","reason":"unsupported_rules"}"#),
+            BotMessage::Error { reason } if reason == "unsupported_rules"
+        ));
+        assert!(matches!(
+            parse(r#"{"type":"some_future_message","data":1}"#),
+            BotMessage::Unknown
+        ));
+
+        let moves = match parse(
+            r#"{"type":"suggestion","moves":[
+                {"location":{"type":"T","orientation":"south","x":4,"y":0},"spin":"full"},
+                {"location":{"type":"I","orientation":"east","x":0,"y":1}}
+            ]}"#,
+        ) {
+            BotMessage::Suggestion { moves } => moves,
+            _ => panic!("not a suggestion"),
+        };
+        assert_eq!(moves.len(), 2);
+        assert_eq!(moves[0].location.piece_type, PieceType::T);
+        assert_eq!(moves[0].location.orientation, TbpOrientation::South);
+        assert_eq!((moves[0].location.x, moves[0].location.y), (4, 0));
+        assert_eq!(moves[0].spin(), Spin::Full);
+        //a move without a spin is taken as no spin
+        assert_eq!(moves[1].spin(), Spin::None);
+
+        assert!(serde_json::from_str::<BotMessage>(r#"{"moves":[]}"#).is_err());
+    }
+}
@@ -0,0 +1,392 @@
+use bevy::asset::FileAssetIo;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::board::board_core::{BoardWallPosition, PieceColor};
+use crate::game_state_machine::{GamePlayState, GameStateInfo};
+use crate::settings::{Settings, UserSettings};
+use crate::FONT_ASSET_PATH;
+
+//the theme made of the game's own assets, used for anything another theme leaves out
+pub const DEFAULT_THEME: &str = "default";
+//where bevy loads assets from, and where the themes are inside it
+const ASSETS_DIRECTORY: &str = "assets";
+const THEMES_DIRECTORY: &str = "themes";
+const MANIFEST_FILE_NAME: &str = "theme.json";
+
+const DEFAULT_WALL_COLOR: Color = Color::rgb(1., 1., 1.);
+const DEFAULT_BACKGROUND_COLOR: Color = Color::rgb(0., 0., 0.);
+
+const GLYPH_FONT_SIZE: f32 = 20.0;
+const GLYPH_COLOR: Color = Color::rgba(0., 0., 0., 0.75);
+
+const ALL_PIECE_COLORS: [PieceColor; 8] = [
+    PieceColor::LightBlue,
+    PieceColor::Blue,
+    PieceColor::Orange,
+    PieceColor::Yellow,
+    PieceColor::Green,
+    PieceColor::Purple,
+    PieceColor::Red,
+    PieceColor::Gray,
+];
+
+#[derive(Debug)]
+pub enum ThemeError {
+    Io(io::Error),
+    Format(serde_json::Error),
+}
+
+impl fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThemeError::Io(error) => write!(f, "{}", error),
+            ThemeError::Format(error) => write!(f, "theme manifest is invalid: {}", error),
+        }
+    }
+}
+
+impl From<io::Error> for ThemeError {
+    fn from(error: io::Error) -> Self {
+        ThemeError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for ThemeError {
+    fn from(error: serde_json::Error) -> Self {
+        ThemeError::Format(error)
+    }
+}
+
+//the theme.json in a theme's directory. block textures are paths inside the theme's directory
+//and colours are red, green and blue from 0 to 1. anything left out comes from the default theme
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct ThemeManifest {
+    name: Option<String>,
+    blocks: HashMap<PieceColor, String>,
+    //multiplied with a block's texture, so one white texture can be coloured for every piece
+    tints: HashMap<PieceColor, [f32; 3]>,
+    wall_color: Option<[f32; 3]>,
+    background_color: Option<[f32; 3]>,
+}
+
+impl ThemeManifest {
+    fn load(directory: &str) -> Result<ThemeManifest, ThemeError> {
+        let path = themes_path().join(directory).join(MANIFEST_FILE_NAME);
+        let text = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&text)?)
+    }
+}
+
+fn themes_path() -> PathBuf {
+    FileAssetIo::get_base_path()
+        .join(ASSETS_DIRECTORY)
+        .join(THEMES_DIRECTORY)
+}
+
+fn color_from_rgb([red, green, blue]: [f32; 3]) -> Color {
+    Color::rgb(red, green, blue)
+}
+
+//block colours picked so pieces can be told apart with each kind of colour blindness. any
+//palette but the theme's own tints the white block for every piece
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Palette {
+    Theme,
+    Deuteranopia,
+    Protanopia,
+    Tritanopia,
+    HighContrast,
+}
+
+impl Palette {
+    pub fn name(&self) -> &str {
+        match self {
+            Palette::Theme => "theme",
+            Palette::Deuteranopia => "deuteranopia",
+            Palette::Protanopia => "protanopia",
+            Palette::Tritanopia => "tritanopia",
+            Palette::HighContrast => "high contrast",
+        }
+    }
+
+    pub fn next(&self) -> Palette {
+        match self {
+            Palette::Theme => Palette::Deuteranopia,
+            Palette::Deuteranopia => Palette::Protanopia,
+            Palette::Protanopia => Palette::Tritanopia,
+            Palette::Tritanopia => Palette::HighContrast,
+            Palette::HighContrast => Palette::Theme,
+        }
+    }
+
+    //red, green and blue for each block colour, in the order of ALL_PIECE_COLORS
+    fn colors(&self) -> Option<[[f32; 3]; 8]> {
+        match self {
+            Palette::Theme => None,
+            //the okabe and ito colours, which stay apart without green
+            Palette::Deuteranopia => Some([
+                [0.34, 0.71, 0.91],
+                [0.0, 0.45, 0.70],
+                [0.90, 0.62, 0.0],
+                [0.94, 0.89, 0.26],
+                [0.0, 0.62, 0.45],
+                [0.80, 0.47, 0.65],
+                [0.84, 0.37, 0.0],
+                [0.6, 0.6, 0.6],
+            ]),
+            //reds look dark without red cones, so they're kept apart by brightness as well
+            Palette::Protanopia => Some([
+                [0.34, 0.71, 0.91],
+                [0.12, 0.25, 0.65],
+                [1.0, 0.65, 0.1],
+                [0.98, 0.93, 0.55],
+                [0.0, 0.55, 0.55],
+                [0.7, 0.55, 0.9],
+                [0.45, 0.3, 0.1],
+                [0.6, 0.6, 0.6],
+            ]),
+            //blue and yellow are the ones that get confused, so it leans on reds and cyans
+            Palette::Tritanopia => Some([
+                [0.3, 0.85, 0.85],
+                [0.0, 0.4, 0.45],
+                [0.95, 0.35, 0.25],
+                [1.0, 0.72, 0.78],
+                [0.55, 0.75, 0.55],
+                [0.75, 0.2, 0.55],
+                [0.55, 0.05, 0.1],
+                [0.6, 0.6, 0.6],
+            ]),
+            Palette::HighContrast => Some([
+                [0.0, 1.0, 1.0],
+                [0.1, 0.2, 1.0],
+                [1.0, 0.55, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 0.9, 0.0],
+                [0.8, 0.0, 1.0],
+                [1.0, 0.0, 0.0],
+                [0.5, 0.5, 0.5],
+            ]),
+        }
+    }
+}
+
+//the letter of the piece a block came from. garbage has none
+pub fn block_glyph(color: PieceColor) -> &'static str {
+    match color {
+        PieceColor::LightBlue => "I",
+        PieceColor::Blue => "J",
+        PieceColor::Orange => "L",
+        PieceColor::Yellow => "O",
+        PieceColor::Green => "S",
+        PieceColor::Purple => "T",
+        PieceColor::Red => "Z",
+        PieceColor::Gray => "",
+    }
+}
+
+//how the board looks: the block textures and colours, and the walls and background around them
+pub struct Theme {
+    //the theme's directory in assets/themes, or DEFAULT_THEME
+    pub directory: String,
+    pub name: String,
+    block_textures: HashMap<PieceColor, Handle<Image>>,
+    block_tints: HashMap<PieceColor, Color>,
+    pub wall_color: Color,
+    pub background_color: Color,
+    pub palette: Palette,
+    //whether blocks show the letter of their piece
+    pub block_glyphs: bool,
+    glyph_font: Handle<Font>,
+}
+
+impl FromWorld for Theme {
+    fn from_world(world: &mut World) -> Self {
+        let settings = world.resource::<UserSettings>().settings.clone();
+        Theme::load(world.resource::<AssetServer>(), &settings)
+    }
+}
+
+impl Theme {
+    fn default_theme(asset_server: &AssetServer) -> Theme {
+        Theme {
+            directory: DEFAULT_THEME.to_string(),
+            name: DEFAULT_THEME.to_string(),
+            block_textures: ALL_PIECE_COLORS
+                .iter()
+                .map(|color| (*color, asset_server.load(color.return_texture_path())))
+                .collect(),
+            block_tints: HashMap::new(),
+            wall_color: DEFAULT_WALL_COLOR,
+            background_color: DEFAULT_BACKGROUND_COLOR,
+            palette: Palette::Theme,
+            block_glyphs: false,
+            glyph_font: asset_server.load(FONT_ASSET_PATH),
+        }
+    }
+
+    //the theme picked in the settings, with the palette and glyphs on top of it
+    pub fn load(asset_server: &AssetServer, settings: &Settings) -> Theme {
+        let mut theme = Theme::load_directory(asset_server, &settings.theme);
+        theme.palette = settings.palette;
+        theme.block_glyphs = settings.block_glyphs;
+        if let Some(colors) = settings.palette.colors() {
+            let white_block: Handle<Image> =
+                asset_server.load(PieceColor::Gray.return_texture_path());
+            for (color, rgb) in ALL_PIECE_COLORS.iter().zip(colors) {
+                theme.block_textures.insert(*color, white_block.clone());
+                theme.block_tints.insert(*color, color_from_rgb(rgb));
+            }
+            info!("using the {} palette", settings.palette.name());
+        }
+        theme
+    }
+
+    //the theme in assets/themes/<directory>. a theme that can't be read is swapped for the
+    //default, and a block texture that isn't there keeps the default's so no block goes blank
+    fn load_directory(asset_server: &AssetServer, directory: &str) -> Theme {
+        let mut theme = Theme::default_theme(asset_server);
+        if directory == DEFAULT_THEME {
+            return theme;
+        }
+        let manifest = match ThemeManifest::load(directory) {
+            Ok(manifest) => manifest,
+            Err(error) => {
+                warn!("couldn't load theme {}: {}", directory, error);
+                return theme;
+            }
+        };
+
+        theme.directory = directory.to_string();
+        theme.name = manifest.name.unwrap_or_else(|| directory.to_string());
+        for (color, file) in &manifest.blocks {
+            if !themes_path().join(directory).join(file).is_file() {
+                warn!(
+                    "theme {} is missing {}, using the default block",
+                    directory, file
+                );
+                continue;
+            }
+            let asset_path = format!("{}/{}/{}", THEMES_DIRECTORY, directory, file);
+            theme
+                .block_textures
+                .insert(*color, asset_server.load(&asset_path));
+        }
+        theme.block_tints = manifest
+            .tints
+            .iter()
+            .map(|(color, rgb)| (*color, color_from_rgb(*rgb)))
+            .collect();
+        if let Some(wall_color) = manifest.wall_color {
+            theme.wall_color = color_from_rgb(wall_color);
+        }
+        if let Some(background_color) = manifest.background_color {
+            theme.background_color = color_from_rgb(background_color);
+        }
+        info!("using the {} theme", theme.name);
+        theme
+    }
+
+    pub fn block_texture(&self, color: PieceColor) -> Handle<Image> {
+        self.block_textures.get(&color).cloned().unwrap_or_default()
+    }
+
+    pub fn block_tint(&self, color: PieceColor) -> Color {
+        self.block_tints
+            .get(&color)
+            .copied()
+            .unwrap_or(Color::WHITE)
+    }
+
+    //the text drawn over a block, empty when glyphs are off
+    pub fn glyph_text(&self, color: Option<PieceColor>) -> Text {
+        let glyph = match color {
+            Some(color) if self.block_glyphs => block_glyph(color),
+            _ => "",
+        };
+        Text::from_section(
+            glyph,
+            TextStyle {
+                font: self.glyph_font.clone(),
+                font_size: GLYPH_FONT_SIZE,
+                color: GLYPH_COLOR,
+            },
+        )
+        .with_alignment(TextAlignment::CENTER)
+    }
+}
+
+//the default theme and then every directory in assets/themes with a manifest, by name
+pub fn available_themes() -> Vec<String> {
+    let mut themes: Vec<String> = match fs::read_dir(themes_path()) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().join(MANIFEST_FILE_NAME).is_file())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect(),
+        Err(_) => vec![],
+    };
+    themes.sort();
+    themes.insert(0, DEFAULT_THEME.to_string());
+    themes
+}
+
+//in the menu t moves on to the next theme, b to the next palette and g turns the block glyphs on
+//and off. they're kept for next time
+pub fn handle_theme_input(
+    keyboard_input: Res<Input<KeyCode>>,
+    game_state: Res<GameStateInfo>,
+    asset_server: Res<AssetServer>,
+    mut user_settings: ResMut<UserSettings>,
+    mut theme: ResMut<Theme>,
+) {
+    if game_state.game_state != GamePlayState::Menu {
+        return;
+    }
+
+    let mut settings = user_settings.settings.clone();
+    if keyboard_input.just_pressed(KeyCode::T) {
+        let themes = available_themes();
+        settings.theme = match themes.iter().position(|name| *name == theme.directory) {
+            Some(index) => themes[(index + 1) % themes.len()].clone(),
+            None => themes[0].clone(),
+        };
+    }
+    if keyboard_input.just_pressed(KeyCode::B) {
+        settings.palette = settings.palette.next();
+    }
+    if keyboard_input.just_pressed(KeyCode::G) {
+        settings.block_glyphs = !settings.block_glyphs;
+    }
+    if settings == user_settings.settings {
+        return;
+    }
+
+    *theme = Theme::load(&asset_server, &settings);
+    settings.theme = theme.directory.clone();
+    user_settings.settings = settings;
+    user_settings.save();
+}
+
+//recolours the walls and background when the theme changes. the blocks and previews redraw
+//themselves
+pub fn apply_theme(
+    theme: Res<Theme>,
+    mut clear_color: ResMut<ClearColor>,
+    mut wall_query: Query<&mut Sprite, With<BoardWallPosition>>,
+) {
+    if !theme.is_changed() {
+        return;
+    }
+
+    clear_color.0 = theme.background_color;
+    for mut sprite in wall_query.iter_mut() {
+        sprite.color = theme.wall_color;
+    }
+}
@@ -0,0 +1,526 @@
+use bevy::ecs::system::Resource;
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::ai::{AiPlayer, AiSettings};
+use crate::board::board_core::{
+    self, BoardCellSprite, BoardData, BoardRoot, BoardSize, PreviewBlock,
+};
+use crate::finesse::FinesseTracker;
+use crate::game_state_machine::{GamePlayState, GameStateInfo};
+use crate::garbage::{self, GarbageMeter, GarbageQueue};
+use crate::input::{GameAction, InputSource, KeyBindings};
+use crate::netplay::Netplay;
+use crate::piece_queue::{self, PieceQueue};
+use crate::stats::GameStats;
+use crate::tbp::TbpBot;
+use crate::theme::Theme;
+use crate::{
+    GameSettings, GameTickInfo, InputController, LockController, Randomizer, Score,
+    SimulationClock, SpawnController, FONT_ASSET_PATH, TEXT_COLOR,
+};
+
+const PLAYERS: usize = 2;
+//how far right of the first board the second one is drawn for boards of the usual size. the
+//camera sits between them
+const PLAYER_TWO_OFFSET: f32 = 960.;
+
+const VERSUS_FONT_SIZE: f32 = 30.0;
+//under each board, with the window at its starting size
+const VERSUS_TEXT_TOP: f32 = 890.0;
+const VERSUS_TEXT_LEFT: f32 = 320.0;
+
+//two players on one keyboard, started with --versus. both get the same pieces. netplay is
+//versus too, with the seed coming from the host
+pub struct VersusSettings {
+    pub enabled: bool,
+    pub match_seed: u64,
+    //both players' games are simulated in two places that have to stay the same, so nothing
+    //can be picked at random
+    pub lockstep: bool,
+}
+
+impl FromWorld for VersusSettings {
+    fn from_world(world: &mut World) -> Self {
+        let netplay_seed = world
+            .resource::<Netplay>()
+            .match_config()
+            .map(|config| config.match_seed);
+        VersusSettings {
+            enabled: netplay_seed.is_some()
+                || std::env::args().any(|argument| argument == "--versus"),
+            match_seed: netplay_seed.unwrap_or_else(|| rand::thread_rng().gen()),
+            lockstep: netplay_seed.is_some(),
+        }
+    }
+}
+
+//one side of the match
+#[derive(Component)]
+pub struct PlayerBoard {
+    index: usize,
+    offset: Vec3,
+}
+
+//a player's copy of every resource the game keeps for one player. the first player's copies
+//live in the world between frames, so every other system sees them. each frame the game runs
+//once per player, with that player's copies swapped into the world
+#[derive(Component, Default)]
+pub struct PlayerContext {
+    board_data: Option<BoardData>,
+    score: Option<Score>,
+    input_controller: Option<InputController>,
+    lock_controller: Option<LockController>,
+    game_tick_info: Option<GameTickInfo>,
+    spawn_controller: Option<SpawnController>,
+    piece_queue: Option<PieceQueue>,
+    randomizer: Option<Randomizer>,
+    game_stats: Option<GameStats>,
+    finesse_tracker: Option<FinesseTracker>,
+    ai_player: Option<AiPlayer>,
+    game_input: Option<Input<GameAction>>,
+    key_bindings: Option<KeyBindings>,
+    input_source: Option<InputSource>,
+    game_state_info: Option<GameStateInfo>,
+    garbage_queue: Option<GarbageQueue>,
+}
+
+impl PlayerContext {
+    //a fresh game for the second player
+    fn new(world: &mut World, match_seed: u64, player: usize) -> PlayerContext {
+        let board_data = BoardData::from_world(world);
+        PlayerContext {
+            board_data: Some(board_data),
+            score: Some(Score::from_world(world)),
+            input_controller: Some(InputController::from_world(world)),
+            lock_controller: Some(LockController::from_world(world)),
+            game_tick_info: Some(GameTickInfo::from_world(world)),
+            spawn_controller: Some(SpawnController::from_world(world)),
+            piece_queue: Some(PieceQueue::from_world(world)),
+            randomizer: Some(Randomizer::new(match_seed)),
+            game_stats: Some(GameStats::from_world(world)),
+            finesse_tracker: Some(FinesseTracker::from_world(world)),
+            ai_player: Some(AiPlayer::from_world(world)),
+            game_input: Some(Input::default()),
+            key_bindings: Some(KeyBindings::arrows()),
+            input_source: Some(opponent_input_source(world)),
+            game_state_info: Some(GameStateInfo::from_world(world)),
+            garbage_queue: Some(GarbageQueue::new(player_seed(match_seed, player))),
+        }
+    }
+
+    //puts the stored copies into the world and the world's into storage
+    fn swap_with_world(&mut self, world: &mut World) {
+        swap(world, &mut self.board_data);
+        swap(world, &mut self.score);
+        swap(world, &mut self.input_controller);
+        swap(world, &mut self.lock_controller);
+        swap(world, &mut self.game_tick_info);
+        swap(world, &mut self.spawn_controller);
+        swap(world, &mut self.piece_queue);
+        swap(world, &mut self.randomizer);
+        swap(world, &mut self.game_stats);
+        swap(world, &mut self.finesse_tracker);
+        swap(world, &mut self.ai_player);
+        swap(world, &mut self.game_input);
+        swap(world, &mut self.key_bindings);
+        swap(world, &mut self.input_source);
+        swap(world, &mut self.game_state_info);
+        swap(world, &mut self.garbage_queue);
+    }
+}
+
+//each player's garbage holes come from their own seed
+fn player_seed(match_seed: u64, player: usize) -> u64 {
+    match_seed.wrapping_add(player as u64)
+}
+
+fn swap<T: Resource>(world: &mut World, stored: &mut Option<T>) {
+    let resident = world.remove_resource::<T>();
+    if let Some(value) = stored.take() {
+        world.insert_resource(value);
+    }
+    *stored = resident;
+}
+
+//the second board is played over the network in netplay, by the ai or a bot when one was asked
+//for, and from the keyboard otherwise
+fn opponent_input_source(world: &World) -> InputSource {
+    if world.resource::<VersusSettings>().lockstep {
+        InputSource::Network
+    } else if world.resource::<AiSettings>().enabled {
+        InputSource::Ai
+    } else if world.resource::<TbpBot>().is_running() {
+        InputSource::Bot
+    } else {
+        InputSource::Keyboard
+    }
+}
+
+//where a player's board is drawn from the first player's. wider boards than usual push the
+//second board over by however much wider they are
+fn player_offset(size: BoardSize, player: usize) -> Vec3 {
+    let extra_width = size.pixels().x - BoardSize::default().pixels().x;
+    Vec3::X * (PLAYER_TWO_OFFSET + extra_width) * player as f32
+}
+
+//shows how a player's side of the match is going
+#[derive(Component)]
+pub struct VersusText {
+    player: usize,
+    name: String,
+}
+
+//walls for the second board, with the camera moved so both boards fit
+#[allow(clippy::too_many_arguments)]
+pub fn setup_versus_boards(
+    versus_settings: Res<VersusSettings>,
+    ai_settings: Res<AiSettings>,
+    bot: Res<TbpBot>,
+    asset_server: Res<AssetServer>,
+    theme: Res<Theme>,
+    game_settings: Res<GameSettings>,
+    mut commands: Commands,
+    mut camera_query: Query<&mut Transform, With<Camera2d>>,
+) {
+    if !versus_settings.enabled {
+        return;
+    }
+
+    let size = game_settings.board_size;
+    let offset = player_offset(size, 1);
+    board_core::spawn_board(&mut commands, &theme, size, 1, offset);
+    garbage::spawn_garbage_meter(&mut commands, size, 1, offset);
+    for mut camera_transform in camera_query.iter_mut() {
+        camera_transform.translation.x = offset.x / 2.;
+    }
+
+    for player in 0..PLAYERS {
+        let name = if player == 0 {
+            "PLAYER 1".to_string()
+        } else if ai_settings.enabled {
+            match ai_settings.difficulty {
+                Some(difficulty) => format!("AI {:?}", difficulty).to_uppercase(),
+                None => "AI".to_string(),
+            }
+        } else if bot.is_running() {
+            "BOT".to_string()
+        } else {
+            format!("PLAYER {}", player + 1)
+        };
+        commands
+            .spawn_bundle(
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load(FONT_ASSET_PATH),
+                        font_size: VERSUS_FONT_SIZE,
+                        color: TEXT_COLOR,
+                    },
+                )
+                .with_style(Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        top: Val::Px(VERSUS_TEXT_TOP),
+                        left: Val::Px(VERSUS_TEXT_LEFT + player_offset(size, player).x),
+                        ..default()
+                    },
+                    ..default()
+                }),
+            )
+            .insert(VersusText { player, name });
+    }
+}
+
+//gives each player a board and makes the simulation run once for each of them every frame
+pub fn setup_players(world: &mut World) {
+    if !world.resource::<VersusSettings>().enabled {
+        return;
+    }
+
+    let match_seed = world.resource::<VersusSettings>().match_seed;
+    let size = world.resource::<GameSettings>().board_size;
+    world
+        .spawn()
+        .insert(PlayerBoard {
+            index: 0,
+            offset: Vec3::ZERO,
+        })
+        .insert(PlayerContext::default());
+    for player in 1..PLAYERS {
+        let context = PlayerContext::new(world, match_seed, player);
+        world
+            .spawn()
+            .insert(PlayerBoard {
+                index: player,
+                offset: player_offset(size, player),
+            })
+            .insert(context);
+    }
+    world.resource_mut::<SimulationClock>().players = PLAYERS;
+}
+
+//clears the match away for a new one, once the first player's game has been reset
+pub fn reset_players(world: &mut World) {
+    if !world.resource::<VersusSettings>().enabled {
+        return;
+    }
+
+    let versus_settings = world.resource::<VersusSettings>();
+    let match_seed = if versus_settings.lockstep {
+        StdRng::seed_from_u64(versus_settings.match_seed).gen()
+    } else {
+        rand::thread_rng().gen()
+    };
+    world.resource_mut::<VersusSettings>().match_seed = match_seed;
+    world.insert_resource(GarbageQueue::new(player_seed(match_seed, 0)));
+    move_player_boards(world);
+    for player in 1..PLAYERS {
+        let context = PlayerContext::new(world, match_seed, player);
+        if let Some(entity) = player_entity(world, player) {
+            world.entity_mut(entity).insert(context);
+        }
+    }
+}
+
+//a game on boards of another width than the last one moves the boards and the camera to make
+//room. the boards themselves are respawned at their new size where they're moved to
+fn move_player_boards(world: &mut World) {
+    let size = world.resource::<GameSettings>().board_size;
+    for mut board in world.query::<&mut PlayerBoard>().iter_mut(world) {
+        board.offset = player_offset(size, board.index);
+    }
+    for (root, mut transform) in world
+        .query::<(&BoardRoot, &mut Transform)>()
+        .iter_mut(world)
+    {
+        transform.translation = player_offset(size, root.player);
+    }
+    for mut meter in world.query::<&mut GarbageMeter>().iter_mut(world) {
+        meter.offset = player_offset(size, meter.player);
+    }
+    for mut transform in world
+        .query_filtered::<&mut Transform, With<Camera2d>>()
+        .iter_mut(world)
+    {
+        transform.translation.x = player_offset(size, 1).x / 2.;
+    }
+}
+
+fn player_entity(world: &mut World, player: usize) -> Option<Entity> {
+    world
+        .query::<(Entity, &PlayerBoard)>()
+        .iter(world)
+        .find(|(_, board)| board.index == player)
+        .map(|(entity, _)| entity)
+}
+
+fn swap_player(world: &mut World, player: usize) {
+    let entity = match player_entity(world, player) {
+        Some(entity) => entity,
+        None => return,
+    };
+    if let Some(mut context) = world.entity_mut(entity).remove::<PlayerContext>() {
+        context.swap_with_world(world);
+        world.entity_mut(entity).insert(context);
+    }
+}
+
+fn with_stored_context(world: &mut World, player: usize, f: impl FnOnce(&mut PlayerContext)) {
+    let mut query = world.query::<(&PlayerBoard, &mut PlayerContext)>();
+    if let Some((_, mut context)) = query
+        .iter_mut(world)
+        .find(|(board, _)| board.index == player)
+    {
+        f(&mut context);
+    }
+}
+
+//swaps the player whose frame is about to run into the world
+pub fn begin_player_pass(world: &mut World) {
+    let player = world.resource::<SimulationClock>().player_pass;
+    if player == 0 {
+        return;
+    }
+
+    swap_player(world, 0);
+    swap_player(world, player);
+}
+
+//hands the garbage the player sent to the opponent, ends the match if the player's game is
+//over, and puts the first player back in the world
+pub fn end_player_pass(world: &mut World) {
+    if !world.resource::<VersusSettings>().enabled {
+        return;
+    }
+    let player = world.resource::<SimulationClock>().player_pass;
+    let opponent = (player + 1) % PLAYERS;
+
+    let outgoing = std::mem::take(&mut world.resource_mut::<GarbageQueue>().outgoing);
+    let game_state = &world.resource::<GameStateInfo>().game_state;
+    let lost = *game_state == GamePlayState::Lose;
+    let game_over = lost || *game_state == GamePlayState::Win;
+    if game_over {
+        world.resource_mut::<GameTickInfo>().do_tick = false;
+    }
+    with_stored_context(world, opponent, |context| {
+        if let Some(garbage_queue) = &mut context.garbage_queue {
+            garbage_queue.receive(outgoing);
+        }
+        if let (true, Some(game_state_info), Some(game_tick_info)) = (
+            game_over,
+            &mut context.game_state_info,
+            &mut context.game_tick_info,
+        ) {
+            if game_state_info.game_state == GamePlayState::Playing {
+                game_state_info.game_state = if lost {
+                    GamePlayState::Win
+                } else {
+                    GamePlayState::Lose
+                };
+                game_tick_info.do_tick = false;
+            }
+        }
+    });
+
+    if player == 0 {
+        return;
+    }
+    swap_player(world, player);
+    swap_player(world, 0);
+}
+
+//draws the boards of the players whose games are put away between frames. the first player's
+//board is in the world and drawn like it is outside versus
+pub fn draw_versus_boards(
+    versus_settings: Res<VersusSettings>,
+    theme: Res<Theme>,
+    context_query: Query<(&PlayerBoard, &PlayerContext)>,
+    mut sprite_query: Query<(
+        &mut BoardCellSprite,
+        &mut Sprite,
+        &mut Handle<Image>,
+        &mut Visibility,
+    )>,
+) {
+    if !versus_settings.enabled {
+        return;
+    }
+
+    for sprite in sprite_query.iter_mut() {
+        let board_data = context_query
+            .iter()
+            .find(|(board, _)| board.index == sprite.0.player)
+            .and_then(|(_, context)| context.board_data.as_ref());
+        if let Some(board_data) = board_data {
+            board_core::draw_board_cell(board_data, &theme, theme.is_changed(), sprite);
+        }
+    }
+}
+
+//redraws both players' queues and holds when either changes
+#[allow(clippy::too_many_arguments)]
+pub fn update_versus_previews(
+    versus_settings: Res<VersusSettings>,
+    theme: Res<Theme>,
+    game_settings: Res<GameSettings>,
+    piece_queue: Res<PieceQueue>,
+    mut drawn_queues: Local<Vec<PieceQueue>>,
+    mut commands: Commands,
+    context_query: Query<(&PlayerBoard, &PlayerContext)>,
+    preview_query: Query<Entity, With<PreviewBlock>>,
+) {
+    if !versus_settings.enabled {
+        return;
+    }
+
+    let mut queues: Vec<(Vec3, &PieceQueue)> = vec![(Vec3::ZERO, &piece_queue)];
+    for (board, context) in context_query.iter() {
+        if let Some(stored_queue) = &context.piece_queue {
+            queues.push((board.offset, stored_queue));
+        }
+    }
+
+    let unchanged = drawn_queues.len() == queues.len()
+        && drawn_queues.iter().zip(&queues).all(|(drawn, (_, queue))| {
+            drawn.next_pieces == queue.next_pieces && drawn.held_piece == queue.held_piece
+        });
+    if unchanged && !theme.is_changed() && !game_settings.is_changed() {
+        return;
+    }
+
+    for entity in preview_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    for (offset, queue) in &queues {
+        piece_queue::spawn_queue_previews(
+            &mut commands,
+            &theme,
+            game_settings.board_size,
+            queue,
+            *offset,
+        );
+    }
+    *drawn_queues = queues.iter().map(|(_, queue)| (*queue).clone()).collect();
+}
+
+pub fn update_versus_text(
+    versus_settings: Res<VersusSettings>,
+    game_state: Res<GameStateInfo>,
+    score: Res<Score>,
+    garbage_queue: Res<GarbageQueue>,
+    context_query: Query<(&PlayerBoard, &PlayerContext)>,
+    mut text_query: Query<(&mut Text, &VersusText)>,
+) {
+    if !versus_settings.enabled {
+        return;
+    }
+
+    for (mut text, versus_text) in text_query.iter_mut() {
+        let player_state = if versus_text.player == 0 {
+            Some((&*game_state, &*score, &*garbage_queue))
+        } else {
+            context_query
+                .iter()
+                .find(|(board, _)| board.index == versus_text.player)
+                .and_then(|(_, context)| {
+                    match (
+                        &context.game_state_info,
+                        &context.score,
+                        &context.garbage_queue,
+                    ) {
+                        (Some(game_state), Some(score), Some(garbage_queue)) => {
+                            Some((game_state, score, garbage_queue))
+                        }
+                        _ => None,
+                    }
+                })
+        };
+
+        let value = match player_state {
+            Some((game_state, _, _)) if game_state.game_state == GamePlayState::Menu => {
+                String::new()
+            }
+            Some((game_state, score, garbage_queue)) => {
+                let result = match game_state.game_state {
+                    GamePlayState::Win => "\nWINS",
+                    GamePlayState::Lose => "\nLOSES",
+                    _ => "",
+                };
+                format!(
+                    "{}  LINES {}  SENT {}  INCOMING {}{}",
+                    versus_text.name,
+                    score.lines_cleared,
+                    garbage_queue.lines_sent,
+                    garbage_queue.pending_lines(),
+                    result
+                )
+            }
+            None => String::new(),
+        };
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+    }
+}